@@ -1,4 +1,5 @@
 #![feature(test)]
+#![allow(clippy::explicit_counter_loop)]
 
 extern crate immutable_map;
 extern crate rand;
@@ -23,6 +24,21 @@ fn insert(b: &mut Bencher) {
     })
 }
 
+#[bench]
+fn insert_mut(b: &mut Bencher) {
+    let mut rng = IsaacRng::new_unseeded();
+    let mut map = TreeMap::new();
+    let mut v: usize = 0;
+
+    b.iter(|| {
+        let k = rng.gen::<u16>() as usize;
+
+        map.insert_mut(k, v);
+
+        v += 1;
+    })
+}
+
 #[bench]
 fn get(b: &mut Bencher) {
     let mut rng = IsaacRng::new_unseeded();
@@ -0,0 +1,778 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::Index;
+
+use Bound;
+use compare::{Compare, Natural};
+use measure::{Measure, CachedHash};
+use pointer::{PointerKind, RcKind, ArcKind};
+
+use tree;
+use tree::NodeRef;
+
+/// An immutable key-value map whose nodes cache the summaries of their subtrees.
+///
+/// The summaries are defined by the measure `A`, and are kept up to date through every insertion,
+/// removal and rebalancing. The summary of the whole map or of any range of the keys is computed
+/// in O(log n) time with `summary` and `aggregate`.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::AugmentedMap;
+/// use immutable_map::Bound::*;
+/// use immutable_map::measure::{Measure, Summarize};
+///
+/// // the largest value of the subtree
+/// struct Max;
+///
+/// impl<K> Summarize<K, i32> for Max {
+///     type Summary = Option<i32>;
+/// }
+///
+/// impl<K> Measure<K, i32> for Max {
+///     fn empty() -> Option<i32> {
+///         None
+///     }
+///
+///     fn measure(_: &K, value: &i32) -> Option<i32> {
+///         Some(*value)
+///     }
+///
+///     fn combine(a: &Option<i32>, b: &Option<i32>) -> Option<i32> {
+///         a.max(b).clone()
+///     }
+/// }
+///
+/// let map_0: AugmentedMap<_, _, Max> = AugmentedMap::new();
+/// let map_1 = map_0.insert("a", 3).insert("b", 7).insert("c", 5);
+///
+/// assert_eq!(Some(7), map_1.summary());
+/// assert_eq!(Some(5), map_1.aggregate(Excluded("b"), Unbounded));
+///
+/// // the original map is not changed, and keeps its own summaries
+/// let map_2 = map_1.insert("b", 1);
+/// assert_eq!(Some(5), map_2.summary());
+/// assert_eq!(Some(7), map_1.summary());
+/// ```
+pub struct AugmentedMap<K, V, A: Measure<K, V>, P: PointerKind = RcKind, C = Natural> {
+    root: Option<NodeRef<K, V, P, A>>,
+    cmp: C
+}
+
+/// An `AugmentedMap` whose nodes and cached summaries are shared with `Arc`.
+pub type ArcAugmentedMap<K, V, A> = AugmentedMap<K, V, A, ArcKind>;
+
+pub type AugmentedMapIter<'r, K, V, A, P = RcKind> = tree::Iter<'r, K, V, P, A>;
+pub type AugmentedMapRevIter<'r, K, V, A, P = RcKind> = tree::RevIter<'r, K, V, P, A>;
+pub type AugmentedMapRange<'r, K, V, A, P = RcKind, C = Natural> =
+    tree::Range<'r, K, V, P, C, A>;
+pub type AugmentedMapKeys<'r, K, V, A, P = RcKind> = tree::Keys<tree::Iter<'r, K, V, P, A>>;
+pub type AugmentedMapValues<'r, K, V, A, P = RcKind> = tree::Values<tree::Iter<'r, K, V, P, A>>;
+pub type AugmentedMapIntoIter<K, V, A, P = RcKind> = tree::IntoIter<K, V, P, A>;
+
+impl<K, V, A: Measure<K, V>> AugmentedMap<K, V, A> {
+    /// Makes a new empty AugmentedMap
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> = AugmentedMap::new();
+    /// let new_map = map.insert("One", 1);
+    /// ```
+    pub fn new() -> AugmentedMap<K, V, A> {
+        AugmentedMap { root: None, cmp: Natural }
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C> AugmentedMap<K, V, A, P, C> {
+    /// Makes a new empty AugmentedMap which orders the keys with the given comparator.
+    ///
+    /// The order of the keys is the order in which the measures of the elements are combined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    /// use immutable_map::compare::{Natural, Reverse};
+    ///
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let map: AugmentedMap<_, _, (), RcKind, _> = AugmentedMap::with_comparator(Reverse(Natural))
+    ///     .insert(1, "One")
+    ///     .insert(2, "Two");
+    ///
+    /// assert_eq!(vec![&2, &1], map.keys().collect::<Vec<_>>());
+    /// ```
+    pub fn with_comparator(cmp: C) -> AugmentedMap<K, V, A, P, C> {
+        AugmentedMap { root: None, cmp }
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C> AugmentedMap<K, V, A, P, C> {
+    /// Returns the number of elements in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> = AugmentedMap::new().insert(1, "One").insert(2, "Two");
+    /// assert_eq!(2, map.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        tree::size(&self.root)
+    }
+
+    /// Returns true if the map contains no elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let empty_map: AugmentedMap<_, _, ()> = AugmentedMap::new();
+    /// let new_map = empty_map.insert(1, "One");
+    ///
+    /// assert_eq!(true, empty_map.is_empty());
+    /// assert_eq!(false, new_map.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Returns the summary of all the elements of the map, in O(1) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    /// use immutable_map::measure::{Measure, Summarize};
+    ///
+    /// struct Count;
+    ///
+    /// impl<K, V> Summarize<K, V> for Count {
+    ///     type Summary = usize;
+    /// }
+    ///
+    /// impl<K, V> Measure<K, V> for Count {
+    ///     fn empty() -> usize { 0 }
+    ///     fn measure(_: &K, _: &V) -> usize { 1 }
+    ///     fn combine(a: &usize, b: &usize) -> usize { a + b }
+    /// }
+    ///
+    /// let map: AugmentedMap<_, _, Count> = AugmentedMap::new().insert(1, "One").insert(2, "Two");
+    /// assert_eq!(2, map.summary());
+    /// ```
+    pub fn summary(&self) -> A::Summary {
+        tree::summary(&self.root)
+    }
+
+    /// Gets an iterator over the entries of the map, sorted by key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> =
+    ///     AugmentedMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// let (first_key, first_value) = map.iter().next().unwrap();
+    /// assert_eq!((1, "One"), (*first_key, *first_value));
+    /// ```
+    pub fn iter<'r>(&'r self) -> AugmentedMapIter<'r, K, V, A, P> {
+        tree::Iter::new(&self.root)
+    }
+
+    /// Gets an iterator over the entries of the map, sorted by key in decreasing order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> =
+    ///     AugmentedMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// let (first_key, first_value) = map.rev_iter().next().unwrap();
+    /// assert_eq!((3, "Three"), (*first_key, *first_value));
+    /// ```
+    pub fn rev_iter<'r>(&'r self) -> AugmentedMapRevIter<'r, K, V, A, P> {
+        tree::Iter::new(&self.root).rev()
+    }
+
+    /// Gets an iterator over the keys of the map, in increasing order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> =
+    ///     AugmentedMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// assert_eq!(vec![&1, &2, &3], map.keys().collect::<Vec<_>>());
+    /// ```
+    pub fn keys<'r>(&'r self) -> AugmentedMapKeys<'r, K, V, A, P> {
+        tree::Keys::new(tree::Iter::new(&self.root))
+    }
+
+    /// Gets an iterator over the values of the map, ordered by key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> =
+    ///     AugmentedMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// assert_eq!(vec![&"One", &"Two", &"Three"], map.values().collect::<Vec<_>>());
+    /// ```
+    pub fn values<'r>(&'r self) -> AugmentedMapValues<'r, K, V, A, P> {
+        tree::Values::new(tree::Iter::new(&self.root))
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C> AugmentedMap<K, V, A, P, C> where C: Clone {
+    // makes the map with the given root, which orders the keys with the comparator of this map
+    fn with_root(&self, root: Option<NodeRef<K, V, P, A>>) -> AugmentedMap<K, V, A, P, C> {
+        AugmentedMap { root, cmp: self.cmp.clone() }
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C> AugmentedMap<K, V, A, P, C>
+    where C: Compare<K>
+{
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> = AugmentedMap::new().insert(1, "One");
+    ///
+    /// assert_eq!(map.get(&1), Some(&"One"));
+    /// assert_eq!(map.get(&2), None);
+    /// ```
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_exact(&self.root, |k| self.cmp.compare(key, k.borrow())).map(|p| &p.1)
+    }
+
+    /// Returns true if the map contains the given key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> = AugmentedMap::new().insert(1, "One");
+    ///
+    /// assert_eq!(true, map.contains_key(&1));
+    /// assert_eq!(false, map.contains_key(&2));
+    /// ```
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        self.get(key).is_some()
+    }
+
+    /// Constructs a double-ended iterator over a sub-range of elements in the map, starting at
+    /// min, and ending at max. If min is Unbounded, then it will be treated as "negative
+    /// infinity", and if max is Unbounded, then it will be treated as "positive infinity".
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    /// use immutable_map::Bound::*;
+    ///
+    /// let map: AugmentedMap<_, _, ()> =
+    ///     AugmentedMap::new().insert(8, "Eight").insert(3, "Three").insert(5, "Five");
+    ///
+    /// let pairs: Vec<_> = map.range(Included(&4), Included(&8)).map(|(k, v)| (*k, *v)).collect();
+    ///
+    /// assert_eq!(pairs, [(5, "Five"), (8, "Eight")]);
+    /// ```
+    pub fn range<'r, Q>(&'r self, min: Bound<&Q>, max: Bound<&Q>)
+            -> AugmentedMapRange<'r, K, V, A, P, C>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::Range::new(&self.root, min, max, &self.cmp)
+    }
+
+    /// Returns the summary of the elements in the range from min to max, in O(log n) time. The
+    /// bounds are interpreted in the same way as in `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    /// use immutable_map::Bound::*;
+    /// use immutable_map::measure::{Measure, Summarize};
+    ///
+    /// struct Sum;
+    ///
+    /// impl<K> Summarize<K, u32> for Sum {
+    ///     type Summary = u32;
+    /// }
+    ///
+    /// impl<K> Measure<K, u32> for Sum {
+    ///     fn empty() -> u32 { 0 }
+    ///     fn measure(_: &K, value: &u32) -> u32 { *value }
+    ///     fn combine(a: &u32, b: &u32) -> u32 { a + b }
+    /// }
+    ///
+    /// // requests per second, keyed by timestamp
+    /// let map: AugmentedMap<u64, u32, Sum> =
+    ///     vec![(100, 5), (101, 3), (102, 8), (105, 1)].into_iter().collect();
+    ///
+    /// assert_eq!(11, map.aggregate(Included(&101), Excluded(&105)));
+    /// assert_eq!(17, map.aggregate(Unbounded, Unbounded));
+    /// assert_eq!(0, map.aggregate(Included(&103), Included(&104)));
+    /// ```
+    pub fn aggregate<Q: ?Sized>(&self, min: Bound<&Q>, max: Bound<&Q>) -> A::Summary
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::aggregate(&self.root, min, max, &self.cmp)
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C> AugmentedMap<K, V, A, P, C>
+    where K: Clone, V: Clone, C: Compare<K> + Clone
+{
+    /// Return a new copy of `AugmentedMap` with the key-value pair inserted
+    ///
+    /// If the map already has the key, the key-value pair is replaced in the new map
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> = AugmentedMap::new();
+    /// let new_map = map.insert(1, "One");
+    ///
+    /// assert_eq!(None, map.get(&1));
+    /// assert_eq!(Some(&"One"), new_map.get(&1));
+    /// ```
+    pub fn insert(&self, key: K, value: V) -> AugmentedMap<K, V, A, P, C> {
+        let root = tree::insert(&self.root, (key, value), &self.cmp);
+        self.with_root(Some(NodeRef::new(root)))
+    }
+
+    /// Inserts the key-value pair into the map in place, and returns the replaced value if the
+    /// map already had the key.
+    ///
+    /// The nodes which are not shared with other maps are modified in place, and only the shared
+    /// nodes are copied. Other maps sharing the nodes with this map are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let mut map: AugmentedMap<_, _, ()> = AugmentedMap::new();
+    /// assert_eq!(None, map.insert_mut(1, "One"));
+    /// assert_eq!(Some("One"), map.insert_mut(1, "Eins"));
+    /// assert_eq!(Some(&"Eins"), map.get(&1));
+    /// ```
+    pub fn insert_mut(&mut self, key: K, value: V) -> Option<V> {
+        tree::insert_mut(&mut self.root, (key, value), &self.cmp).map(|p| p.1)
+    }
+
+    /// Find the map with given key, and if the key is found, udpate the value with the provided
+    /// function `f`, and return the new map. Returns `None` if the map does not have the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> = AugmentedMap::new().insert("Two", 2);
+    ///
+    /// assert!(map.update("One", |v| v + 1).is_none());
+    /// assert_eq!(Some(&12), map.update("Two", |v| v + 10).unwrap().get("Two"));
+    /// ```
+    pub fn update<Q: ?Sized, F>(&self, key: &Q, f: F) -> Option<AugmentedMap<K, V, A, P, C>>
+        where K: Borrow<Q>, C: Compare<Q>, F: FnMut(&V) -> V
+    {
+        match self.root {
+            Some(ref root) =>
+                tree::update(root, key, f, &self.cmp).map(|new_root|
+                    self.with_root(Some(NodeRef::new(new_root)))
+                ),
+            None =>
+                None
+        }
+    }
+
+    /// Remove the key from the map
+    ///
+    /// Returns `None` if the map did not contain the key
+    ///
+    /// If the map contained the key, returns the pair of a new map without the key, and the value
+    /// corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let map: AugmentedMap<_, _, ()> = AugmentedMap::new().insert(2, "Two").insert(1, "One");
+    ///
+    /// let (new_map, value) = map.remove(&2).unwrap();
+    ///
+    /// assert_eq!(None, new_map.get(&2));
+    /// assert_eq!(&"Two", value);
+    /// assert!(new_map.remove(&2).is_none());
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn remove<Q: ?Sized>(&self, key: &Q) -> Option<(AugmentedMap<K, V, A, P, C>, &V)>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::remove(&self.root, key, &self.cmp).map(|(new_root, v)|
+            (self.with_root(new_root), &v.1)
+        )
+    }
+
+    /// Removes the key from the map in place, and returns the removed value.
+    ///
+    /// Returns `None` if the map did not contain the key.
+    ///
+    /// The nodes which are not shared with other maps are modified in place, and only the shared
+    /// nodes are copied. Other maps sharing the nodes with this map are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::AugmentedMap;
+    ///
+    /// let mut map: AugmentedMap<_, _, ()> = AugmentedMap::new().insert(2, "Two");
+    ///
+    /// assert_eq!(Some("Two"), map.remove_mut(&2));
+    /// assert_eq!(None, map.remove_mut(&2));
+    /// ```
+    pub fn remove_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        if !self.contains_key(key) {
+            return None;
+        }
+        tree::remove_mut(&mut self.root, key, &self.cmp).map(|p| p.1)
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C: Clone> Clone for AugmentedMap<K, V, A, P, C> {
+    fn clone(&self) -> AugmentedMap<K, V, A, P, C> {
+        AugmentedMap { root: self.root.clone(), cmp: self.cmp.clone() }
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C: Default> Default for AugmentedMap<K, V, A, P, C> {
+    fn default() -> AugmentedMap<K, V, A, P, C> {
+        AugmentedMap { root: None, cmp: C::default() }
+    }
+}
+
+impl<K: Debug, V: Debug, A: Measure<K, V>, P: PointerKind, C> Debug
+    for AugmentedMap<K, V, A, P, C>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'r, K, V, A: Measure<K, V>, P: PointerKind, C> IntoIterator
+    for &'r AugmentedMap<K, V, A, P, C>
+{
+    type Item = (&'r K, &'r V);
+    type IntoIter = AugmentedMapIter<'r, K, V, A, P>;
+
+    fn into_iter(self) -> AugmentedMapIter<'r, K, V, A, P> {
+        self.iter()
+    }
+}
+
+impl<K: Clone, V: Clone, A: Measure<K, V>, P: PointerKind, C> IntoIterator
+    for AugmentedMap<K, V, A, P, C>
+{
+    type Item = (K, V);
+    type IntoIter = AugmentedMapIntoIter<K, V, A, P>;
+
+    fn into_iter(self) -> AugmentedMapIntoIter<K, V, A, P> {
+        tree::IntoIter::new(self.root)
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, A: Measure<K, V>, P: PointerKind, C> PartialEq
+    for AugmentedMap<K, V, A, P, C>
+{
+    fn eq(&self, other: &AugmentedMap<K, V, A, P, C>) -> bool {
+        self.len() == other.len()
+            && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<K: Eq, V: Eq, A: Measure<K, V>, P: PointerKind, C> Eq for AugmentedMap<K, V, A, P, C> {}
+
+impl<K: Hash, V: Hash, P: PointerKind, C> Hash for AugmentedMap<K, V, CachedHash, P, C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        self.summary().0.hash(state);
+    }
+}
+
+impl <K, Q: ?Sized, V, A: Measure<K, V>, P: PointerKind, C> Index<&Q>
+    for AugmentedMap<K, V, A, P, C>
+    where K: Borrow<Q>, C: Compare<K> + Compare<Q>
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl <K, V, A: Measure<K, V>, P: PointerKind, C> FromIterator<(K, V)>
+    for AugmentedMap<K, V, A, P, C>
+    where K: Clone, V: Clone, C: Compare<K> + Clone + Default
+{
+    fn from_iter<T>(iter: T) -> AugmentedMap<K, V, A, P, C>
+        where T: IntoIterator<Item=(K, V)>
+    {
+        let cmp = C::default();
+        let mut elems: Vec<(K, V)> = iter.into_iter().collect();
+        if tree::is_sorted(&elems, &cmp) {
+            tree::dedup_sorted(&mut elems, &cmp);
+            return AugmentedMap { root: tree::from_sorted_vec(elems), cmp };
+        }
+
+        let mut m = AugmentedMap { root: None, cmp };
+        for (k, v) in elems {
+            m.insert_mut(k, v);
+        }
+        m
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use tree::balanced;
+    use measure::{Measure, Summarize};
+
+    use super::{AugmentedMap, ArcAugmentedMap};
+    use Bound;
+
+    struct Sum;
+
+    impl<K> Summarize<K, isize> for Sum {
+        type Summary = isize;
+    }
+
+    impl<K> Measure<K, isize> for Sum {
+        fn empty() -> isize {
+            0
+        }
+
+        fn measure(_: &K, value: &isize) -> isize {
+            *value
+        }
+
+        fn combine(a: &isize, b: &isize) -> isize {
+            a + b
+        }
+    }
+
+    #[test]
+    fn test_summary_after_rebalancing() {
+        let mut m: AugmentedMap<isize, isize, Sum> = AugmentedMap::new();
+        for i in 0..100 {
+            m = m.insert(i, i);
+        }
+        assert!(balanced(&m.root));
+        assert_eq!(4950, m.summary());
+
+        for i in 0..50 {
+            m = m.remove(&(i * 2)).unwrap().0;
+        }
+        assert!(balanced(&m.root));
+        assert_eq!(2500, m.summary());
+        assert_eq!(5 + 7 + 9, m.aggregate(Bound::Included(&4), Bound::Excluded(&11)));
+    }
+
+    #[test]
+    fn test_summary_in_place() {
+        let mut m: AugmentedMap<isize, isize, Sum> = AugmentedMap::new();
+        for i in 0..100 {
+            m.insert_mut(i, i);
+        }
+        let old = m.clone();
+        m.insert_mut(10, 1000);
+        for i in 50..100 {
+            m.remove_mut(&i);
+        }
+
+        assert!(balanced(&m.root));
+        assert_eq!(1225 - 10 + 1000, m.summary());
+        assert_eq!(4950, old.summary());
+    }
+
+    #[test]
+    fn test_send() {
+        let m: ArcAugmentedMap<isize, isize, Sum> = (0..10).map(|i| (i, i)).collect();
+        let handle = thread::spawn(move || m.insert(10, 10).summary());
+        assert_eq!(55, handle.join().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod quickcheck {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use augmented::AugmentedMap;
+    use measure::{Measure, Summarize, CachedHash};
+    use tree::balanced;
+    use Bound;
+
+    // the keys of the subtree in the key order, which checks the order of the combination
+    struct KeyList;
+
+    impl<V> Summarize<isize, V> for KeyList {
+        type Summary = Vec<isize>;
+    }
+
+    impl<V> Measure<isize, V> for KeyList {
+        fn empty() -> Vec<isize> {
+            Vec::new()
+        }
+
+        fn measure(key: &isize, _: &V) -> Vec<isize> {
+            vec![*key]
+        }
+
+        fn combine(a: &Vec<isize>, b: &Vec<isize>) -> Vec<isize> {
+            a.iter().chain(b.iter()).cloned().collect()
+        }
+    }
+
+    struct Sum;
+
+    impl<K> Summarize<K, isize> for Sum {
+        type Summary = isize;
+    }
+
+    impl<K> Measure<K, isize> for Sum {
+        fn empty() -> isize {
+            0
+        }
+
+        fn measure(_: &K, value: &isize) -> isize {
+            *value
+        }
+
+        fn combine(a: &isize, b: &isize) -> isize {
+            a.wrapping_add(*b)
+        }
+    }
+
+    quickcheck! {
+        fn check_aggregate(xs: Vec<(isize, isize)>, min: Bound<isize>, max: Bound<isize>)
+                -> bool
+        {
+            let m: AugmentedMap<isize, isize, KeyList> = xs.iter().cloned().collect();
+            let min = match min {
+                Bound::Included(ref k) => Bound::Included(k),
+                Bound::Excluded(ref k) => Bound::Excluded(k),
+                Bound::Unbounded => Bound::Unbounded
+            };
+            let max = match max {
+                Bound::Included(ref k) => Bound::Included(k),
+                Bound::Excluded(ref k) => Bound::Excluded(k),
+                Bound::Unbounded => Bound::Unbounded
+            };
+            let in_range = |k: &isize| match min {
+                Bound::Included(lower) => lower <= k,
+                Bound::Excluded(lower) => lower < k,
+                Bound::Unbounded => true
+            } && match max {
+                Bound::Included(upper) => k <= upper,
+                Bound::Excluded(upper) => k < upper,
+                Bound::Unbounded => true
+            };
+
+            let expected: Vec<isize> = m.keys().cloned().filter(|k| in_range(k)).collect();
+
+            m.aggregate(min, max) == expected
+                && m.summary() == m.keys().cloned().collect::<Vec<_>>()
+        }
+    }
+
+    quickcheck! {
+        fn check_summary(xs: Vec<(isize, isize)>, ys: Vec<isize>) -> bool {
+            let mut m: AugmentedMap<isize, isize, Sum> = AugmentedMap::new();
+            let mut n: AugmentedMap<isize, isize, Sum> = AugmentedMap::new();
+            for &(k, v) in &xs {
+                m = m.insert(k, v);
+                n.insert_mut(k, v);
+            }
+            for k in &ys {
+                m = m.remove(k).map_or(m.clone(), |p| p.0);
+                n.remove_mut(k);
+            }
+
+            let expected = m.values().fold(0isize, |a, &v| a.wrapping_add(v));
+
+            balanced(&m.root) && balanced(&n.root)
+                && m == n
+                && m.summary() == expected
+                && n.summary() == expected
+        }
+    }
+
+    fn hash<T: Hash>(t: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        t.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    quickcheck! {
+        fn check_cached_hash(xs: Vec<(isize, isize)>, ys: Vec<isize>) -> bool {
+            let mut m: AugmentedMap<isize, isize, CachedHash> = AugmentedMap::new();
+            for &(k, v) in &xs {
+                m.insert_mut(k, v);
+            }
+            for k in &ys {
+                m.remove_mut(k);
+            }
+
+            // rebuilt from the sorted entries, which gives a different shape
+            let n: AugmentedMap<isize, isize, CachedHash> =
+                m.iter().map(|(&k, &v)| (k, v)).collect();
+            let changed = m.insert(isize::MAX, 0).insert(isize::MIN, 0);
+
+            balanced(&m.root) && balanced(&n.root)
+                && m.summary() == n.summary()
+                && hash(&m) == hash(&n)
+                && (changed == m || hash(&m) != hash(&changed))
+        }
+    }
+}
@@ -0,0 +1,785 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+
+use tree;
+use tree::NodeRef;
+use Bound;
+use compare::{Compare, Natural};
+use measure::{Measure, Summarize};
+use pointer::{PointerKind, RcKind, ArcKind};
+
+// sums the multiplicities of the elements of each subtree
+#[derive(Clone, Copy)]
+struct Multiplicity;
+
+impl<V> Summarize<V, usize> for Multiplicity {
+    type Summary = usize;
+}
+
+impl<V> Measure<V, usize> for Multiplicity {
+    fn empty() -> usize {
+        0
+    }
+
+    fn measure(_: &V, count: &usize) -> usize {
+        *count
+    }
+
+    fn combine(a: &usize, b: &usize) -> usize {
+        a + b
+    }
+}
+
+/// An immutable multiset based on weight-balanced binary tree.
+///
+/// Each distinct element is stored once with its multiplicity, and every node caches the total
+/// multiplicity of its subtree. The positions of the elements count the duplicates, so
+/// `get_index` and `index_of` take O(log n) time, where n is the number of the distinct
+/// elements.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::TreeBag;
+///
+/// let bag_0 = TreeBag::new();
+///
+/// // `insert` returns new copies with one more copy of the element, and does not change the
+/// // original bag
+/// let bag_1 = bag_0.insert(3);
+/// let bag_2 = bag_1.insert(3).insert(4);
+///
+/// assert_eq!(1, bag_1.count(&3));
+/// assert_eq!(2, bag_2.count(&3));
+/// assert_eq!(3, bag_2.total_len());
+/// ```
+pub struct TreeBag<V, P: PointerKind = RcKind, C = Natural> {
+    root: Option<NodeRef<V, usize, P, Multiplicity>>,
+    cmp: C
+}
+
+/// A `TreeBag` whose nodes are shared with `Arc`, so that it can be sent to other threads.
+pub type ArcTreeBag<V> = TreeBag<V, ArcKind>;
+
+/// An iterator over the elements of a `TreeBag` in sorted order, which yields each element as
+/// many times as its multiplicity.
+#[derive(Clone)]
+pub struct TreeBagIter<'r, V: 'r, P: PointerKind = RcKind> {
+    iter: tree::Iter<'r, V, usize, P, Multiplicity>,
+    // the elements partially taken from either end, with their remaining multiplicities
+    front: Option<(&'r V, usize)>,
+    back: Option<(&'r V, usize)>,
+    len: usize
+}
+
+impl<'r, V: 'r, P: PointerKind> Iterator for TreeBagIter<'r, V, P> {
+    type Item = &'r V;
+
+    fn next(&mut self) -> Option<&'r V> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+
+        loop {
+            if let Some(ref mut front) = self.front {
+                if front.1 > 0 {
+                    front.1 -= 1;
+                    return Some(front.0);
+                }
+            }
+            // the elements which are not taken from the front are partially taken from the back
+            self.front = match self.iter.next() {
+                Some((v, &count)) => Some((v, count)),
+                None => self.back.take()
+            };
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'r, V: 'r, P: PointerKind> DoubleEndedIterator for TreeBagIter<'r, V, P> {
+    fn next_back(&mut self) -> Option<&'r V> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+
+        loop {
+            if let Some(ref mut back) = self.back {
+                if back.1 > 0 {
+                    back.1 -= 1;
+                    return Some(back.0);
+                }
+            }
+            self.back = match self.iter.next_back() {
+                Some((v, &count)) => Some((v, count)),
+                None => self.front.take()
+            };
+        }
+    }
+}
+
+impl<'r, V: 'r, P: PointerKind> ExactSizeIterator for TreeBagIter<'r, V, P> {
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// An iterator over the distinct elements of a `TreeBag` and their multiplicities, in sorted
+/// order.
+#[derive(Clone)]
+pub struct TreeBagCounts<'r, V: 'r, P: PointerKind = RcKind> {
+    iter: tree::Iter<'r, V, usize, P, Multiplicity>
+}
+
+impl<'r, V: 'r, P: PointerKind> Iterator for TreeBagCounts<'r, V, P> {
+    type Item = (&'r V, usize);
+
+    fn next(&mut self) -> Option<(&'r V, usize)> {
+        self.iter.next().map(|(v, &count)| (v, count))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'r, V: 'r, P: PointerKind> DoubleEndedIterator for TreeBagCounts<'r, V, P> {
+    fn next_back(&mut self) -> Option<(&'r V, usize)> {
+        self.iter.next_back().map(|(v, &count)| (v, count))
+    }
+}
+
+impl<'r, V: 'r, P: PointerKind> ExactSizeIterator for TreeBagCounts<'r, V, P> {
+    fn len(&self) -> usize {
+        self.iter.len()
+    }
+}
+
+impl<V> TreeBag<V> {
+    /// Makes a new empty TreeBag
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new();
+    /// let new_bag = bag.insert(1);
+    /// ```
+    pub fn new() -> TreeBag<V> {
+        TreeBag { root: None, cmp: Natural }
+    }
+}
+
+impl<V, P: PointerKind, C> TreeBag<V, P, C> {
+    /// Makes a new empty TreeBag which orders the elements with the given comparator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let rev = |a: &i32, b: &i32| b.cmp(a);
+    /// let bag: TreeBag<_, RcKind, _> = TreeBag::with_comparator(rev).insert(1).insert(2);
+    ///
+    /// assert_eq!(vec![&2, &1], bag.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn with_comparator(cmp: C) -> TreeBag<V, P, C> {
+        TreeBag { root: None, cmp }
+    }
+}
+
+impl<V, P: PointerKind, C> TreeBag<V, P, C> {
+    /// Returns the number of the distinct elements in the bag.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(1).insert(1).insert(2);
+    /// assert_eq!(2, bag.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        tree::size(&self.root)
+    }
+
+    /// Returns the number of the elements in the bag, counting the duplicates. This operation
+    /// takes O(1) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(1).insert(1).insert(2);
+    /// assert_eq!(3, bag.total_len());
+    /// ```
+    pub fn total_len(&self) -> usize {
+        tree::summary(&self.root)
+    }
+
+    /// Returns true if the bag contains no elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let empty_bag = TreeBag::new();
+    /// let new_bag = empty_bag.insert(1);
+    ///
+    /// assert!(empty_bag.is_empty());
+    /// assert!(!new_bag.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Gets an iterator over the elements of the bag in sorted order, which yields each element
+    /// as many times as its multiplicity.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(2).insert(1).insert(2);
+    ///
+    /// assert_eq!(vec![&1, &2, &2], bag.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn iter<'r>(&'r self) -> TreeBagIter<'r, V, P> {
+        TreeBagIter {
+            iter: tree::Iter::new(&self.root),
+            front: None,
+            back: None,
+            len: self.total_len()
+        }
+    }
+
+    /// Gets an iterator over the distinct elements of the bag and their multiplicities, in
+    /// sorted order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(2).insert(1).insert(2);
+    ///
+    /// assert_eq!(vec![(&1, 1), (&2, 2)], bag.counts().collect::<Vec<_>>());
+    /// ```
+    pub fn counts<'r>(&'r self) -> TreeBagCounts<'r, V, P> {
+        TreeBagCounts { iter: tree::Iter::new(&self.root) }
+    }
+
+    /// Returns the element at the given position in sorted order, counting the duplicates.
+    /// This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert_many(1, 3).insert(2);
+    ///
+    /// assert_eq!(Some(&1), bag.get_index(2));
+    /// assert_eq!(Some(&2), bag.get_index(3));
+    /// assert_eq!(None, bag.get_index(4));
+    /// ```
+    pub fn get_index(&self, index: usize) -> Option<&V> {
+        tree::find_weighted_index(&self.root, index).map(|elem| &elem.0)
+    }
+}
+
+impl<V, P: PointerKind, C> TreeBag<V, P, C> where C: Compare<V> {
+    /// Returns the multiplicity of the element, which is zero if the bag does not contain the
+    /// element.
+    ///
+    /// The element may be any borrowed form of the bag's element type, but the ordering on the
+    /// borrowed form must match the ordering on the element type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(1).insert(1);
+    ///
+    /// assert_eq!(2, bag.count(&1));
+    /// assert_eq!(0, bag.count(&2));
+    /// ```
+    pub fn count<Q: ?Sized>(&self, value: &Q) -> usize
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_exact(&self.root, |x| self.cmp.compare(value, x.borrow()))
+            .map_or(0, |elem| elem.1)
+    }
+
+    /// Returns true if the bag contains at least one copy of the element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(1);
+    ///
+    /// assert!(bag.contains(&1));
+    /// assert!(!bag.contains(&2));
+    /// ```
+    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        self.count(value) > 0
+    }
+
+    /// Returns the position of the first copy of the element in sorted order, counting the
+    /// duplicates of the smaller elements, or `None` if the bag does not contain the element.
+    /// This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert_many(1, 3).insert(2);
+    ///
+    /// assert_eq!(Some(0), bag.index_of(&1));
+    /// assert_eq!(Some(3), bag.index_of(&2));
+    /// assert_eq!(None, bag.index_of(&3));
+    /// ```
+    pub fn index_of<Q: ?Sized>(&self, value: &Q) -> Option<usize>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        if self.contains(value) {
+            Some(tree::aggregate(&self.root, Bound::Unbounded, Bound::Excluded(value), &self.cmp))
+        } else {
+            None
+        }
+    }
+}
+
+impl<V, P: PointerKind, C> TreeBag<V, P, C> where V: Clone, C: Compare<V> + Clone {
+    // makes the bag with the given root, which orders the elements with the comparator of this
+    // bag
+    fn with_root(&self, root: Option<NodeRef<V, usize, P, Multiplicity>>) -> TreeBag<V, P, C> {
+        TreeBag { root, cmp: self.cmp.clone() }
+    }
+
+    /// Returns a new bag with one more copy of the element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(1).insert(1);
+    ///
+    /// assert_eq!(2, bag.count(&1));
+    /// ```
+    pub fn insert(&self, value: V) -> TreeBag<V, P, C> {
+        self.insert_many(value, 1)
+    }
+
+    /// Returns a new bag with `count` more copies of the element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(1).insert_many(1, 2);
+    ///
+    /// assert_eq!(3, bag.count(&1));
+    /// ```
+    pub fn insert_many(&self, value: V, count: usize) -> TreeBag<V, P, C> {
+        if count == 0 {
+            return self.clone();
+        }
+        let root = tree::insert_or_update(&self.root, value, count, |&n| n + count, &self.cmp);
+        self.with_root(Some(NodeRef::new(root)))
+    }
+
+    /// Returns a new bag with one copy of the element removed, or `None` if the bag does not
+    /// contain the element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert(1).insert(1);
+    ///
+    /// let removed = bag.remove_one(&1).unwrap();
+    /// assert_eq!(1, removed.count(&1));
+    /// assert!(removed.remove_one(&1).unwrap().is_empty());
+    /// assert!(bag.remove_one(&2).is_none());
+    /// ```
+    pub fn remove_one<Q: ?Sized>(&self, value: &Q) -> Option<TreeBag<V, P, C>>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        match self.count(value) {
+            0 => None,
+            1 => self.remove_all(value).map(|p| p.0),
+            _ => {
+                let root = self.root.as_ref().and_then(|r|
+                    tree::update(r, value, |&n| n - 1, &self.cmp)
+                );
+                root.map(|r| self.with_root(Some(NodeRef::new(r))))
+            }
+        }
+    }
+
+    /// Returns a new bag with all the copies of the element removed, and the number of the
+    /// removed copies. Returns `None` if the bag does not contain the element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let bag = TreeBag::new().insert_many(1, 3).insert(2);
+    ///
+    /// let (removed, count) = bag.remove_all(&1).unwrap();
+    /// assert_eq!(3, count);
+    /// assert_eq!(vec![&2], removed.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn remove_all<Q: ?Sized>(&self, value: &Q) -> Option<(TreeBag<V, P, C>, usize)>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::remove(&self.root, value, &self.cmp).map(|(new_root, elem)|
+            (self.with_root(new_root), elem.1)
+        )
+    }
+
+    /// Returns the union of the two bags, where the multiplicity of each element is the larger
+    /// one of the two bags.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let a = TreeBag::new().insert_many(1, 2).insert(2);
+    /// let b = TreeBag::new().insert(1).insert_many(3, 2);
+    ///
+    /// let union = a.union(&b);
+    /// assert_eq!(vec![(&1, 2), (&2, 1), (&3, 2)], union.counts().collect::<Vec<_>>());
+    /// ```
+    pub fn union(&self, other: &TreeBag<V, P, C>) -> TreeBag<V, P, C> {
+        let root = tree::merge_with(&self.root, &other.root,
+                                    &mut |_, &a, &b| Some(if a < b { b } else { a }),
+                                    true, true, &self.cmp);
+        self.with_root(root)
+    }
+
+    /// Returns the intersection of the two bags, where the multiplicity of each element is the
+    /// smaller one of the two bags.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let a = TreeBag::new().insert_many(1, 2).insert(2);
+    /// let b = TreeBag::new().insert(1).insert_many(3, 2);
+    ///
+    /// let intersection = a.intersection(&b);
+    /// assert_eq!(vec![(&1, 1)], intersection.counts().collect::<Vec<_>>());
+    /// ```
+    pub fn intersection(&self, other: &TreeBag<V, P, C>) -> TreeBag<V, P, C> {
+        let root = tree::merge_with(&self.root, &other.root,
+                                    &mut |_, &a, &b| Some(if a < b { a } else { b }),
+                                    false, false, &self.cmp);
+        self.with_root(root)
+    }
+
+    /// Returns the sum of the two bags, where the multiplicity of each element is the sum of the
+    /// multiplicities in the two bags.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let a = TreeBag::new().insert_many(1, 2).insert(2);
+    /// let b = TreeBag::new().insert(1).insert_many(3, 2);
+    ///
+    /// let sum = a.sum(&b);
+    /// assert_eq!(vec![(&1, 3), (&2, 1), (&3, 2)], sum.counts().collect::<Vec<_>>());
+    /// ```
+    pub fn sum(&self, other: &TreeBag<V, P, C>) -> TreeBag<V, P, C> {
+        let root = tree::merge_with(&self.root, &other.root,
+                                    &mut |_, &a, &b| Some(a + b),
+                                    true, true, &self.cmp);
+        self.with_root(root)
+    }
+
+    /// Returns the difference of the two bags, where the multiplicity of each element is
+    /// reduced by its multiplicity in `other`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeBag;
+    ///
+    /// let a = TreeBag::new().insert_many(1, 2).insert(2);
+    /// let b = TreeBag::new().insert(1).insert_many(2, 2);
+    ///
+    /// let difference = a.difference(&b);
+    /// assert_eq!(vec![(&1, 1)], difference.counts().collect::<Vec<_>>());
+    /// ```
+    pub fn difference(&self, other: &TreeBag<V, P, C>) -> TreeBag<V, P, C> {
+        let root = tree::merge_with(&self.root, &other.root,
+                                    &mut |_, &a, &b| if a > b { Some(a - b) } else { None },
+                                    true, false, &self.cmp);
+        self.with_root(root)
+    }
+}
+
+impl<V, P: PointerKind, C: Clone> Clone for TreeBag<V, P, C> {
+    fn clone(&self) -> TreeBag<V, P, C> {
+        TreeBag { root: self.root.clone(), cmp: self.cmp.clone() }
+    }
+}
+
+impl<V, P: PointerKind, C: Default> Default for TreeBag<V, P, C> {
+    fn default() -> TreeBag<V, P, C> {
+        TreeBag { root: None, cmp: C::default() }
+    }
+}
+
+impl<V: Debug, P: PointerKind, C> Debug for TreeBag<V, P, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'r, V, P: PointerKind, C> IntoIterator for &'r TreeBag<V, P, C> {
+    type Item = &'r V;
+    type IntoIter = TreeBagIter<'r, V, P>;
+
+    fn into_iter(self) -> TreeBagIter<'r, V, P> {
+        self.iter()
+    }
+}
+
+impl<V: PartialEq, P: PointerKind, C> PartialEq for TreeBag<V, P, C> {
+    fn eq(&self, other: &TreeBag<V, P, C>) -> bool {
+        self.len() == other.len() && self.counts().eq(other.counts())
+    }
+}
+
+impl<V: Eq, P: PointerKind, C> Eq for TreeBag<V, P, C> {}
+
+impl<V: Hash, P: PointerKind, C> Hash for TreeBag<V, P, C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for elem in self.counts() {
+            elem.hash(state);
+        }
+    }
+}
+
+/// Builds the bag in O(n) time if the values are sorted, and by inserting them one by one
+/// otherwise. The values are collected into a `Vec` first to check their order, so this takes
+/// O(n) extra memory while building.
+impl<V, P: PointerKind, C> FromIterator<V> for TreeBag<V, P, C>
+    where V: Clone, C: Compare<V> + Clone + Default
+{
+    fn from_iter<T>(iter: T) -> TreeBag<V, P, C> where T: IntoIterator<Item=V> {
+        let cmp = C::default();
+        let elems: Vec<(V, usize)> = iter.into_iter().map(|v| (v, 1)).collect();
+        if tree::is_sorted(&elems, &cmp) {
+            // counts the runs of the equal elements
+            let mut counted: Vec<(V, usize)> = Vec::new();
+            for (v, _) in elems {
+                let duplicated = match counted.last() {
+                    Some(last) => cmp.compare(&last.0, &v) == Ordering::Equal,
+                    None => false
+                };
+                if duplicated {
+                    counted.last_mut().unwrap().1 += 1;
+                } else {
+                    counted.push((v, 1));
+                }
+            }
+            return TreeBag { root: tree::from_sorted_vec(counted), cmp };
+        }
+
+        let mut b = TreeBag { root: None, cmp };
+        for (v, _) in elems {
+            b = b.insert(v);
+        }
+        b
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tree::balanced;
+
+    use super::TreeBag;
+
+    #[test]
+    fn test_iter_both_ends() {
+        let bag = TreeBag::new().insert_many(1, 2).insert_many(2, 3);
+        let mut iter = bag.iter();
+
+        assert_eq!(Some(&1), iter.next());
+        assert_eq!(Some(&2), iter.next_back());
+        assert_eq!(3, iter.len());
+        assert_eq!(Some(&1), iter.next());
+        assert_eq!(Some(&2), iter.next());
+        assert_eq!(Some(&2), iter.next_back());
+        assert_eq!(None, iter.next());
+        assert_eq!(None, iter.next_back());
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let sorted: TreeBag<_> = vec![1, 1, 2, 3, 3, 3].into_iter().collect();
+        let unsorted: TreeBag<_> = vec![3, 1, 3, 2, 1, 3].into_iter().collect();
+
+        assert!(balanced(&sorted.root));
+        assert_eq!(sorted, unsorted);
+        assert_eq!(vec![(&1, 2), (&2, 1), (&3, 3)], sorted.counts().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_zero_multiplicity() {
+        // no element is kept with the multiplicity of zero
+        let bag = TreeBag::new().insert_many(1, 0).insert(2);
+        let removed = bag.remove_one(&2).unwrap();
+
+        assert_eq!(1, bag.len());
+        assert!(!bag.contains(&1));
+        assert!(removed.is_empty());
+        assert_eq!(0, removed.total_len());
+        assert_eq!(None, removed.index_of(&2));
+    }
+}
+
+#[cfg(test)]
+mod quickcheck {
+    use std::collections::BTreeMap;
+
+    use bag::TreeBag;
+    use tree::balanced;
+
+    fn counts(xs: &[u8]) -> BTreeMap<u8, usize> {
+        let mut m = BTreeMap::new();
+        for &x in xs {
+            *m.entry(x).or_insert(0) += 1;
+        }
+        m
+    }
+
+    fn to_vec(bag: &TreeBag<u8>) -> Vec<(u8, usize)> {
+        bag.counts().map(|(&v, n)| (v, n)).collect()
+    }
+
+    quickcheck! {
+        fn check_insert_remove(xs: Vec<u8>, ys: Vec<u8>) -> bool {
+            let mut bag = TreeBag::new();
+            for &x in &xs {
+                bag = bag.insert(x);
+            }
+            let mut expected = counts(&xs);
+            for y in &ys {
+                bag = bag.remove_one(y).unwrap_or(bag);
+                if let Some(n) = expected.get_mut(y) {
+                    *n = n.saturating_sub(1);
+                }
+            }
+            expected.retain(|_, n| *n > 0);
+
+            balanced(&bag.root)
+                && to_vec(&bag) == expected.into_iter().collect::<Vec<_>>()
+                && bag.total_len() == bag.iter().count()
+                && bag.total_len() == bag.counts().map(|p| p.1).sum::<usize>()
+        }
+    }
+
+    quickcheck! {
+        fn check_remove_all(xs: Vec<u8>, y: u8) -> bool {
+            let bag: TreeBag<u8> = xs.iter().cloned().collect();
+            let count = xs.iter().filter(|&&x| x == y).count();
+
+            match bag.remove_all(&y) {
+                None => count == 0,
+                Some((removed, n)) => n == count
+                    && !removed.contains(&y)
+                    && removed.total_len() + count == bag.total_len()
+            }
+        }
+    }
+
+    quickcheck! {
+        fn check_index(xs: Vec<u8>) -> bool {
+            let bag: TreeBag<u8> = xs.iter().cloned().collect();
+            let expanded: Vec<u8> = bag.iter().cloned().collect();
+
+            (0..expanded.len()).all(|i| bag.get_index(i) == Some(&expanded[i]))
+                && bag.get_index(expanded.len()).is_none()
+                && xs.iter().all(|x|
+                    bag.index_of(x) == expanded.iter().position(|y| y == x)
+                )
+        }
+    }
+
+    quickcheck! {
+        fn check_rev_iter(xs: Vec<u8>) -> bool {
+            let bag: TreeBag<u8> = xs.iter().cloned().collect();
+            let mut sorted = xs.clone();
+            sorted.sort();
+            sorted.reverse();
+
+            bag.iter().rev().cloned().collect::<Vec<_>>() == sorted
+        }
+    }
+
+    quickcheck! {
+        fn check_multiset_ops(xs: Vec<u8>, ys: Vec<u8>) -> bool {
+            let a: TreeBag<u8> = xs.iter().cloned().collect();
+            let b: TreeBag<u8> = ys.iter().cloned().collect();
+            let (ca, cb) = (counts(&xs), counts(&ys));
+            let count = |m: &BTreeMap<u8, usize>, k: u8| m.get(&k).cloned().unwrap_or(0);
+
+            let keys: Vec<u8> = ca.keys().chain(cb.keys()).cloned().collect();
+            let expected = |f: &dyn Fn(usize, usize) -> usize| {
+                let mut m = BTreeMap::new();
+                for &k in &keys {
+                    let n = f(count(&ca, k), count(&cb, k));
+                    if n > 0 {
+                        m.insert(k, n);
+                    }
+                }
+                m.into_iter().collect::<Vec<_>>()
+            };
+
+            let union = a.union(&b);
+            let intersection = a.intersection(&b);
+            let sum = a.sum(&b);
+            let difference = a.difference(&b);
+
+            balanced(&union.root) && balanced(&intersection.root)
+                && balanced(&sum.root) && balanced(&difference.root)
+                && to_vec(&union) == expected(&|x, y| x.max(y))
+                && to_vec(&intersection) == expected(&|x, y| x.min(y))
+                && to_vec(&sum) == expected(&|x, y| x + y)
+                && to_vec(&difference) == expected(&|x, y| x.saturating_sub(y))
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::cmp::Ordering;
+
+/// A comparator which defines the order of the keys in the maps and sets.
+///
+/// The maps and sets are ordered by the natural order of the keys by default. A custom
+/// comparator can order the keys without wrapping every key in a newtype.
+///
+/// The comparator must define a total order, and must give the same results while the keys are
+/// in the map or set. The functions of type `Fn(&T, &T) -> Ordering` are comparators.
+///
+/// # Examples
+///
+/// ```
+/// use std::cmp::Ordering;
+/// use immutable_map::TreeSet;
+/// use immutable_map::compare::Compare;
+/// use immutable_map::pointer::RcKind;
+///
+/// #[derive(Clone, Copy, Debug, Default)]
+/// struct CaseInsensitive;
+///
+/// impl Compare<str> for CaseInsensitive {
+///     fn compare(&self, a: &str, b: &str) -> Ordering {
+///         a.to_lowercase().cmp(&b.to_lowercase())
+///     }
+/// }
+///
+/// impl Compare<String> for CaseInsensitive {
+///     fn compare(&self, a: &String, b: &String) -> Ordering {
+///         self.compare(a.as_str(), b.as_str())
+///     }
+/// }
+///
+/// let set: TreeSet<_, RcKind, _> = TreeSet::with_comparator(CaseInsensitive)
+///     .insert("b".to_string())
+///     .insert("A".to_string())
+///     .insert("B".to_string());
+///
+/// assert_eq!(2, set.len());
+/// assert!(set.contains("a"));
+/// assert_eq!(vec!["A", "B"], set.iter().collect::<Vec<_>>());
+/// ```
+pub trait Compare<T: ?Sized> {
+    /// Compares the two values.
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// The comparator of the natural order defined by `Ord`.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct Natural;
+
+impl<T: ?Sized + Ord> Compare<T> for Natural {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// The comparator of the reverse order of the inner comparator.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::TreeMap;
+/// use immutable_map::compare::{Natural, Reverse};
+/// use immutable_map::pointer::RcKind;
+///
+/// let map: TreeMap<_, _, RcKind, _> = TreeMap::with_comparator(Reverse(Natural))
+///     .insert(1, "One")
+///     .insert(2, "Two");
+///
+/// assert_eq!(vec![(&2, &"Two"), (&1, &"One")], map.iter().collect::<Vec<_>>());
+/// ```
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct Reverse<C>(pub C);
+
+impl<T: ?Sized, C: Compare<T>> Compare<T> for Reverse<C> {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self.0.compare(b, a)
+    }
+}
+
+impl<T: ?Sized, F> Compare<T> for F where F: Fn(&T, &T) -> Ordering {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
@@ -0,0 +1,517 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::FromIterator;
+
+use tree;
+use tree::NodeRef;
+use Bound;
+use compare::Natural;
+use measure::{Measure, Summarize};
+use pointer::{PointerKind, RcKind, ArcKind};
+
+// finds the maximum end of the intervals of each subtree
+#[derive(Clone, Copy)]
+struct MaxEnd;
+
+impl<K, V> Summarize<(K, K), V> for MaxEnd {
+    type Summary = Option<K>;
+}
+
+impl<K: Ord + Clone, V> Measure<(K, K), V> for MaxEnd {
+    fn empty() -> Option<K> {
+        None
+    }
+
+    fn measure(key: &(K, K), _: &V) -> Option<K> {
+        Some(key.1.clone())
+    }
+
+    fn combine(a: &Option<K>, b: &Option<K>) -> Option<K> {
+        if a >= b { a.clone() } else { b.clone() }
+    }
+}
+
+// matches the intervals whose starts are below `start_max` and whose ends are above `end_min`.
+// the intervals are sorted by their starts, so the search stops at the first start above
+// `start_max`, and the subtrees whose ends are all below `end_min` are skipped.
+#[derive(Clone)]
+struct IntervalQuery<K> {
+    start_max: Bound<K>,
+    end_min: Bound<K>
+}
+
+impl<K: Ord + Clone, V> tree::Query<(K, K), V, MaxEnd> for IntervalQuery<K> {
+    fn visit(&self, summary: &Option<K>) -> bool {
+        let end = match *summary {
+            Some(ref end) => end,
+            None => return false
+        };
+        match self.end_min {
+            Bound::Unbounded => true,
+            Bound::Included(ref min) => end >= min,
+            Bound::Excluded(ref min) => end > min
+        }
+    }
+
+    fn stop(&self, key: &(K, K)) -> bool {
+        match self.start_max {
+            Bound::Unbounded => false,
+            Bound::Included(ref max) => key.0 > *max,
+            Bound::Excluded(ref max) => key.0 >= *max
+        }
+    }
+}
+
+/// An immutable map from half-open intervals to values, based on weight-balanced binary tree.
+///
+/// An interval is a pair `(start, end)`, which contains the points from `start` inclusive to
+/// `end` exclusive. The intervals are sorted by their starts and then by their ends, and every
+/// node caches the maximum end of the intervals of its subtree, so the intervals overlapping a
+/// range or containing a point are found in O((k + 1) log n) time, where k is the number of the
+/// found intervals.
+///
+/// An empty interval, where `start >= end`, contains no points. As with `RangeMap` and
+/// `RangeSet`, inserting an empty interval leaves the map unchanged, and querying with an empty
+/// range finds nothing.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::IntervalMap;
+///
+/// let map = IntervalMap::new()
+///     .insert((9, 12), "standup")
+///     .insert((10, 11), "review")
+///     .insert((13, 15), "lunch");
+///
+/// let busy: Vec<_> = map.overlapping(&10, &14).map(|(_, v)| *v).collect();
+/// assert_eq!(vec!["standup", "review", "lunch"], busy);
+///
+/// let at_ten: Vec<_> = map.stabbing(&10).map(|(_, v)| *v).collect();
+/// assert_eq!(vec!["standup", "review"], at_ten);
+/// ```
+pub struct IntervalMap<K, V, P: PointerKind = RcKind> {
+    root: Option<NodeRef<(K, K), V, P, MaxEnd>>
+}
+
+/// An `IntervalMap` with its nodes in `Arc`, which can be shared between threads.
+pub type ArcIntervalMap<K, V> = IntervalMap<K, V, ArcKind>;
+
+/// An iterator over the intervals of an `IntervalMap` and their values, in sorted order.
+#[derive(Clone)]
+pub struct IntervalMapIter<'r, K: 'r, V: 'r, P: PointerKind = RcKind> {
+    iter: tree::Iter<'r, (K, K), V, P, MaxEnd>
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind> Iterator for IntervalMapIter<'r, K, V, P> {
+    type Item = (&'r (K, K), &'r V);
+
+    fn next(&mut self) -> Option<(&'r (K, K), &'r V)> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind> DoubleEndedIterator for IntervalMapIter<'r, K, V, P>
+{
+    fn next_back(&mut self) -> Option<(&'r (K, K), &'r V)> {
+        self.iter.next_back()
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind> ExactSizeIterator for IntervalMapIter<'r, K, V, P>
+{
+    fn len(&self) -> usize {
+        self.iter.len()
+    }
+}
+
+/// An iterator over the intervals of an `IntervalMap` matching a query, in sorted order.
+#[derive(Clone)]
+pub struct IntervalMapSearch<'r, K: 'r, V: 'r, P: PointerKind = RcKind> {
+    search: tree::Search<'r, (K, K), V, P, MaxEnd, IntervalQuery<K>>
+}
+
+impl<'r, K: 'r + Ord + Clone, V: 'r, P: PointerKind> Iterator for IntervalMapSearch<'r, K, V, P> {
+    type Item = (&'r (K, K), &'r V);
+
+    fn next(&mut self) -> Option<(&'r (K, K), &'r V)> {
+        self.search.next()
+    }
+}
+
+impl<K, V> IntervalMap<K, V> {
+    /// Makes a new empty IntervalMap
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new();
+    /// let new_map = map.insert((1, 2), "a");
+    /// ```
+    pub fn new() -> IntervalMap<K, V> {
+        IntervalMap { root: None }
+    }
+}
+
+impl<K, V, P: PointerKind> IntervalMap<K, V, P> {
+    /// Returns the number of intervals in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((1, 3), "a").insert((2, 4), "b");
+    /// assert_eq!(2, map.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        tree::size(&self.root)
+    }
+
+    /// Returns true if the map contains no intervals.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let empty_map = IntervalMap::new();
+    /// let new_map = empty_map.insert((1, 2), "a");
+    ///
+    /// assert!(empty_map.is_empty());
+    /// assert!(!new_map.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Gets an iterator over the intervals of the map and their values, sorted by the starts
+    /// and then by the ends of the intervals.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((2, 3), "b").insert((1, 5), "a").insert((1, 2), "c");
+    ///
+    /// assert_eq!(vec![(&(1, 2), &"c"), (&(1, 5), &"a"), (&(2, 3), &"b")],
+    ///            map.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn iter<'r>(&'r self) -> IntervalMapIter<'r, K, V, P> {
+        IntervalMapIter { iter: tree::Iter::new(&self.root) }
+    }
+}
+
+impl<K: Ord + Clone, V, P: PointerKind> IntervalMap<K, V, P> {
+    /// Returns a reference to the value of the given interval.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((1, 3), "a");
+    ///
+    /// assert_eq!(Some(&"a"), map.get(&(1, 3)));
+    /// assert_eq!(None, map.get(&(1, 2)));
+    /// ```
+    pub fn get(&self, interval: &(K, K)) -> Option<&V> {
+        tree::find_exact(&self.root, |k| interval.cmp(k)).map(|p| &p.1)
+    }
+
+    /// Gets an iterator over the intervals which share at least one point with the range from
+    /// `start` inclusive to `end` exclusive. The iterator yields nothing if the range is empty,
+    /// that is, `start >= end`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((1, 3), 'a').insert((3, 5), 'b').insert((6, 8), 'c');
+    ///
+    /// assert_eq!(vec![(&(3, 5), &'b')], map.overlapping(&3, &6).collect::<Vec<_>>());
+    /// assert_eq!(0, map.overlapping(&5, &6).count());
+    /// ```
+    pub fn overlapping<'r>(&'r self, start: &K, end: &K) -> IntervalMapSearch<'r, K, V, P> {
+        let root = if start < end { &self.root } else { &None };
+        self.search(root, Bound::Excluded(end.clone()), Bound::Excluded(start.clone()))
+    }
+
+    /// Gets an iterator over the intervals which contain the given point.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((1, 3), 'a').insert((2, 4), 'b').insert((3, 5), 'c');
+    ///
+    /// assert_eq!(vec![&'b', &'c'], map.stabbing(&3).map(|(_, v)| v).collect::<Vec<_>>());
+    /// assert_eq!(0, map.stabbing(&5).count());
+    /// ```
+    pub fn stabbing<'r>(&'r self, point: &K) -> IntervalMapSearch<'r, K, V, P> {
+        self.search(&self.root, Bound::Included(point.clone()), Bound::Excluded(point.clone()))
+    }
+
+    /// Gets an iterator over the intervals which contain the whole range from `start` inclusive
+    /// to `end` exclusive. The iterator yields nothing if the range is empty, that is,
+    /// `start >= end`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((1, 6), 'a').insert((2, 4), 'b').insert((3, 8), 'c');
+    ///
+    /// assert_eq!(vec![&'a', &'c'], map.containing(&3, &6).map(|(_, v)| v).collect::<Vec<_>>());
+    /// assert_eq!(0, map.containing(&3, &3).count());
+    /// ```
+    pub fn containing<'r>(&'r self, start: &K, end: &K) -> IntervalMapSearch<'r, K, V, P> {
+        let root = if start < end { &self.root } else { &None };
+        self.search(root, Bound::Included(start.clone()), Bound::Included(end.clone()))
+    }
+
+    fn search<'r>(&'r self, root: &'r Option<NodeRef<(K, K), V, P, MaxEnd>>,
+                  start_max: Bound<K>, end_min: Bound<K>)
+        -> IntervalMapSearch<'r, K, V, P>
+    {
+        let query = IntervalQuery { start_max, end_min };
+        IntervalMapSearch { search: tree::Search::new(root, query) }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, P: PointerKind> IntervalMap<K, V, P> {
+    /// Returns a new copy of the map with the interval and the value inserted. If the map
+    /// already has the same interval, the value is replaced in the new map. Inserting an empty
+    /// interval, where `start >= end`, returns an unchanged copy of the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((1, 3), "a");
+    /// let new_map = map.insert((1, 3), "b");
+    ///
+    /// assert_eq!(Some(&"a"), map.get(&(1, 3)));
+    /// assert_eq!(Some(&"b"), new_map.get(&(1, 3)));
+    /// assert_eq!(map, map.insert((2, 2), "c"));
+    /// ```
+    pub fn insert(&self, interval: (K, K), value: V) -> IntervalMap<K, V, P> {
+        if interval.0 >= interval.1 {
+            return self.clone();
+        }
+        let root = tree::insert(&self.root, (interval, value), &Natural);
+        IntervalMap { root: Some(NodeRef::new(root)) }
+    }
+
+    /// Removes the interval from the map, and returns a new copy of the map without the
+    /// interval and a reference to its value. Returns `None` if the map did not contain the
+    /// interval.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::IntervalMap;
+    ///
+    /// let map = IntervalMap::new().insert((1, 3), "a").insert((2, 4), "b");
+    ///
+    /// let (new_map, value) = map.remove(&(1, 3)).unwrap();
+    ///
+    /// assert_eq!(&"a", value);
+    /// assert_eq!(0, new_map.stabbing(&1).count());
+    /// assert!(new_map.remove(&(1, 3)).is_none());
+    /// ```
+    pub fn remove(&self, interval: &(K, K)) -> Option<(IntervalMap<K, V, P>, &V)> {
+        tree::remove(&self.root, interval, &Natural).map(|(new_root, v)|
+            (IntervalMap { root: new_root }, &v.1)
+        )
+    }
+}
+
+impl<K, V, P: PointerKind> Clone for IntervalMap<K, V, P> {
+    fn clone(&self) -> IntervalMap<K, V, P> {
+        IntervalMap { root: self.root.clone() }
+    }
+}
+
+impl<K, V, P: PointerKind> Default for IntervalMap<K, V, P> {
+    fn default() -> IntervalMap<K, V, P> {
+        IntervalMap { root: None }
+    }
+}
+
+impl<K: Debug, V: Debug, P: PointerKind> Debug for IntervalMap<K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'r, K, V, P: PointerKind> IntoIterator for &'r IntervalMap<K, V, P> {
+    type Item = (&'r (K, K), &'r V);
+    type IntoIter = IntervalMapIter<'r, K, V, P>;
+
+    fn into_iter(self) -> IntervalMapIter<'r, K, V, P> {
+        self.iter()
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, P: PointerKind> PartialEq for IntervalMap<K, V, P> {
+    fn eq(&self, other: &IntervalMap<K, V, P>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Eq, V: Eq, P: PointerKind> Eq for IntervalMap<K, V, P> {}
+
+impl<K: Ord + Clone, V: Clone, P: PointerKind> FromIterator<((K, K), V)> for IntervalMap<K, V, P> {
+    fn from_iter<T>(iter: T) -> IntervalMap<K, V, P> where T: IntoIterator<Item=((K, K), V)> {
+        // the empty intervals are skipped as in `insert`
+        let mut elems: Vec<((K, K), V)> =
+            iter.into_iter().filter(|e| (e.0).0 < (e.0).1).collect();
+        if tree::is_sorted(&elems, &Natural) {
+            tree::dedup_sorted(&mut elems, &Natural);
+            return IntervalMap { root: tree::from_sorted_vec(elems) };
+        }
+
+        let mut m = IntervalMap { root: None };
+        for (k, v) in elems {
+            m = m.insert(k, v);
+        }
+        m
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tree::balanced;
+
+    use super::IntervalMap;
+
+    // a key type which is neither `Ord` nor `Clone`
+    #[derive(Debug, PartialEq)]
+    struct Opaque;
+
+    #[test]
+    fn test_unordered_key_type() {
+        let map: IntervalMap<Opaque, i32> = IntervalMap::new();
+        let copied = map.clone();
+
+        assert!(copied.is_empty());
+        assert_eq!(map, copied);
+        assert_eq!("{}", format!("{:?}", copied));
+    }
+
+    #[test]
+    fn test_touching_intervals() {
+        let map = IntervalMap::new().insert((1, 3), 'a').insert((3, 5), 'b');
+
+        // the intervals are half-open, so the end points are not contained
+        assert_eq!(vec![&'b'], map.stabbing(&3).map(|(_, v)| v).collect::<Vec<_>>());
+        assert_eq!(vec![&'a'], map.overlapping(&0, &3).map(|(_, v)| v).collect::<Vec<_>>());
+        assert_eq!(0, map.overlapping(&2, &2).count());
+    }
+
+    #[test]
+    fn test_empty_intervals() {
+        let map = IntervalMap::new().insert((1, 3), 'a').insert((3, 3), 'b');
+        let collected: IntervalMap<_, _> = vec![((1, 3), 'a'), ((4, 2), 'c')].into_iter().collect();
+
+        // the empty intervals are never stored, and the empty ranges find nothing
+        assert_eq!(1, map.len());
+        assert_eq!(map, collected);
+        assert_eq!(0, map.overlapping(&2, &2).count());
+        assert_eq!(0, map.containing(&2, &2).count());
+        assert_eq!(0, map.containing(&3, &1).count());
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let sorted: IntervalMap<_, _> = (0..100).map(|i| ((i, i + 10), i)).collect();
+        let unsorted: IntervalMap<_, _> = (0..100).rev().map(|i| ((i, i + 10), i)).collect();
+
+        assert!(balanced(&sorted.root));
+        assert_eq!(sorted, unsorted);
+        assert_eq!(10, sorted.stabbing(&50).count());
+    }
+
+    #[test]
+    fn test_remove_long_interval() {
+        // the maximum ends cached on the path are updated when the longest interval goes away
+        let map: IntervalMap<_, _> = (0..100).map(|i| ((i, i + 1), i)).collect();
+        let with_long = map.insert((0, 100), 100);
+        let (without_long, _) = with_long.remove(&(0, 100)).unwrap();
+
+        assert_eq!(2, with_long.stabbing(&50).count());
+        assert_eq!(vec![(&(50, 51), &50)], without_long.stabbing(&50).collect::<Vec<_>>());
+        assert_eq!(0, without_long.containing(&10, &20).count());
+        assert!(balanced(&without_long.root));
+    }
+}
+
+#[cfg(test)]
+mod quickcheck {
+    use std::collections::BTreeMap;
+
+    use interval::IntervalMap;
+    use tree::balanced;
+
+    fn build(xs: &[(u8, u8)]) -> (IntervalMap<u8, usize>, BTreeMap<(u8, u8), usize>) {
+        let mut map = IntervalMap::new();
+        let mut model = BTreeMap::new();
+        for (i, &(a, b)) in xs.iter().enumerate() {
+            map = map.insert((a, b), i);
+            if a < b {
+                model.insert((a, b), i);
+            }
+        }
+        (map, model)
+    }
+
+    quickcheck! {
+        fn check_insert_remove(xs: Vec<(u8, u8)>, ys: Vec<(u8, u8)>) -> bool {
+            let (mut map, mut model) = build(&xs);
+            if !map.iter().eq(model.iter()) {
+                return false;
+            }
+            for y in ys {
+                match map.remove(&y) {
+                    Some((new_map, &v)) => {
+                        if model.remove(&y) != Some(v) {
+                            return false;
+                        }
+                        map = new_map;
+                    },
+                    None => if model.contains_key(&y) {
+                        return false;
+                    }
+                }
+            }
+            balanced(&map.root) && map.iter().eq(model.iter())
+        }
+
+        fn check_overlapping(xs: Vec<(u8, u8)>, a: u8, b: u8) -> bool {
+            let (map, model) = build(&xs);
+            map.overlapping(&a, &b).eq(model.iter().filter(|&(k, _)| k.0 < b && a < k.1 && a < b))
+        }
+
+        fn check_stabbing(xs: Vec<(u8, u8)>, p: u8) -> bool {
+            let (map, model) = build(&xs);
+            map.stabbing(&p).eq(model.iter().filter(|&(k, _)| k.0 <= p && p < k.1))
+        }
+
+        fn check_containing(xs: Vec<(u8, u8)>, a: u8, b: u8) -> bool {
+            let (map, model) = build(&xs);
+            map.containing(&a, &b).eq(model.iter().filter(|&(k, _)| k.0 <= a && b <= k.1 && a < b))
+        }
+    }
+}
@@ -9,12 +9,36 @@
 //! To share the data between the old and the new data structure after modification, most of the
 //! functions require the key and value type to implement `Clone`. If you want to store non-
 //! clonable data into this map, you can wrap it under shared pointer such as `Rc` or `Arc`.
+//!
+//! `TreeMap` and `TreeSet` share their nodes with `Rc`, and cannot be sent to other threads.
+//! `ArcTreeMap` and `ArcTreeSet` provide the same API with the nodes shared with `Arc`.
+//!
+//! `TreeBag` is a multiset, which counts the duplicated elements. Its positions are weighted by
+//! the multiplicities of the elements. `TreeMultiMap` associates each key with a set of values.
+//! `TreeVec` is a sequence indexed by position, which supports insertion, removal, splitting and
+//! concatenation at any position in O(log n) time. `IntervalMap` finds the intervals overlapping
+//! a range or containing a point by caching the maximum end of the intervals of each subtree.
+//! `RangeMap` and `RangeSet` keep disjoint ranges, and merge the adjacent ranges on insertion.
+//!
+//! The keys are ordered by `Ord` by default. Maps and sets made with `with_comparator` order the
+//! keys with the given comparator instead. See the `compare` module.
+//!
+//! `AugmentedMap` caches the summaries of its subtrees, such as the sum or the maximum of the
+//! values, so that the summary of any range of the keys is computed in O(log n) time. See the
+//! `measure` module. With the `CachedHash` measure, the map is hashed in O(1) time.
+//!
+//! With the `serde` feature, the maps are serialized as maps and the sets as sequences. The
+//! deserialized collections are built in O(n) time if the input is sorted.
 
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
 #[cfg(test)]
 extern crate rand;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_test;
 
 #[cfg(test)]
 use quickcheck::{Arbitrary, Gen};
@@ -23,10 +47,39 @@ use quickcheck::{Arbitrary, Gen};
 pub mod set;
 /// An immutable map based on binary search tree
 pub mod map;
+/// An immutable multiset based on binary search tree
+pub mod bag;
+/// An immutable multimap based on binary search tree
+pub mod multimap;
+/// An immutable sequence based on binary search tree
+pub mod vec;
+/// An immutable interval map based on binary search tree
+pub mod interval;
+/// An immutable map of disjoint ranges based on binary search tree
+pub mod rangemap;
+/// An immutable set of disjoint ranges based on binary search tree
+pub mod rangeset;
+/// Shared pointer kinds which link the nodes of the trees
+pub mod pointer;
+/// Comparators which define the order of the keys
+pub mod compare;
+/// An immutable map which caches the summaries of its subtrees
+pub mod augmented;
+/// Measures which summarize the elements of augmented trees
+pub mod measure;
 mod tree;
+#[cfg(feature = "serde")]
+mod serde_impls;
 
-pub use set::TreeSet;
-pub use map::TreeMap;
+pub use set::{TreeSet, ArcTreeSet};
+pub use map::{TreeMap, ArcTreeMap};
+pub use bag::{TreeBag, ArcTreeBag};
+pub use multimap::{TreeMultiMap, ArcTreeMultiMap};
+pub use vec::{TreeVec, ArcTreeVec};
+pub use interval::{IntervalMap, ArcIntervalMap};
+pub use rangemap::{RangeMap, ArcRangeMap};
+pub use rangeset::{RangeSet, ArcRangeSet};
+pub use augmented::{AugmentedMap, ArcAugmentedMap};
 
 /// An endpoint of a range of keys.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -50,3 +103,23 @@ impl<T: Arbitrary> Arbitrary for Bound<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_arc_collections_are_send() {
+        assert_send::<ArcTreeMap<i32, i32>>();
+        assert_send::<ArcTreeSet<i32>>();
+        assert_send::<ArcTreeBag<i32>>();
+        assert_send::<ArcTreeMultiMap<i32, i32>>();
+        assert_send::<ArcTreeVec<i32>>();
+        assert_send::<ArcIntervalMap<i32, i32>>();
+        assert_send::<ArcRangeMap<i32, i32>>();
+        assert_send::<ArcRangeSet<i32>>();
+        assert_send::<ArcAugmentedMap<i32, i32, ()>>();
+    }
+}
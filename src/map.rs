@@ -2,14 +2,16 @@ use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 use std::ops::Index;
-use std::rc::Rc;
 
 use Bound;
+use compare::{Compare, Natural};
+use pointer::{PointerKind, RcKind, ArcKind};
 
 use tree;
-use tree::TreeNode;
+use tree::NodeRef;
 
 /// An immutable key-value map based on weight-balanced binary tree.
 /// See https://yoichihirai.com/bst.pdf for the balancing algorithm.
@@ -31,20 +33,347 @@ use tree::TreeNode;
 ///
 /// assert_eq!("Four", map_2[&4]);
 /// ```
-#[derive(Clone, Default)]
-pub struct TreeMap<K, V> {
-    root: Option<Rc<TreeNode<K, V>>>
+pub struct TreeMap<K, V, P: PointerKind = RcKind, C = Natural> {
+    root: Option<NodeRef<K, V, P>>,
+    cmp: C
 }
 
-pub type TreeMapIter<'r, K, V> = tree::Iter<'r, K, V>;
-pub type TreeMapRevIter<'r, K, V> = tree::RevIter<'r, K, V>;
-pub type TreeMapRange<'r, K, V> = tree::Range<'r, K, V>;
-pub type TreeMapKeys<'r, K, V> = tree::Keys<tree::Iter<'r, K, V>>;
-pub type TreeMapValues<'r, K, V> = tree::Values<tree::Iter<'r, K, V>>;
+/// A thread-safe variant of `TreeMap`, which shares its nodes with `Arc` instead of `Rc`.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+/// use immutable_map::ArcTreeMap;
+///
+/// let map = ArcTreeMap::default().insert(1, "One").insert(2, "Two");
+///
+/// let map_in_thread = map.clone();
+/// let handle = thread::spawn(move || map_in_thread.insert(3, "Three"));
+/// let new_map = handle.join().unwrap();
+///
+/// assert_eq!(2, map.len());
+/// assert_eq!(Some(&"Three"), new_map.get(&3));
+/// ```
+pub type ArcTreeMap<K, V> = TreeMap<K, V, ArcKind>;
+
+pub type TreeMapIter<'r, K, V, P = RcKind> = tree::Iter<'r, K, V, P>;
+pub type TreeMapRevIter<'r, K, V, P = RcKind> = tree::RevIter<'r, K, V, P>;
+pub type TreeMapRange<'r, K, V, P = RcKind, C = Natural> = tree::Range<'r, K, V, P, C>;
+pub type TreeMapKeys<'r, K, V, P = RcKind> = tree::Keys<tree::Iter<'r, K, V, P>>;
+pub type TreeMapValues<'r, K, V, P = RcKind> = tree::Values<tree::Iter<'r, K, V, P>>;
+pub type TreeMapIntoIter<K, V, P = RcKind> = tree::IntoIter<K, V, P>;
+
+/// A cursor over the elements of a `TreeMap`, which can move in both directions.
+///
+/// A cursor points to an element of the map, or to the "ghost" position, which is after the last
+/// element and before the first element. Moving the cursor takes amortized O(1) time, and
+/// O(log n) time in the worst case.
+pub struct TreeMapCursor<'r, K: 'r, V: 'r, P: PointerKind = RcKind> {
+    cursor: tree::Cursor<'r, K, V, P>
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind> TreeMapCursor<'r, K, V, P> {
+    /// Returns the key of the current element, or `None` at the ghost position.
+    pub fn key(&self) -> Option<&'r K> {
+        self.cursor.key()
+    }
+
+    /// Returns the value of the current element, or `None` at the ghost position.
+    pub fn value(&self) -> Option<&'r V> {
+        self.cursor.value()
+    }
+
+    /// Returns the position of the current element in the key order, or `None` at the ghost
+    /// position.
+    pub fn rank(&self) -> Option<usize> {
+        self.cursor.rank()
+    }
+
+    /// Moves to the next element. Moving from the ghost position goes to the first element, and
+    /// moving from the last element goes to the ghost position.
+    pub fn move_next(&mut self) {
+        self.cursor.move_next()
+    }
+
+    /// Moves to the previous element. Moving from the ghost position goes to the last element,
+    /// and moving from the first element goes to the ghost position.
+    pub fn move_prev(&mut self) {
+        self.cursor.move_prev()
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind> Clone for TreeMapCursor<'r, K, V, P> {
+    fn clone(&self) -> TreeMapCursor<'r, K, V, P> {
+        TreeMapCursor { cursor: self.cursor.clone() }
+    }
+}
+
+/// A difference between two maps, yielded by `TreeMapDiff`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DiffItem<'r, K: 'r, V: 'r> {
+    /// The key and the value which are only in the other map
+    Added(&'r K, &'r V),
+    /// The key and the value which are only in this map
+    Removed(&'r K, &'r V),
+    /// The key which is in both maps with different values, with the value of this map and the
+    /// value of the other map
+    Changed(&'r K, &'r V, &'r V)
+}
+
+impl<'r, K: 'r, V: 'r> Clone for DiffItem<'r, K, V> {
+    fn clone(&self) -> DiffItem<'r, K, V> {
+        *self
+    }
+}
+
+impl<'r, K: 'r, V: 'r> Copy for DiffItem<'r, K, V> {}
+
+/// An iterator over the differences between two maps. See `TreeMap::diff`.
+pub struct TreeMapDiff<'r, K: 'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    diff: tree::Diff<'r, K, V, P, C>
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r> Iterator for TreeMapDiff<'r, K, V, P, C>
+    where V: PartialEq, C: Compare<K>
+{
+    type Item = DiffItem<'r, K, V>;
+
+    fn next(&mut self) -> Option<DiffItem<'r, K, V>> {
+        self.diff.next().map(|pair| match pair {
+            (Some(a), Some(b)) => DiffItem::Changed(&a.0, &a.1, &b.1),
+            (Some(a), None) => DiffItem::Removed(&a.0, &a.1),
+            (None, Some(b)) => DiffItem::Added(&b.0, &b.1),
+            (None, None) => panic!("diff yielded no elements")
+        })
+    }
+}
+
+/// A view into a single entry of a `TreeMap`, which is either occupied or vacant.
+///
+/// This is constructed with `TreeMap::entry`. The entry remembers the path from the root to the
+/// key, so the new map built from the entry copies only the nodes on the path without searching
+/// the key again.
+pub enum Entry<'r, K: 'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    /// An entry of the key which is in the map
+    Occupied(OccupiedEntry<'r, K, V, P, C>),
+    /// An entry of the key which is not in the map
+    Vacant(VacantEntry<'r, K, V, P, C>)
+}
+
+/// An entry of the key which is in the map. See `Entry`.
+pub struct OccupiedEntry<'r, K: 'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    map: &'r TreeMap<K, V, P, C>,
+    path: tree::Path<'r, K, V, P>
+}
+
+/// An entry of the key which is not in the map. See `Entry`.
+pub struct VacantEntry<'r, K: 'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    map: &'r TreeMap<K, V, P, C>,
+    key: K,
+    path: tree::Path<'r, K, V, P>
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r> Entry<'r, K, V, P, C> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref e) => e.key(),
+            Entry::Vacant(ref e) => e.key()
+        }
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r> Entry<'r, K, V, P, C>
+    where K: Clone, V: Clone, C: Clone
+{
+    /// Returns the map with the default value inserted if the entry is vacant, or the original
+    /// map if the entry is occupied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert("One", 1);
+    ///
+    /// assert_eq!(Some(&1), map.entry("One").or_insert(10).get("One"));
+    /// assert_eq!(Some(&2), map.entry("Two").or_insert(2).get("Two"));
+    /// ```
+    pub fn or_insert(self, default: V) -> TreeMap<K, V, P, C> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Returns the map with the value returned by `f` inserted if the entry is vacant, or the
+    /// original map if the entry is occupied. `f` is called only if the entry is vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert("One", 1);
+    ///
+    /// let new_map = map.entry("Two").or_insert_with(|| 2);
+    /// assert_eq!(Some(&2), new_map.get("Two"));
+    ///
+    /// let same_map = new_map.entry("Two").or_insert_with(|| panic!("not called"));
+    /// assert_eq!(Some(&2), same_map.get("Two"));
+    /// ```
+    pub fn or_insert_with<F>(self, f: F) -> TreeMap<K, V, P, C>
+        where F: FnOnce() -> V
+    {
+        match self {
+            Entry::Occupied(e) => e.map.clone(),
+            Entry::Vacant(e) => e.insert(f())
+        }
+    }
+
+    /// Returns the map with the value updated with `f` if the entry is occupied, or the original
+    /// map if the entry is vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert("One", 1);
+    ///
+    /// assert_eq!(Some(&11), map.entry("One").and_modify(|v| v + 10).get("One"));
+    /// assert_eq!(None, map.entry("Two").and_modify(|v| v + 10).get("Two"));
+    /// ```
+    pub fn and_modify<F>(self, f: F) -> TreeMap<K, V, P, C>
+        where F: FnOnce(&V) -> V
+    {
+        match self {
+            Entry::Occupied(e) => e.update(f),
+            Entry::Vacant(e) => e.map.clone()
+        }
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r> OccupiedEntry<'r, K, V, P, C> {
+    /// Returns the key of the entry, which is the key stored in the map.
+    pub fn key(&self) -> &'r K {
+        &self.elem().0
+    }
+
+    /// Returns the value of the entry.
+    pub fn get(&self) -> &'r V {
+        &self.elem().1
+    }
+
+    fn elem(&self) -> &'r (K, V) {
+        self.path.found().expect("occupied entry does not have the element")
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r> OccupiedEntry<'r, K, V, P, C>
+    where K: Clone, V: Clone, C: Clone
+{
+    /// Returns the map with the value of the entry replaced with the given value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::map::Entry;
+    ///
+    /// let map = TreeMap::new().insert("One", 1);
+    ///
+    /// if let Entry::Occupied(e) = map.entry("One") {
+    ///     assert_eq!(&1, e.get());
+    ///     assert_eq!(Some(&10), e.insert(10).get("One"));
+    /// }
+    /// ```
+    pub fn insert(self, value: V) -> TreeMap<K, V, P, C> {
+        let key = self.key().clone();
+        let root = self.path.replace((key, value));
+        self.map.with_root(Some(NodeRef::new(root)))
+    }
+
+    /// Returns the map with the value of the entry updated with `f`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::map::Entry;
+    ///
+    /// let map = TreeMap::new().insert("One", 1);
+    ///
+    /// if let Entry::Occupied(e) = map.entry("One") {
+    ///     assert_eq!(Some(&11), e.update(|v| v + 10).get("One"));
+    /// }
+    /// ```
+    pub fn update<F>(self, f: F) -> TreeMap<K, V, P, C>
+        where F: FnOnce(&V) -> V
+    {
+        let value = f(self.get());
+        self.insert(value)
+    }
+
+    /// Returns the map with the entry removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::map::Entry;
+    ///
+    /// let map = TreeMap::new().insert("One", 1).insert("Two", 2);
+    ///
+    /// if let Entry::Occupied(e) = map.entry("One") {
+    ///     let new_map = e.remove();
+    ///     assert_eq!(None, new_map.get("One"));
+    ///     assert_eq!(1, new_map.len());
+    /// }
+    /// ```
+    pub fn remove(self) -> TreeMap<K, V, P, C> {
+        self.map.with_root(self.path.remove())
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r> VacantEntry<'r, K, V, P, C> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes the ownership of the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r> VacantEntry<'r, K, V, P, C>
+    where K: Clone, V: Clone, C: Clone
+{
+    /// Returns the map with the key of the entry and the given value inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::map::Entry;
+    ///
+    /// let map = TreeMap::new().insert("One", 1);
+    ///
+    /// if let Entry::Vacant(e) = map.entry("Two") {
+    ///     assert_eq!(Some(&2), e.insert(2).get("Two"));
+    /// }
+    /// ```
+    pub fn insert(self, value: V) -> TreeMap<K, V, P, C> {
+        let root = self.path.insert((self.key, value));
+        self.map.with_root(Some(NodeRef::new(root)))
+    }
+}
 
 impl<K, V> TreeMap<K, V> {
     /// Makes a new empty TreeMap
     ///
+    /// The maps shared with `Arc` are made with `ArcTreeMap::default()`.
+    ///
     /// # Examples
     ///
     /// ```
@@ -54,9 +383,53 @@ impl<K, V> TreeMap<K, V> {
     /// let new_map = map.insert("One", 1);
     /// ```
     pub fn new() -> TreeMap<K, V> {
-        TreeMap { root: None }
+        TreeMap { root: None, cmp: Natural }
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMap<K, V, P, C> {
+    /// Makes a new empty TreeMap which orders the keys with the given comparator.
+    ///
+    /// The maps made from this map keep the comparator. When two maps are combined, such as with
+    /// `union_with`, both maps must order the keys in the same way, and the comparator of `self`
+    /// is kept.
+    ///
+    /// The pointer kind is not inferred from the comparator, so the type of the map has to be
+    /// given when it is not known from the context.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let map: TreeMap<_, _, RcKind, _> = TreeMap::with_comparator(|a: &i32, b: &i32| b.cmp(a))
+    ///     .insert(1, "One")
+    ///     .insert(2, "Two");
+    ///
+    /// assert_eq!(vec![&2, &1], map.keys().collect::<Vec<_>>());
+    /// ```
+    ///
+    /// The maps shared with `Arc` take the comparators in the same way.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::pointer::ArcKind;
+    ///
+    /// let map: TreeMap<_, _, ArcKind, _> = TreeMap::with_comparator(|a: &i32, b: &i32| b.cmp(a))
+    ///     .insert(1, "One")
+    ///     .insert(2, "Two");
+    ///
+    /// let handle = thread::spawn(move || map.keys().cloned().collect::<Vec<_>>());
+    /// assert_eq!(vec![2, 1], handle.join().unwrap());
+    /// ```
+    pub fn with_comparator(cmp: C) -> TreeMap<K, V, P, C> {
+        TreeMap { root: None, cmp }
     }
+}
 
+impl<K, V, P: PointerKind, C> TreeMap<K, V, P, C> {
     /// Returns the number of elements in the map.
     ///
     /// # Examples
@@ -104,7 +477,7 @@ impl<K, V> TreeMap<K, V> {
     /// let (first_key, first_value) = map.iter().next().unwrap();
     /// assert_eq!((1, "One"), (*first_key, *first_value));
     /// ```
-    pub fn iter<'r>(&'r self) -> TreeMapIter<'r, K, V> {
+    pub fn iter<'r>(&'r self) -> TreeMapIter<'r, K, V, P> {
         tree::Iter::new(&self.root)
     }
 
@@ -124,8 +497,8 @@ impl<K, V> TreeMap<K, V> {
     /// let (first_key, first_value) = map.rev_iter().next().unwrap();
     /// assert_eq!((3, "Three"), (*first_key, *first_value));
     /// ```
-    pub fn rev_iter<'r>(&'r self) -> TreeMapRevIter<'r, K, V> {
-        tree::RevIter::new(&self.root)
+    pub fn rev_iter<'r>(&'r self) -> TreeMapRevIter<'r, K, V, P> {
+        tree::Iter::new(&self.root).rev()
     }
 
     /// Gets an iterator over the keys of the map, in increasing order.
@@ -144,7 +517,7 @@ impl<K, V> TreeMap<K, V> {
     /// let first_key = map.keys().next().unwrap();
     /// assert_eq!(1, *first_key);
     /// ```
-    pub fn keys<'r>(&'r self) -> TreeMapKeys<'r, K, V> {
+    pub fn keys<'r>(&'r self) -> TreeMapKeys<'r, K, V, P> {
         tree::Keys::new(tree::Iter::new(&self.root))
     }
 
@@ -164,12 +537,36 @@ impl<K, V> TreeMap<K, V> {
     /// let first_value = map.values().next().unwrap();
     /// assert_eq!("One", *first_value);
     /// ```
-    pub fn values<'r>(&'r self) -> TreeMapValues<'r, K, V> {
+    pub fn values<'r>(&'r self) -> TreeMapValues<'r, K, V, P> {
         tree::Values::new(tree::Iter::new(&self.root))
     }
+
+    /// Returns the key-value pair at the given position in the key order, or `None` if the
+    /// index is out of bounds. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// assert_eq!(Some((&2, &"Two")), map.get_index(1));
+    /// assert_eq!(None, map.get_index(3));
+    /// ```
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        tree::find_index(&self.root, index).map(|p| (&p.0, &p.1))
+    }
 }
 
-impl<K, V> TreeMap<K, V> where K: Ord {
+impl<K, V, P: PointerKind, C> TreeMap<K, V, P, C> where C: Clone {
+    // makes the map with the given root, which orders the keys with the comparator of this map
+    fn with_root<X>(&self, root: Option<NodeRef<K, X, P>>) -> TreeMap<K, X, P, C> {
+        TreeMap { root, cmp: self.cmp.clone() }
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMap<K, V, P, C> where C: Compare<K> {
     /// Returns a reference to the value corresponding to the key.
     ///
     /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
@@ -185,16 +582,108 @@ impl<K, V> TreeMap<K, V> where K: Ord {
     /// assert_eq!(map.get(&1), Some(&"One"));
     /// assert_eq!(map.get(&2), None);
     /// ```
-    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&V>
-        where K: Borrow<Q>
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+        where K: Borrow<Q>, C: Compare<Q>
     {
-        fn f<'r, K, V, Q: ?Sized + Ord>(node: &'r Option<Rc<TreeNode<K, V>>>, key: &Q)
-                -> Option<&'r V> where K: Borrow<Q>
+        fn f<'r, K, V, P: PointerKind, Q: ?Sized, C>(node: &'r Option<NodeRef<K, V, P>>,
+                                                     key: &Q,
+                                                     cmp: &C)
+                -> Option<&'r V> where K: Borrow<Q>, C: Compare<Q>
         {
-            tree::find_exact(node, |k| key.cmp(k.borrow())).map(|p| &p.1)
+            tree::find_exact(node, |k| cmp.compare(key, k.borrow())).map(|p| &p.1)
         }
 
-        f(&self.root, key)
+        f(&self.root, key, &self.cmp)
+    }
+
+    /// Returns the key-value pair with the greatest key not larger than the given key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(10, "Ten").insert(20, "Twenty");
+    ///
+    /// assert_eq!(Some((&10, &"Ten")), map.get_le(&15));
+    /// assert_eq!(Some((&20, &"Twenty")), map.get_le(&20));
+    /// assert_eq!(None, map.get_le(&5));
+    /// ```
+    pub fn get_le<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_last(&self.root, |k| self.cmp.compare(k.borrow(), key) != Ordering::Greater)
+            .map(|p| (&p.0, &p.1))
+    }
+
+    /// Returns the key-value pair with the greatest key smaller than the given key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(10, "Ten").insert(20, "Twenty");
+    ///
+    /// assert_eq!(Some((&10, &"Ten")), map.get_lt(&20));
+    /// assert_eq!(None, map.get_lt(&10));
+    /// ```
+    pub fn get_lt<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_last(&self.root, |k| self.cmp.compare(k.borrow(), key) == Ordering::Less)
+            .map(|p| (&p.0, &p.1))
+    }
+
+    /// Returns the key-value pair with the smallest key not smaller than the given key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(10, "Ten").insert(20, "Twenty");
+    ///
+    /// assert_eq!(Some((&20, &"Twenty")), map.get_ge(&15));
+    /// assert_eq!(Some((&10, &"Ten")), map.get_ge(&10));
+    /// assert_eq!(None, map.get_ge(&25));
+    /// ```
+    pub fn get_ge<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_first(&self.root, |k| self.cmp.compare(k.borrow(), key) != Ordering::Less)
+            .map(|p| (&p.0, &p.1))
+    }
+
+    /// Returns the key-value pair with the smallest key larger than the given key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(10, "Ten").insert(20, "Twenty");
+    ///
+    /// assert_eq!(Some((&20, &"Twenty")), map.get_gt(&10));
+    /// assert_eq!(None, map.get_gt(&20));
+    /// ```
+    pub fn get_gt<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_first(&self.root, |k| self.cmp.compare(k.borrow(), key) == Ordering::Greater)
+            .map(|p| (&p.0, &p.1))
     }
 
     /// Returns true if the map contains given key
@@ -212,16 +701,39 @@ impl<K, V> TreeMap<K, V> where K: Ord {
     /// assert_eq!(true, map.contains_key(&1));
     /// assert_eq!(false, map.contains_key(&2));
     /// ```
-    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool
-        where K: Borrow<Q>
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+        where K: Borrow<Q>, C: Compare<Q>
     {
         self.get(key).is_some()
     }
 
+    /// Returns the position of the key in the key order, or `None` if the map does not contain
+    /// the key. This operation takes O(log n) time.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// assert_eq!(Some(2), map.index_of(&3));
+    /// assert_eq!(None, map.index_of(&4));
+    /// ```
+    pub fn index_of<Q: ?Sized>(&self, key: &Q) -> Option<usize>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::rank(&self.root, key, &self.cmp)
+    }
+
     /// Constructs a double-ended iterator over a sub-range of elements in the map, starting at
     /// min, and ending at max. If min is Unbounded, then it will be treated as "negative
     /// infinity", and if max is Unbounded, then it will be treated as "positive infinity". Thus
-    /// range(Unbounded, Unbounded) will yield the whole collection.
+    /// range(Unbounded, Unbounded) will yield the whole collection. The length of the range is
+    /// computed in O(log n) time.
     ///
     /// # Examples
     ///
@@ -231,265 +743,1127 @@ impl<K, V> TreeMap<K, V> where K: Ord {
     ///
     /// let map = TreeMap::new().insert(8, "Eight").insert(3, "Three").insert(5, "Five");
     ///
-    /// for (key, value) in map.range(Included(&4), Included(&8)) {
-    ///     println!("{}: {}", key, value);
-    /// }
+    /// assert_eq!(2, map.range(Included(&4), Included(&8)).len());
+    ///
+    /// for (key, value) in map.range(Included(&4), Included(&8)) {
+    ///     println!("{}: {}", key, value);
+    /// }
+    ///
+    /// let pairs: Vec<_> = map.range(Included(&4), Included(&8)).map(|(k, v)| (*k, *v)).collect();
+    ///
+    /// assert_eq!(pairs, [(5, "Five"), (8, "Eight")]);
+    /// ```
+    pub fn range<'r, Q>(&'r self, min: Bound<&Q>, max: Bound<&Q>) -> TreeMapRange<'r, K, V, P, C>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::Range::new(&self.root, min, max, &self.cmp)
+    }
+
+    /// Returns the number of the elements in the sub-range of the map, which is bounded by min
+    /// and max in the same way as `range`. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::Bound::*;
+    ///
+    /// let map: TreeMap<_, _> = (0..100).map(|i| (i * 2, i)).collect();
+    ///
+    /// assert_eq!(5, map.range_count(Included(&10), Excluded(&20)));
+    /// assert_eq!(90, map.range_count(Excluded(&19), Unbounded));
+    /// assert_eq!(0, map.range_count(Included(&20), Excluded(&10)));
+    /// ```
+    pub fn range_count<Q: ?Sized>(&self, min: Bound<&Q>, max: Bound<&Q>) -> usize
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::range_count(&self.root, min, max, &self.cmp)
+    }
+
+    /// Returns a cursor pointing to the first element with the key not smaller than the given
+    /// key. If there is no such element, the cursor points to the ghost position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(10, "Ten").insert(20, "Twenty").insert(30, "Thirty");
+    ///
+    /// let mut cursor = map.lower_bound(&15);
+    /// assert_eq!(Some(&20), cursor.key());
+    /// assert_eq!(Some(&"Twenty"), cursor.value());
+    /// assert_eq!(Some(1), cursor.rank());
+    ///
+    /// // look at the neighbours
+    /// let mut prev = cursor.clone();
+    /// prev.move_prev();
+    /// cursor.move_next();
+    /// assert_eq!(Some(&10), prev.key());
+    /// assert_eq!(Some(&30), cursor.key());
+    ///
+    /// // moving past the last element goes to the ghost position
+    /// cursor.move_next();
+    /// assert_eq!(None, cursor.key());
+    /// cursor.move_next();
+    /// assert_eq!(Some(&10), cursor.key());
+    ///
+    /// assert_eq!(Some(&20), map.lower_bound(&20).key());
+    /// assert_eq!(None, map.lower_bound(&31).key());
+    /// ```
+    pub fn lower_bound<'r, Q: ?Sized>(&'r self, key: &Q) -> TreeMapCursor<'r, K, V, P>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        TreeMapCursor { cursor: tree::Cursor::lower_bound(&self.root, key, &self.cmp) }
+    }
+
+    /// Returns a cursor pointing to the first element with the key larger than the given key. If
+    /// there is no such element, the cursor points to the ghost position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(10, "Ten").insert(20, "Twenty").insert(30, "Thirty");
+    ///
+    /// assert_eq!(Some(&30), map.upper_bound(&20).key());
+    /// assert_eq!(Some(&10), map.upper_bound(&5).key());
+    ///
+    /// // the ghost position is before the first element
+    /// let mut cursor = map.upper_bound(&30);
+    /// assert_eq!(None, cursor.key());
+    /// cursor.move_prev();
+    /// assert_eq!(Some(&30), cursor.key());
+    /// ```
+    pub fn upper_bound<'r, Q: ?Sized>(&'r self, key: &Q) -> TreeMapCursor<'r, K, V, P>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        TreeMapCursor { cursor: tree::Cursor::upper_bound(&self.root, key, &self.cmp) }
+    }
+
+    /// Returns a cursor pointing to the element with the given key, or `None` if the map does
+    /// not contain the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(10, "Ten").insert(20, "Twenty").insert(30, "Thirty");
+    ///
+    /// let mut cursor = map.find(&20).unwrap();
+    /// cursor.move_prev();
+    /// assert_eq!(Some(&"Ten"), cursor.value());
+    ///
+    /// assert!(map.find(&15).is_none());
+    /// ```
+    pub fn find<'r, Q: ?Sized>(&'r self, key: &Q) -> Option<TreeMapCursor<'r, K, V, P>>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::Cursor::find(&self.root, key, &self.cmp)
+            .map(|cursor| TreeMapCursor { cursor })
+    }
+
+    /// Gets an iterator over the differences from this map to the other map, in the key order.
+    ///
+    /// The subtrees shared by both maps are skipped without visiting their elements. Comparing a
+    /// map with its copy modified by a few insertions or removals visits only the nodes near the
+    /// modified keys, instead of iterating over both maps.
+    ///
+    /// Both maps must order the keys in the same way. The keys are compared with the comparator
+    /// of `self`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::map::DiffItem;
+    ///
+    /// let old_map = TreeMap::new().insert(1, "One").insert(2, "Two").insert(3, "Three");
+    /// let new_map = old_map.insert(2, "Zwei").insert(4, "Four").remove(&1).unwrap().0;
+    ///
+    /// let diff: Vec<_> = old_map.diff(&new_map).collect();
+    ///
+    /// assert_eq!(diff, [
+    ///     DiffItem::Removed(&1, &"One"),
+    ///     DiffItem::Changed(&2, &"Two", &"Zwei"),
+    ///     DiffItem::Added(&4, &"Four")
+    /// ]);
+    /// ```
+    pub fn diff<'r>(&'r self, other: &'r TreeMap<K, V, P, C>) -> TreeMapDiff<'r, K, V, P, C>
+        where V: PartialEq
+    {
+        TreeMapDiff { diff: tree::Diff::new(&self.root, &other.root, &self.cmp) }
+    }
+
+    /// Builds a map from the key-value pairs sorted by key, in O(n) time.
+    ///
+    /// If the input has several pairs with the same key, the last one is kept in the map, as if
+    /// the pairs were inserted one by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let pairs = vec![(1, "One"), (2, "Two"), (2, "Zwei"), (3, "Three")];
+    /// let map: TreeMap<_, _> = TreeMap::from_sorted_iter(pairs);
+    ///
+    /// assert_eq!(3, map.len());
+    /// assert_eq!(Some(&"Zwei"), map.get(&2));
+    /// ```
+    pub fn from_sorted_iter<I>(iter: I) -> TreeMap<K, V, P, C>
+        where I: IntoIterator<Item=(K, V)>, C: Default
+    {
+        TreeMap::from_sorted_iter_by(iter, C::default())
+    }
+
+    /// Builds a map from the key-value pairs sorted by key with the given comparator, in O(n)
+    /// time.
+    ///
+    /// If the input has several pairs with the same key, the last one is kept in the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not in ascending order of the comparator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let pairs = vec![(3, "Three"), (2, "Two"), (1, "One")];
+    /// let map: TreeMap<_, _, RcKind, _> =
+    ///     TreeMap::from_sorted_iter_by(pairs, |a: &i32, b: &i32| b.cmp(a));
+    ///
+    /// assert_eq!(vec![&3, &2, &1], map.keys().collect::<Vec<_>>());
+    /// ```
+    pub fn from_sorted_iter_by<I>(iter: I, cmp: C) -> TreeMap<K, V, P, C>
+        where I: IntoIterator<Item=(K, V)>
+    {
+        let mut elems: Vec<(K, V)> = iter.into_iter().collect();
+        assert!(tree::is_sorted(&elems, &cmp), "keys are not in ascending order");
+        tree::dedup_sorted(&mut elems, &cmp);
+        TreeMap { root: tree::from_sorted_vec(elems), cmp }
+    }
+
+    /// Builds a map from the key-value pairs sorted by key, in O(n) time.
+    ///
+    /// Returns `None` if the keys are not in strictly ascending order, including when the input
+    /// has several pairs with the same key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map: TreeMap<_, _> =
+    ///     TreeMap::from_strictly_sorted_iter(vec![(1, "One"), (2, "Two")]).unwrap();
+    /// assert_eq!(Some(&"Two"), map.get(&2));
+    ///
+    /// let unordered: Option<TreeMap<_, _>> =
+    ///     TreeMap::from_strictly_sorted_iter(vec![(2, "Two"), (1, "One")]);
+    /// assert!(unordered.is_none());
+    ///
+    /// let duplicated: Option<TreeMap<_, _>> =
+    ///     TreeMap::from_strictly_sorted_iter(vec![(1, "One"), (1, "Eins")]);
+    /// assert!(duplicated.is_none());
+    /// ```
+    pub fn from_strictly_sorted_iter<I>(iter: I) -> Option<TreeMap<K, V, P, C>>
+        where I: IntoIterator<Item=(K, V)>, C: Default
+    {
+        TreeMap::from_strictly_sorted_iter_by(iter, C::default())
+    }
+
+    /// Builds a map from the key-value pairs sorted by key with the given comparator, in O(n)
+    /// time.
+    ///
+    /// Returns `None` if the keys are not in strictly ascending order of the comparator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let rev = |a: &i32, b: &i32| b.cmp(a);
+    ///
+    /// let map: Option<TreeMap<_, _, RcKind, _>> =
+    ///     TreeMap::from_strictly_sorted_iter_by(vec![(2, "Two"), (1, "One")], rev);
+    /// assert_eq!(Some(&"One"), map.unwrap().get(&1));
+    ///
+    /// let unordered: Option<TreeMap<_, _, RcKind, _>> =
+    ///     TreeMap::from_strictly_sorted_iter_by(vec![(1, "One"), (2, "Two")], rev);
+    /// assert!(unordered.is_none());
+    /// ```
+    pub fn from_strictly_sorted_iter_by<I>(iter: I, cmp: C) -> Option<TreeMap<K, V, P, C>>
+        where I: IntoIterator<Item=(K, V)>
+    {
+        let elems: Vec<(K, V)> = iter.into_iter().collect();
+        if tree::is_strictly_sorted(&elems, &cmp) {
+            Some(TreeMap { root: tree::from_sorted_vec(elems), cmp })
+        } else {
+            None
+        }
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMap<K, V, P, C>
+    where K: Clone, V: Clone, C: Compare<K> + Clone
+{
+    /// Return a new copy of `TreeMap` with the key-value pair inserted
+    ///
+    /// If the map already has the key, the key-value pair is replaced in the new map
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new();
+    ///
+    /// assert_eq!(false, map.contains_key(&1));
+    /// assert_eq!(None, map.get(&1));
+    ///
+    /// let new_map = map.insert(1, "One");
+    ///
+    /// assert_eq!(true, new_map.contains_key(&1));
+    /// assert_eq!(Some(&"One"), new_map.get(&1));
+    /// ```
+    pub fn insert(&self, key: K, value: V) -> TreeMap<K, V, P, C>
+    {
+        let root = tree::insert(&self.root, (key, value), &self.cmp);
+        self.with_root(Some(NodeRef::new(root)))
+    }
+
+    /// Inserts the key-value pair into the map in place, and returns the replaced value if the
+    /// map already had the key.
+    ///
+    /// The nodes which are not shared with other maps are modified in place, and only the shared
+    /// nodes are copied. Other maps sharing the nodes with this map are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let mut map = TreeMap::new();
+    /// assert_eq!(None, map.insert_mut(1, "One"));
+    ///
+    /// let old_map = map.clone();
+    /// assert_eq!(Some("One"), map.insert_mut(1, "Eins"));
+    ///
+    /// assert_eq!(Some(&"Eins"), map.get(&1));
+    /// assert_eq!(Some(&"One"), old_map.get(&1));
+    /// ```
+    pub fn insert_mut(&mut self, key: K, value: V) -> Option<V>
+    {
+        tree::insert_mut(&mut self.root, (key, value), &self.cmp).map(|p| p.1)
+    }
+
+    /// Return a new copy of `TreeMap` with the key-value pair inserted.
+    ///
+    /// Returns `None` if the map already has the key
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three");
+    ///
+    /// assert_eq!(None, map.insert_if_absent(2, "Zwei"));
+    ///
+    /// let new_map = map.insert_if_absent(1, "One").unwrap();
+    ///
+    /// assert_eq!(Some(&"One"), new_map.get(&1));
+    /// ```
+    pub fn insert_if_absent(&self, key: K, value: V) -> Option<TreeMap<K, V, P, C>>
+    {
+        tree::insert_if_absent(&self.root, (key, value), &self.cmp).map(|root|
+            self.with_root(Some(NodeRef::new(root)))
+        )
+    }
+
+    /// Find the map with given key, and if the key is found, udpate the value with the provided
+    /// function `f`, and return the new map. Returns `None` if the map already has the key.
+    ///
+    /// When the key is found in the map, function `f` is called, and the value is updated with
+    /// the return value of `f`.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert("Two".to_string(), 2).insert("Three".to_string(), 3);
+    ///
+    /// // returns `None` because the key "One" is not in the map
+    /// assert_eq!(None, map.update("One", |v| v+1));
+    ///
+    /// let map_1 = map.update("Two", |v| v+10).unwrap();
+    /// // the value is updated
+    /// assert_eq!(Some(&12), map_1.get("Two"));
+    /// ```
+    pub fn update<Q: ?Sized, F>(&self, key: &Q, f: F) -> Option<TreeMap<K, V, P, C>>
+        where K: Borrow<Q>, C: Compare<Q>, F: FnMut(&V) -> V
+    {
+        match self.root {
+            Some(ref root) =>
+                tree::update(root, key, f, &self.cmp).map(|new_root|
+                    self.with_root(Some(NodeRef::new(new_root)))
+                ),
+            None =>
+                None
+        }
+    }
+
+    /// Find the map with given key, and if the key is found, udpate the value with the provided
+    /// function `f`, and return the new map. If the key is not found, insert the key-value pair
+    /// to the map and return it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert("One", 1).insert("Three", 3);
+    ///
+    /// // The new pair ("Two", 2) is inserted
+    /// let map_1 = map.insert_or_update("Two", 2, |v| v + 10);
+    /// assert_eq!(Some(&2), map_1.get("Two"));
+    ///
+    /// // The ("Two", 2) pair is updated to ("Two", 2 + 10)
+    /// let map_2 = map_1.insert_or_update("Two", 2, |v| v + 10);
+    /// assert_eq!(Some(&12), map_2.get("Two"));
+    /// ```
+    pub fn insert_or_update<F>(&self, key: K, value: V, f: F) -> TreeMap<K, V, P, C>
+        where F: FnMut(&V) -> V
+    {
+        let root = tree::insert_or_update(&self.root, key, value, f, &self.cmp);
+        self.with_root(Some(NodeRef::new(root)))
+    }
+
+    /// Find the map with given key, and return the new map with the value altered by the provided
+    /// function `f`. This can insert, update or remove the key in a single descent.
+    ///
+    /// `f` is called with the value of the key, or `None` if the key is not found. If `f` returns
+    /// `Some`, the key is inserted or updated with the returned value. If `f` returns `None`, the
+    /// key is removed.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type. The key is converted to the key type with
+    /// `to_owned` only when it is inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert("One".to_string(), 1).insert("Two".to_string(), 2);
+    ///
+    /// let count = |v: Option<&usize>| Some(v.map_or(1, |v| v + 1));
+    /// let drop_two = |v: Option<&usize>| v.cloned().filter(|&v| v != 2);
+    ///
+    /// // the new pair ("Three", 1) is inserted
+    /// assert_eq!(Some(&1), map.alter("Three", count).get("Three"));
+    /// // the pair ("One", 1) is updated to ("One", 2)
+    /// assert_eq!(Some(&2), map.alter("One", count).get("One"));
+    /// // the pair ("Two", 2) is removed
+    /// assert_eq!(None, map.alter("Two", drop_two).get("Two"));
+    /// ```
+    pub fn alter<Q, F>(&self, key: &Q, f: F) -> TreeMap<K, V, P, C>
+        where K: Borrow<Q>, Q: ?Sized + ToOwned<Owned=K>, C: Compare<Q>,
+              F: FnOnce(Option<&V>) -> Option<V>
+    {
+        let path = tree::Path::new(&self.root, key, &self.cmp);
+        let found = path.found();
+        match (found, f(found.map(|p| &p.1))) {
+            (Some(p), Some(value)) =>
+                self.with_root(Some(NodeRef::new(path.replace((p.0.clone(), value))))),
+            (Some(_), None) =>
+                self.with_root(path.remove()),
+            (None, Some(value)) =>
+                self.with_root(Some(NodeRef::new(path.insert((key.to_owned(), value))))),
+            (None, None) =>
+                self.clone()
+        }
+    }
+
+    /// Gets the entry of the given key, which builds the modified copy of the map without
+    /// searching the key again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    /// use immutable_map::map::Entry;
+    ///
+    /// let map = TreeMap::new().insert("One", 1).insert("Two", 2);
+    ///
+    /// let new_map = match map.entry("Two") {
+    ///     Entry::Occupied(e) => e.remove(),
+    ///     Entry::Vacant(e) => e.insert(2)
+    /// };
+    /// assert_eq!(None, new_map.get("Two"));
+    ///
+    /// let new_map = map.entry("Three").or_insert(3);
+    /// assert_eq!(Some(&3), new_map.get("Three"));
+    /// ```
+    pub fn entry<'r>(&'r self, key: K) -> Entry<'r, K, V, P, C> {
+        let path = tree::Path::new(&self.root, &key, &self.cmp);
+        if path.found().is_some() {
+            Entry::Occupied(OccupiedEntry { map: self, path })
+        } else {
+            Entry::Vacant(VacantEntry { map: self, key, path })
+        }
+    }
+
+    /// Remove the smallest key-value pair from the map, and returns the modified copy.
+    ///
+    /// Returns `None` if the original map was empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let empty_map = TreeMap::new();
+    /// assert_eq!(None, empty_map.delete_min());
+    ///
+    /// let map = empty_map.insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// let (new_map, pair) = map.delete_min().unwrap();
+    ///
+    /// assert_eq!(None, new_map.get(&1));
+    /// assert_eq!((&1, &"One"), pair);
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn delete_min(&self) -> Option<(TreeMap<K, V, P, C>, (&K, &V))>
+    {
+        if let Some(ref root) = self.root {
+            let (new_root, v) = tree::delete_min(root);
+            Some((
+                self.with_root(new_root),
+                (&v.0, &v.1)
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Remove the largest key-value pair from the map, and returns the modified copy.
+    ///
+    /// Returns `None` if the original map was empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let empty_map = TreeMap::new();
+    /// assert_eq!(None, empty_map.delete_max());
+    ///
+    /// let map = empty_map.insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// let (new_map, pair) = map.delete_max().unwrap();
+    ///
+    /// assert_eq!(None, new_map.get(&3));
+    /// assert_eq!((&3, &"Three"), pair);
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn delete_max(&self) -> Option<(TreeMap<K, V, P, C>, (&K, &V))>
+    {
+        if let Some(ref root) = self.root {
+            let (new_root, v) = tree::delete_max(root);
+            Some((
+                self.with_root(new_root),
+                (&v.0, &v.1)
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Remove the key from the map
+    ///
+    /// Returns `None` if the original map did not contain the key
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let empty_map = TreeMap::new();
+    /// assert_eq!(None, empty_map.remove(&2));
+    ///
+    /// let map = empty_map.insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// let (new_map, pair) = map.remove(&2).unwrap();
+    ///
+    /// assert_eq!(None, new_map.get(&2));
+    /// assert_eq!(&"Two", pair);
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn remove<Q: ?Sized>(&self, key: &Q) -> Option<(TreeMap<K, V, P, C>, &V)>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        tree::remove(&self.root, key, &self.cmp).map(|(new_root, v)|
+            (self.with_root(new_root), &v.1)
+        )
+    }
+
+    /// Removes the key from the map in place, and returns the removed value.
+    ///
+    /// Returns `None` if the map did not contain the key.
+    ///
+    /// The nodes which are not shared with other maps are modified in place, and only the shared
+    /// nodes are copied. Other maps sharing the nodes with this map are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let mut map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    /// let old_map = map.clone();
+    ///
+    /// assert_eq!(Some("Two"), map.remove_mut(&2));
+    /// assert_eq!(None, map.remove_mut(&2));
+    ///
+    /// assert_eq!(None, map.get(&2));
+    /// assert_eq!(Some(&"Two"), old_map.get(&2));
+    /// ```
+    pub fn remove_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        if !self.contains_key(key) {
+            return None;
+        }
+        tree::remove_mut(&mut self.root, key, &self.cmp).map(|p| p.1)
+    }
+
+    /// Removes the key-value pair at the given position in the key order, and returns the
+    /// modified copy and the removed pair. This operation takes O(log n) time.
+    ///
+    /// Returns `None` if the index is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// let (new_map, pair) = map.remove_index(1).unwrap();
+    ///
+    /// assert_eq!(None, new_map.get(&2));
+    /// assert_eq!((&2, &"Two"), pair);
+    /// assert_eq!(None, map.remove_index(3));
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn remove_index(&self, index: usize) -> Option<(TreeMap<K, V, P, C>, (&K, &V))>
+    {
+        tree::remove_index(&self.root, index).map(|(new_root, v)|
+            (self.with_root(new_root), (&v.0, &v.1))
+        )
+    }
+
+    /// Splits the map at the given position. Returns the map with the first `index` pairs in
+    /// the key order, and the map with the rest. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// let (left, right) = map.split_at(1);
+    ///
+    /// assert_eq!(vec![&1], left.keys().collect::<Vec<_>>());
+    /// assert_eq!(vec![&2, &3], right.keys().collect::<Vec<_>>());
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn split_at(&self, index: usize) -> (TreeMap<K, V, P, C>, TreeMap<K, V, P, C>)
+    {
+        let (left, right) = tree::split_at(&self.root, index);
+        (self.with_root(left), self.with_root(right))
+    }
+
+    /// Returns the map with the first `n` pairs in the key order. This operation takes
+    /// O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// assert_eq!(vec![&1, &2], map.take(2).keys().collect::<Vec<_>>());
+    /// ```
+    pub fn take(&self, n: usize) -> TreeMap<K, V, P, C>
+    {
+        self.split_at(n).0
+    }
+
+    /// Returns the map without the first `n` pairs in the key order. This operation takes
+    /// O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+    ///
+    /// assert_eq!(vec![&3], map.skip(2).keys().collect::<Vec<_>>());
+    /// ```
+    pub fn skip(&self, n: usize) -> TreeMap<K, V, P, C>
+    {
+        self.split_at(n).1
+    }
+
+    /// Splits the map at the given key. Returns the map with the keys smaller than `key`, the
+    /// key-value pair matching `key` if any, and the map with the keys larger than `key`.
+    ///
+    /// This operation takes O(log n) time, and the resulting maps share most of their nodes
+    /// with the original map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
+    /// form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let map = TreeMap::new().insert(1, "One").insert(2, "Two").insert(3, "Three");
+    ///
+    /// let (left, pair, right) = map.split(&2);
+    ///
+    /// assert_eq!(vec![(&1, &"One")], left.iter().collect::<Vec<_>>());
+    /// assert_eq!(Some((&2, &"Two")), pair);
+    /// assert_eq!(vec![(&3, &"Three")], right.iter().collect::<Vec<_>>());
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn split<Q: ?Sized>(&self, key: &Q)
+            -> (TreeMap<K, V, P, C>, Option<(&K, &V)>, TreeMap<K, V, P, C>)
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        let (left, found, right) = tree::split(&self.root, key, &self.cmp);
+        (
+            self.with_root(left),
+            found.map(|p| (&p.0, &p.1)),
+            self.with_root(right)
+        )
+    }
+
+    /// Joins the two maps with the key-value pair in the middle, and returns the new map.
+    ///
+    /// This operation takes O(log n) time, and the resulting map shares most of its nodes
+    /// with `left` and `right`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any key of `left` is not smaller than `key`, or any key of `right` is not
+    /// larger than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let left = TreeMap::new().insert(1, "One");
+    /// let right = TreeMap::new().insert(3, "Three").insert(4, "Four");
+    ///
+    /// let map = TreeMap::join(&left, 2, "Two", &right);
+    ///
+    /// let keys: Vec<_> = map.keys().cloned().collect();
+    /// assert_eq!(keys, [1, 2, 3, 4]);
+    /// ```
+    pub fn join(left: &TreeMap<K, V, P, C>, key: K, value: V, right: &TreeMap<K, V, P, C>)
+            -> TreeMap<K, V, P, C>
+    {
+        if let Some(ref l) = left.root {
+            assert!(left.cmp.compare(&tree::find_max(l).0, &key) == Ordering::Less,
+                    "left map has a key not smaller than the key");
+        }
+        if let Some(ref r) = right.root {
+            assert!(left.cmp.compare(&key, &tree::find_min(r).0) == Ordering::Less,
+                    "right map has a key not larger than the key");
+        }
+        left.with_root(Some(NodeRef::new(tree::join((key, value), &left.root, &right.root))))
+    }
+
+    /// Returns the union of the two maps. When a key is found in both maps, the value is
+    /// resolved with the function `f`, which is called with the values from `self` and `other`
+    /// in that order.
+    ///
+    /// The resulting map shares the subtrees which only contain the keys found in one of the
+    /// maps with the original maps.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let a = TreeMap::new().insert(1, 1).insert(2, 2);
+    /// let b = TreeMap::new().insert(2, 20).insert(3, 30);
+    ///
+    /// let union = a.union_with(&b, |x, y| x + y);
+    ///
+    /// let pairs: Vec<_> = union.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(1, 1), (2, 22), (3, 30)]);
+    /// ```
+    pub fn union_with<F>(&self, other: &TreeMap<K, V, P, C>, mut f: F) -> TreeMap<K, V, P, C>
+        where F: FnMut(&V, &V) -> V
+    {
+        self.merge_with_key(other, |_, a, b| Some(f(a, b)), |m| m, |m| m)
+    }
+
+    /// Returns the intersection of the two maps. The value of the new map is the return value of
+    /// `f`, which is called with the values from `self` and `other` in that order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let a = TreeMap::new().insert(1, "One").insert(2, "Two");
+    /// let b = TreeMap::new().insert(2, 2).insert(3, 3);
+    ///
+    /// let intersection = a.intersection_with(&b, |s, n| format!("{}: {}", s, n));
+    ///
+    /// let pairs: Vec<_> = intersection.iter().map(|(&k, v)| (k, v.as_str())).collect();
+    /// assert_eq!(pairs, [(2, "Two: 2")]);
+    /// ```
+    pub fn intersection_with<W, X, F>(&self, other: &TreeMap<K, W, P, C>, mut f: F)
+            -> TreeMap<K, X, P, C>
+        where W: Clone, X: Clone, F: FnMut(&V, &W) -> X
+    {
+        self.merge_with_key(other,
+                            |_, a, b| Some(f(a, b)),
+                            |_| self.with_root(None),
+                            |_| self.with_root(None))
+    }
+
+    /// Returns the difference of the two maps. When a key is found in both maps, `f` is called
+    /// with the values from `self` and `other` in that order. If `f` returns `None`, the key is
+    /// removed from the new map. Otherwise, the value is replaced with the return value.
+    ///
+    /// The resulting map shares the subtrees which only contain the keys not found in `other`
+    /// with `self`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMap;
+    ///
+    /// let a = TreeMap::new().insert(1, 1).insert(2, 2).insert(3, 3);
+    /// let b = TreeMap::new().insert(2, true).insert(3, false);
     ///
-    /// let pairs: Vec<_> = map.range(Included(&4), Included(&8)).map(|(k, v)| (*k, *v)).collect();
+    /// let difference = a.difference_with(&b, |&x, &keep| if keep { Some(x * 10) } else { None });
     ///
-    /// assert_eq!(pairs, [(5, "Five"), (8, "Eight")]);
+    /// let pairs: Vec<_> = difference.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(1, 1), (2, 20)]);
     /// ```
-    pub fn range<'r, Q: Ord>(&'r self, min: Bound<&Q>, max: Bound<&Q>) -> TreeMapRange<'r, K, V>
-        where K: Borrow<Q>
+    pub fn difference_with<W, F>(&self, other: &TreeMap<K, W, P, C>, mut f: F)
+            -> TreeMap<K, V, P, C>
+        where W: Clone, F: FnMut(&V, &W) -> Option<V>
     {
-        tree::Range::new(&self.root, min, max)
+        self.merge_with_key(other, |_, a, b| f(a, b), |m| m, |_| self.with_root(None))
     }
-}
 
-impl<K, V> TreeMap<K, V> where K: Clone + Ord, V: Clone {
-    /// Return a new copy of `TreeMap` with the key-value pair inserted
+    /// Combines the two maps with the given functions. This is the general form of
+    /// `union_with`, `intersection_with` and `difference_with`.
     ///
-    /// If the map already has the key, the key-value pair is replaced in the new map
+    /// When a key is found in both maps, `f` is called with the key and the values from `self`
+    /// and `other`. If `f` returns `None`, the key is removed from the new map. Otherwise, the
+    /// value is replaced with the return value.
+    ///
+    /// `only_self` and `only_other` are called with the submaps which only contain the keys found
+    /// in `self` or `other`, respectively. They must return a map with the subset of the keys of
+    /// the given map, such as the map itself or an empty map. Returning the given map as-is lets
+    /// the new map share its subtrees.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `only_self` or `only_other` returns a map with a key not found in the given map,
+    /// as such a key could break the order of the new map. Returning the given map itself is
+    /// checked in O(1) time, and any other map in time linear to the size of the given map.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeMap;
     ///
-    /// let map = TreeMap::new();
-    ///
-    /// assert_eq!(false, map.contains_key(&1));
-    /// assert_eq!(None, map.get(&1));
+    /// let a = TreeMap::new().insert(1, 1).insert(2, 2).insert(3, 3);
+    /// let b = TreeMap::new().insert(2, 20).insert(3, 30).insert(4, 40);
     ///
-    /// let new_map = map.insert(1, "One");
+    /// // keeps the keys only found in `other`, and the keys found in both maps where the sum of
+    /// // the values is even
+    /// let merged = a.merge_with_key(&b,
+    ///                               |_, x, y| if (x + y) % 2 == 0 { Some(x + y) } else { None },
+    ///                               |_| TreeMap::new(),
+    ///                               |m| m);
     ///
-    /// assert_eq!(true, new_map.contains_key(&1));
-    /// assert_eq!(Some(&"One"), new_map.get(&1));
+    /// let pairs: Vec<_> = merged.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(2, 22), (4, 40)]);
     /// ```
-    pub fn insert(&self, key: K, value: V) -> TreeMap<K, V>
+    pub fn merge_with_key<W, X, F, G1, G2>(&self,
+                                           other: &TreeMap<K, W, P, C>,
+                                           mut f: F,
+                                           mut only_self: G1,
+                                           mut only_other: G2) -> TreeMap<K, X, P, C>
+        where W: Clone, X: Clone,
+              F: FnMut(&K, &V, &W) -> Option<X>,
+              G1: FnMut(TreeMap<K, V, P, C>) -> TreeMap<K, X, P, C>,
+              G2: FnMut(TreeMap<K, W, P, C>) -> TreeMap<K, X, P, C>
     {
-        let root = tree::insert(&self.root, (key, value));
-        TreeMap { root: Some(Rc::new(root)) }
+        let root = tree::merge_with_key(
+            &self.root,
+            &other.root,
+            &mut f,
+            &mut |node: &Option<NodeRef<K, V, P>>| only_self(self.with_root(node.clone())).root,
+            &mut |node: &Option<NodeRef<K, W, P>>| only_other(self.with_root(node.clone())).root,
+            &self.cmp
+        );
+        self.with_root(root)
     }
+}
 
-    /// Return a new copy of `TreeMap` with the key-value pair inserted.
-    ///
-    /// Returns `None` if the map already has the key
+impl<K, V, P: PointerKind, C> TreeMap<K, V, P, C> where K: Clone, C: Clone {
+    /// Returns the map with the values mapped by `f`. The new map has exactly the same shape as
+    /// this map, so no keys are compared. `f` is called in the key order.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeMap;
     ///
-    /// let map = TreeMap::new().insert(2, "Two").insert(3, "Three");
-    ///
-    /// assert_eq!(None, map.insert_if_absent(2, "Zwei"));
+    /// let map = TreeMap::new().insert(1, "One").insert(2, "Two");
     ///
-    /// let new_map = map.insert_if_absent(1, "One").unwrap();
+    /// let lengths = map.map_values(|v| v.len());
     ///
-    /// assert_eq!(Some(&"One"), new_map.get(&1));
+    /// let pairs: Vec<_> = lengths.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(1, 3), (2, 3)]);
     /// ```
-    pub fn insert_if_absent(&self, key: K, value: V) -> Option<TreeMap<K, V>>
+    pub fn map_values<W, F>(&self, mut f: F) -> TreeMap<K, W, P, C>
+        where F: FnMut(&V) -> W
     {
-        tree::insert_if_absent(&self.root, (key, value)).map(|root|
-            TreeMap { root: Some(Rc::new(root)) }
-        )
+        self.with_root(tree::map(&self.root, &mut |_, v| f(v)))
     }
 
-    /// Find the map with given key, and if the key is found, udpate the value with the provided
-    /// function `f`, and return the new map. Returns `None` if the map already has the key.
-    ///
-    /// When the key is found in the map, function `f` is called, and the value is updated with
-    /// the return value of `f`.
-    ///
-    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
-    /// form must match the ordering on the key type.
+    /// Returns the map with the values mapped by `f`, which is called with the key and the
+    /// value. The new map has exactly the same shape as this map. `f` is called in the key order.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeMap;
     ///
-    /// let map = TreeMap::new().insert("Two".to_string(), 2).insert("Three".to_string(), 3);
+    /// let map = TreeMap::new().insert(1, 10).insert(2, 20);
     ///
-    /// // returns `None` because the key "One" is not in the map
-    /// assert_eq!(None, map.update("One", |v| v+1));
+    /// let sums = map.map_with_key(|k, v| k + v);
     ///
-    /// let map_1 = map.update("Two", |v| v+10).unwrap();
-    /// // the value is updated
-    /// assert_eq!(Some(&12), map_1.get("Two"));
+    /// let pairs: Vec<_> = sums.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(1, 11), (2, 22)]);
     /// ```
-    pub fn update<Q: ?Sized + Ord, F>(&self, key: &Q, f: F) -> Option<TreeMap<K, V>>
-        where K: Borrow<Q>, F: FnMut(&V) -> V
+    pub fn map_with_key<W, F>(&self, mut f: F) -> TreeMap<K, W, P, C>
+        where F: FnMut(&K, &V) -> W
     {
-        match self.root {
-            Some(ref root) =>
-                tree::update(root, key, f).map(|new_root|
-                    TreeMap { root: Some(Rc::new(new_root)) }
-                ),
-            None =>
-                None
-        }
+        self.with_root(tree::map(&self.root, &mut f))
     }
 
-    /// Find the map with given key, and if the key is found, udpate the value with the provided
-    /// function `f`, and return the new map. If the key is not found, insert the key-value pair
-    /// to the map and return it.
+    /// Returns the map with the values mapped by `f`, without the pairs for which `f` returns
+    /// `None`. `f` is called in the key order. This operation takes O(n) time.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeMap;
     ///
-    /// let map = TreeMap::new().insert("One", 1).insert("Three", 3);
+    /// let map = TreeMap::new().insert(1, "1").insert(2, "Two").insert(3, "3");
     ///
-    /// // The new pair ("Two", 2) is inserted
-    /// let map_1 = map.insert_or_update("Two", 2, |v| v + 10);
-    /// assert_eq!(Some(&2), map_1.get("Two"));
+    /// let numbers = map.filter_map(|_, v| v.parse::<i32>().ok());
     ///
-    /// // The ("Two", 2) pair is updated to ("Two", 2 + 10)
-    /// let map_2 = map_1.insert_or_update("Two", 2, |v| v + 10);
-    /// assert_eq!(Some(&12), map_2.get("Two"));
+    /// let pairs: Vec<_> = numbers.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(1, 1), (3, 3)]);
     /// ```
-    pub fn insert_or_update<F>(&self, key: K, value: V, f: F) -> TreeMap<K, V>
-        where F: FnMut(&V) -> V
+    pub fn filter_map<W, F>(&self, mut f: F) -> TreeMap<K, W, P, C>
+        where W: Clone, F: FnMut(&K, &V) -> Option<W>
     {
-        TreeMap { root: Some(Rc::new(tree::insert_or_update(&self.root, key, value, f))) }
+        self.with_root(tree::filter_map(&self.root, &mut f))
     }
 
-    /// Remove the smallest key-value pair from the map, and returns the modified copy.
-    ///
-    /// Returns `None` if the original map was empty.
+    /// Returns the map with only the pairs for which `f` returns true. The subtrees which keep
+    /// all of their pairs are shared with this map. `f` is called in the key order. This
+    /// operation takes O(n) time.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeMap;
     ///
-    /// let empty_map = TreeMap::new();
-    /// assert_eq!(None, empty_map.delete_min());
-    ///
-    /// let map = empty_map.insert(2, "Two").insert(3, "Three").insert(1, "One");
+    /// let map: TreeMap<_, _> = (0..10).map(|i| (i, i * i)).collect();
     ///
-    /// let (new_map, pair) = map.delete_min().unwrap();
+    /// let even = map.filter(|&k, _| k % 2 == 0);
     ///
-    /// assert_eq!(None, new_map.get(&1));
-    /// assert_eq!((&1, &"One"), pair);
+    /// assert_eq!(vec![&0, &4, &16, &36, &64], even.values().collect::<Vec<_>>());
     /// ```
-    pub fn delete_min(&self) -> Option<(TreeMap<K, V>, (&K, &V))>
+    pub fn filter<F>(&self, mut f: F) -> TreeMap<K, V, P, C>
+        where V: Clone, F: FnMut(&K, &V) -> bool
     {
-        if let Some(ref root) = self.root {
-            let (new_root, v) = tree::delete_min(&root);
-            Some((
-                TreeMap { root: new_root },
-                (&v.0, &v.1)
-            ))
-        } else {
-            None
-        }
+        self.with_root(tree::filter(&self.root, &mut f))
     }
 
-    /// Remove the largest key-value pair from the map, and returns the modified copy.
-    ///
-    /// Returns `None` if the original map was empty.
+    /// Splits the map into the map with the pairs for which `f` returns true, and the map with
+    /// the rest. The subtrees which go entirely to one side are shared with this map. `f` is
+    /// called in the key order. This operation takes O(n) time.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeMap;
     ///
-    /// let empty_map = TreeMap::new();
-    /// assert_eq!(None, empty_map.delete_max());
-    ///
-    /// let map = empty_map.insert(2, "Two").insert(3, "Three").insert(1, "One");
+    /// let map: TreeMap<_, _> = (0..6).map(|i| (i, i * i)).collect();
     ///
-    /// let (new_map, pair) = map.delete_max().unwrap();
+    /// let (small, large) = map.partition(|_, &v| v < 10);
     ///
-    /// assert_eq!(None, new_map.get(&3));
-    /// assert_eq!((&3, &"Three"), pair);
+    /// assert_eq!(vec![&0, &1, &2, &3], small.keys().collect::<Vec<_>>());
+    /// assert_eq!(vec![&4, &5], large.keys().collect::<Vec<_>>());
     /// ```
-    pub fn delete_max(&self) -> Option<(TreeMap<K, V>, (&K, &V))>
+    #[allow(clippy::type_complexity)]
+    pub fn partition<F>(&self, mut f: F) -> (TreeMap<K, V, P, C>, TreeMap<K, V, P, C>)
+        where V: Clone, F: FnMut(&K, &V) -> bool
     {
-        if let Some(ref root) = self.root {
-            let (new_root, v) = tree::delete_max(&root);
-            Some((
-                TreeMap { root: new_root },
-                (&v.0, &v.1)
-            ))
-        } else {
-            None
-        }
+        let (left, right) = tree::partition(&self.root, &mut f);
+        (self.with_root(left), self.with_root(right))
     }
 
-    /// Remove the key from the map
-    ///
-    /// Returns `None` if the original map did not contain the key
-    ///
-    /// The key may be any borrowed form of the map's key type, but the ordering on the borrowed
-    /// form must match the ordering on the key type.
+    /// Removes the pairs for which `f` returns false from the map in place. The nodes not shared
+    /// with other maps are reused, and the shared ones are copied only where pairs are removed
+    /// from them. `f` is called in the key order. This operation takes O(n) time.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeMap;
     ///
-    /// let empty_map = TreeMap::new();
-    /// assert_eq!(None, empty_map.remove(&2));
-    ///
-    /// let map = empty_map.insert(2, "Two").insert(3, "Three").insert(1, "One");
+    /// let mut map: TreeMap<_, _> = (0..10).map(|i| (i, i)).collect();
     ///
-    /// let (new_map, pair) = map.remove(&2).unwrap();
+    /// map.retain(|_, &v| v >= 7);
     ///
-    /// assert_eq!(None, new_map.get(&2));
-    /// assert_eq!(&"Two", pair);
+    /// assert_eq!(vec![&7, &8, &9], map.keys().collect::<Vec<_>>());
     /// ```
-    pub fn remove<Q: ?Sized + Ord>(&self, key: &Q) -> Option<(TreeMap<K, V>, &V)>
-        where K: Borrow<Q>
+    pub fn retain<F>(&mut self, mut f: F)
+        where V: Clone, F: FnMut(&K, &V) -> bool
     {
-        tree::remove(&self.root, key).map(|(new_root, v)|
-            (TreeMap { root: new_root }, &v.1)
-        )
+        tree::filter_mut(&mut self.root, &mut f);
+    }
+}
+
+impl<K, V, P: PointerKind, C: Clone> Clone for TreeMap<K, V, P, C> {
+    fn clone(&self) -> TreeMap<K, V, P, C> {
+        TreeMap { root: self.root.clone(), cmp: self.cmp.clone() }
+    }
+}
+
+impl<K, V, P: PointerKind, C: Default> Default for TreeMap<K, V, P, C> {
+    fn default() -> TreeMap<K, V, P, C> {
+        TreeMap { root: None, cmp: C::default() }
     }
 }
 
-impl<K: Debug + Ord, V: Debug> Debug for TreeMap<K, V> {
+impl<K: Debug, V: Debug, P: PointerKind, C> Debug for TreeMap<K, V, P, C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl<'r, K: Ord, V> IntoIterator for &'r TreeMap<K, V> {
+impl<'r, K, V, P: PointerKind, C> IntoIterator for &'r TreeMap<K, V, P, C> {
     type Item = (&'r K, &'r V);
-    type IntoIter = TreeMapIter<'r, K, V>;
+    type IntoIter = TreeMapIter<'r, K, V, P>;
 
-    fn into_iter(self) -> TreeMapIter<'r, K, V> {
+    fn into_iter(self) -> TreeMapIter<'r, K, V, P> {
         self.iter()
     }
 }
 
-impl<K: PartialEq, V: PartialEq> PartialEq for TreeMap<K, V> {
-    fn eq(&self, other: &TreeMap<K, V>) -> bool {
+/// Consumes the map and yields the key-value pairs in the key order.
+///
+/// The nodes which are not shared with other maps are moved out without cloning the keys and
+/// values, and only the shared nodes are cloned.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::TreeMap;
+///
+/// let map = TreeMap::new().insert(2, "Two").insert(3, "Three").insert(1, "One");
+///
+/// let mut iter = map.into_iter();
+/// assert_eq!(Some((1, "One")), iter.next());
+/// assert_eq!(Some((3, "Three")), iter.next_back());
+/// assert_eq!(1, iter.len());
+/// assert_eq!(Some((2, "Two")), iter.next());
+/// assert_eq!(None, iter.next());
+/// ```
+impl<K: Clone, V: Clone, P: PointerKind, C> IntoIterator for TreeMap<K, V, P, C> {
+    type Item = (K, V);
+    type IntoIter = TreeMapIntoIter<K, V, P>;
+
+    fn into_iter(self) -> TreeMapIntoIter<K, V, P> {
+        tree::IntoIter::new(self.root)
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, P: PointerKind, C> PartialEq for TreeMap<K, V, P, C> {
+    fn eq(&self, other: &TreeMap<K, V, P, C>) -> bool {
         self.len() == other.len()
             && self.iter().zip(other.iter()).all(|(a, b)| a == b)
     }
 }
 
-impl<K: Eq, V: Eq> Eq for TreeMap<K, V> {}
+impl<K: Eq, V: Eq, P: PointerKind, C> Eq for TreeMap<K, V, P, C> {}
 
-impl <K: PartialOrd, V: PartialOrd> PartialOrd for TreeMap<K, V> {
-    fn partial_cmp(&self, other: &TreeMap<K, V>) -> Option<Ordering> {
+impl <K: PartialOrd, V: PartialOrd, P: PointerKind, C> PartialOrd for TreeMap<K, V, P, C> {
+    fn partial_cmp(&self, other: &TreeMap<K, V, P, C>) -> Option<Ordering> {
         self.iter().partial_cmp(other.iter())
     }
 }
 
-impl <K: Ord, V: Ord> Ord for TreeMap<K, V> {
-    fn cmp(&self, other: &TreeMap<K, V>) -> Ordering {
+impl <K: Ord, V: Ord, P: PointerKind, C> Ord for TreeMap<K, V, P, C> {
+    fn cmp(&self, other: &TreeMap<K, V, P, C>) -> Ordering {
         self.iter().cmp(other.iter())
     }
 }
 
-impl <'a, K: Ord, Q: ?Sized, V> Index<&'a Q> for TreeMap<K, V>
-    where K: Borrow<Q>, Q: Ord
+impl<K: Hash, V: Hash, P: PointerKind, C> Hash for TreeMap<K, V, P, C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for elem in self.iter() {
+            elem.hash(state);
+        }
+    }
+}
+
+impl <K, Q: ?Sized, V, P: PointerKind, C> Index<&Q> for TreeMap<K, V, P, C>
+    where K: Borrow<Q>, C: Compare<K> + Compare<Q>
 {
     type Output = V;
 
@@ -498,21 +1872,146 @@ impl <'a, K: Ord, Q: ?Sized, V> Index<&'a Q> for TreeMap<K, V>
     }
 }
 
-impl <K: Ord + Clone, V: Clone> FromIterator<(K, V)> for TreeMap<K, V> {
-    fn from_iter<T>(iter: T) -> TreeMap<K, V> where T: IntoIterator<Item=(K, V)> {
-        let mut m = TreeMap::new();
-        for (k, v) in iter {
-            m = m.insert(k, v);
+/// Builds the map in O(n) time if the pairs are sorted by key, and by inserting them one by one
+/// otherwise. The pairs are collected into a `Vec` first to check their order, so this takes
+/// O(n) extra memory while building.
+impl <K, V, P: PointerKind, C> FromIterator<(K, V)> for TreeMap<K, V, P, C>
+    where K: Clone, V: Clone, C: Compare<K> + Clone + Default
+{
+    fn from_iter<T>(iter: T) -> TreeMap<K, V, P, C> where T: IntoIterator<Item=(K, V)> {
+        let cmp = C::default();
+        let mut elems: Vec<(K, V)> = iter.into_iter().collect();
+        if tree::is_sorted(&elems, &cmp) {
+            tree::dedup_sorted(&mut elems, &cmp);
+            return TreeMap { root: tree::from_sorted_vec(elems), cmp };
+        }
+
+        let mut m = TreeMap { root: None, cmp };
+        for (k, v) in elems {
+            m.insert_mut(k, v);
         }
         m
     }
 }
 
+/// A builder which builds a `TreeMap` by inserting and removing the keys in place.
+///
+/// Building a map with `TreeMap::insert` allocates a new path of nodes for every key. The builder
+/// owns the map being built, so the nodes are modified in place and most insertions allocate only
+/// the new node. `freeze` returns the built map.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::map::TreeMapBuilder;
+///
+/// let mut builder = TreeMapBuilder::new();
+/// for i in 0..100 {
+///     builder.insert(i, i * i);
+/// }
+/// builder.remove(&50);
+///
+/// let map = builder.freeze();
+/// assert_eq!(99, map.len());
+/// assert_eq!(Some(&49), map.get(&7));
+/// assert_eq!(None, map.get(&50));
+/// ```
+pub struct TreeMapBuilder<K, V, P: PointerKind = RcKind, C = Natural> {
+    map: TreeMap<K, V, P, C>
+}
+
+impl<K, V> TreeMapBuilder<K, V> {
+    /// Makes a new builder with an empty map
+    ///
+    /// As with `TreeMap::new`, the builders of the maps shared with `Arc` are made with
+    /// `Default`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::ArcTreeMap;
+    /// use immutable_map::map::TreeMapBuilder;
+    /// use immutable_map::pointer::ArcKind;
+    ///
+    /// let mut builder: TreeMapBuilder<_, _, ArcKind> = TreeMapBuilder::default();
+    /// builder.insert(1, "One");
+    ///
+    /// let map: ArcTreeMap<_, _> = builder.freeze();
+    /// assert_eq!(Some(&"One"), map.get(&1));
+    /// ```
+    pub fn new() -> TreeMapBuilder<K, V> {
+        TreeMapBuilder { map: TreeMap::new() }
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMapBuilder<K, V, P, C> {
+    /// Returns the number of elements in the map being built.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the map being built contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the built map.
+    pub fn freeze(self) -> TreeMap<K, V, P, C> {
+        self.map
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMapBuilder<K, V, P, C>
+    where K: Clone, V: Clone, C: Compare<K> + Clone
+{
+    /// Inserts the key-value pair, and returns the replaced value if the map already had the key.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.map.insert_mut(key, value)
+    }
+
+    /// Removes the key, and returns the removed value if the map had the key.
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        self.map.remove_mut(key)
+    }
+}
+
+impl<K, V, P: PointerKind, C: Default> Default for TreeMapBuilder<K, V, P, C> {
+    fn default() -> TreeMapBuilder<K, V, P, C> {
+        TreeMapBuilder { map: TreeMap { root: None, cmp: C::default() } }
+    }
+}
+
+impl<K, V, P: PointerKind, C> From<TreeMap<K, V, P, C>> for TreeMapBuilder<K, V, P, C> {
+    fn from(map: TreeMap<K, V, P, C>) -> TreeMapBuilder<K, V, P, C> {
+        TreeMapBuilder { map }
+    }
+}
+
+impl<K, V, P: PointerKind, C> Extend<(K, V)> for TreeMapBuilder<K, V, P, C>
+    where K: Clone, V: Clone, C: Compare<K> + Clone
+{
+    fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item=(K, V)> {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::cell::Cell;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::ptr;
+    use std::rc::Rc;
+    use std::thread;
+
+    use pointer::RcKind;
     use tree::balanced;
 
-    use super::TreeMap;
+    use super::{TreeMap, ArcTreeMap, DiffItem};
     use Bound;
 
     #[test]
@@ -706,22 +2205,240 @@ mod test {
                              .map(|(&k, &v)| (k, v))
                              .collect();
 
-        assert_eq!(expected, res);
+        assert_eq!(expected, res);
+    }
+
+    #[test]
+    fn test_debug() {
+        let r0 = TreeMap::new();
+        let r1 = r0.insert(7, 'g');
+        let r2 = r1.insert(4, 'd');
+
+        assert_eq!("{4: 'd', 7: 'g'}", &format!("{:?}", r2));
+    }
+
+    #[test]
+    fn test_split_join() {
+        let map: TreeMap<usize, char> = (0..20).map(|i| (i, 'x')).collect();
+
+        let (left, pair, right) = map.split(&7);
+        assert_eq!(Some((&7, &'x')), pair);
+        assert_eq!(7, left.len());
+        assert_eq!(12, right.len());
+        assert!(balanced(&left.root));
+        assert!(balanced(&right.root));
+
+        let joined = TreeMap::join(&left, 7, 'y', &right);
+        assert_eq!(20, joined.len());
+        assert_eq!(Some(&'y'), joined.get(&7));
+        assert!(balanced(&joined.root));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_sorted_iter_unordered() {
+        TreeMap::<_, _>::from_sorted_iter(vec![(1, 'a'), (3, 'c'), (2, 'b')]);
+    }
+
+    #[test]
+    fn test_alter_borrowed_key() {
+        let map = TreeMap::new().insert("b".to_string(), 2);
+
+        let removed = map.alter("b", |_| None);
+        let kept = map.alter("a", |_| None);
+        let inserted = map.alter("a", |_| Some(1));
+
+        assert!(removed.is_empty());
+        assert_eq!(map, kept);
+        assert_eq!(vec![("a", 1), ("b", 2)],
+                   inserted.iter().map(|(k, &v)| (k.as_str(), v)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_sorted_iter_by_unordered() {
+        let rev = |a: &i32, b: &i32| b.cmp(a);
+        let _: TreeMap<_, _, RcKind, _> =
+            TreeMap::from_sorted_iter_by(vec![(1, 'a'), (2, 'b')], rev);
+    }
+
+    #[test]
+    fn test_from_sorted_iter_by() {
+        let rev = |a: &i32, b: &i32| b.cmp(a);
+        let map: TreeMap<_, _, RcKind, _> =
+            TreeMap::from_sorted_iter_by((0..100).rev().map(|x| (x / 2, x)), rev);
+
+        assert_eq!(50, map.len());
+        assert_eq!(Some(&20), map.get(&10));
+        assert!(balanced(&map.root));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_join_unordered() {
+        let left = TreeMap::new().insert(1, 'a').insert(5, 'e');
+        let right = TreeMap::new().insert(7, 'g');
+
+        TreeMap::join(&left, 3, 'c', &right);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merge_with_key_substituted_key() {
+        let a = TreeMap::new().insert(2, 'b');
+        let b = TreeMap::new().insert(1, 'a').insert(3, 'c');
+
+        a.merge_with_key(&b, |_, _, _| None, |_| TreeMap::new().insert(4, 'd'), |m| m);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merge_with_key_out_of_bounds() {
+        let a = TreeMap::new().insert(2, 'b');
+        let b = TreeMap::new().insert(1, 'a').insert(3, 'c');
+
+        a.merge_with_key(&b, |_, _, _| None, |m| m, |m| m.insert(0, 'z'));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_merge_with_key_invented_key() {
+        let a = TreeMap::new().insert(1, 'a').insert(5, 'e');
+        let b = TreeMap::new().insert(7, 'g');
+
+        a.merge_with_key(&b, |_, _, _| None, |m| m.insert(3, 'c'), |m| m);
+    }
+
+    #[test]
+    fn test_merge_with_key_whole_submaps() {
+        let a: TreeMap<_, _> = (0..100).map(|x| (x, x)).collect();
+        let b = TreeMap::new().insert(30, 0).insert(60, 0);
+        let mut calls = 0;
+
+        let merged = a.merge_with_key(&b, |_, _, _| None, |m| { calls += 1; m }, |m| m);
+
+        assert_eq!(3, calls);
+        assert_eq!(98, merged.len());
+        assert!(balanced(&merged.root));
+    }
+
+    #[test]
+    fn test_arc_threads() {
+        let map: ArcTreeMap<usize, usize> = (0..100).map(|x| (x, x)).collect();
+
+        let handles: Vec<_> = (0..4).map(|i| {
+            let m = map.clone();
+            thread::spawn(move || m.insert_or_update(i, 0, |v| v + 100))
+        }).collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            let m = handle.join().unwrap();
+            assert_eq!(Some(&(i + 100)), m.get(&i));
+            assert!(balanced(&m.root));
+        }
+        assert_eq!(100, map.len());
+        assert_eq!(Some(&0), map.get(&0));
+    }
+
+    #[test]
+    fn test_into_iter_moves_unshared() {
+        let map: TreeMap<usize, Rc<usize>> = (0..100).map(|x| (x, Rc::new(x))).collect();
+        let shared = map.clone();
+
+        // every node is shared with `shared`, so the values are cloned
+        for (i, (k, v)) in map.into_iter().enumerate() {
+            assert_eq!((i, i), (k, *v));
+            assert_eq!(2, Rc::strong_count(&v));
+        }
+        // `shared` is the only owner of the nodes now, so the values are moved out
+        for (i, (k, v)) in shared.into_iter().enumerate() {
+            assert_eq!((i, i), (k, *v));
+            assert_eq!(1, Rc::strong_count(&v));
+        }
+    }
+
+    #[test]
+    fn test_diff_skips_shared_nodes() {
+        let count = Cell::new(0);
+        let cmp = |a: &usize, b: &usize| {
+            count.set(count.get() + 1);
+            a.cmp(b)
+        };
+        let mut old: TreeMap<_, _, RcKind, _> = TreeMap::with_comparator(cmp);
+        for i in 0..10000 {
+            old.insert_mut(i * 2, i);
+        }
+        let new = old.insert(501, 0).insert(8000, 0).remove(&9000).unwrap().0;
+
+        count.set(0);
+        let diff: Vec<_> = old.diff(&new).collect();
+        assert_eq!(diff, [
+            DiffItem::Added(&501, &0),
+            DiffItem::Changed(&8000, &4000, &0),
+            DiffItem::Removed(&9000, &4500)
+        ]);
+        assert!(count.get() < 1000);
+
+        assert_eq!(0, old.diff(&old.clone()).count());
+    }
+
+    #[test]
+    fn test_filter_shares_subtrees() {
+        let m: TreeMap<_, _> = (0..100).map(|i| (i, Rc::new(i))).collect();
+
+        // the values of the shared nodes are not cloned
+        let all = m.filter(|_, _| true);
+        assert!(all.values().all(|v| Rc::strong_count(v) == 1));
+
+        let (small, large) = m.partition(|&k, _| k < 99);
+        let shared = small.values().filter(|&v| Rc::strong_count(v) == 1).count();
+        assert!(shared >= 90);
+        assert_eq!(vec![&99], large.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_retain_reuses_unshared() {
+        let mut m: TreeMap<_, _> = (0..100).map(|i| (i, Rc::new(i))).collect();
+
+        // the unshared nodes keeping all of their elements are not reallocated
+        let root: *const _ = &**m.root.as_ref().unwrap();
+        m.retain(|_, _| true);
+        assert!(ptr::eq(root, &**m.root.as_ref().unwrap()));
+
+        // the nodes shared with the other map are not modified
+        let old = m.clone();
+        m.retain(|&k, _| k != 50);
+        assert_eq!(100, old.len());
+        assert_eq!(None, m.get(&50));
+        assert!(balanced(&m.root));
+        let shared = m.values().filter(|&v| Rc::strong_count(v) == 1).count();
+        assert!(shared >= 90);
+    }
+
+    fn hash<T: Hash>(t: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        t.hash(&mut hasher);
+        hasher.finish()
     }
 
     #[test]
-    fn test_debug() {
-        let r0 = TreeMap::new();
-        let r1 = r0.insert(7, 'g');
-        let r2 = r1.insert(4, 'd');
-
-        assert_eq!("{4: 'd', 7: 'g'}", &format!("{:?}", r2));
+    fn test_hash() {
+        let a: TreeMap<_, _> = (0..100).map(|i| (i, i * 2)).collect();
+        let b = (0..100).rev().fold(TreeMap::new(), |m, i| m.insert(i, i * 2));
+        let c = a.insert(50, 0);
+
+        assert_eq!(hash(&a), hash(&b));
+        assert!(hash(&a) != hash(&c));
+        assert!(hash(&TreeMap::<i32, i32>::new()) != hash(&TreeMap::new().insert(0, 0)));
     }
 }
 
 #[cfg(test)]
 mod quickcheck {
-    use map::TreeMap;
+    use map::{TreeMap, TreeMapBuilder, TreeMapCursor, Entry, DiffItem};
+    use pointer::RcKind;
+    use set::TreeSet;
+    use tree::{balanced, same_shape};
     use Bound;
 
     use quickcheck::TestResult;
@@ -979,6 +2696,78 @@ mod quickcheck {
         }
     }
 
+    quickcheck! {
+        fn check_range_count(xs: Vec<(isize, char)>,
+                             min_bound: Bound<isize>,
+                             max_bound: Bound<isize>)
+                -> bool
+        {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            let min = match min_bound {
+                Bound::Unbounded => Bound::Unbounded,
+                Bound::Included(ref s) => Bound::Included(s),
+                Bound::Excluded(ref s) => Bound::Excluded(s),
+            };
+
+            let max = match max_bound {
+                Bound::Unbounded => Bound::Unbounded,
+                Bound::Included(ref s) => Bound::Included(s),
+                Bound::Excluded(ref s) => Bound::Excluded(s),
+            };
+
+            let expected = m.keys().filter(|k| match_bound(*k, &min_bound, &max_bound)).count();
+
+            m.range_count(min, max) == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_range_double_ended(xs: Vec<(isize, char)>,
+                                    min_bound: Bound<isize>,
+                                    max_bound: Bound<isize>,
+                                    dirs: Vec<bool>)
+                -> bool
+        {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            let min = match min_bound {
+                Bound::Unbounded => Bound::Unbounded,
+                Bound::Included(ref s) => Bound::Included(s),
+                Bound::Excluded(ref s) => Bound::Excluded(s),
+            };
+
+            let max = match max_bound {
+                Bound::Unbounded => Bound::Unbounded,
+                Bound::Included(ref s) => Bound::Included(s),
+                Bound::Excluded(ref s) => Bound::Excluded(s),
+            };
+
+            let mut expected: Vec<(&isize, &char)> =
+                m.iter().filter(|p| match_bound(p.0, &min_bound, &max_bound)).collect();
+            let mut iter = m.range(min, max);
+
+            if iter.len() != expected.len() {
+                return false;
+            }
+
+            for back in dirs {
+                let (actual, elem) = if back {
+                    (iter.next_back(), expected.pop())
+                } else if expected.is_empty() {
+                    (iter.next(), None)
+                } else {
+                    (iter.next(), Some(expected.remove(0)))
+                };
+                if actual != elem || iter.len() != expected.len() {
+                    return false;
+                }
+            }
+
+            iter.collect::<Vec<_>>() == expected
+        }
+    }
+
     quickcheck! {
         fn check_eq(xs: Vec<(isize, char)>) -> bool
         {
@@ -1052,7 +2841,7 @@ mod quickcheck {
             let m: TreeMap<isize, char> = input.iter().cloned().collect();
 
             if input.iter().any(|&(k, _)| k == key) {
-                None == m.insert_if_absent(key, value)
+                m.insert_if_absent(key, value).is_none()
             } else {
                 let res = m.insert_if_absent(key, value);
                 res.is_some() && res.unwrap().get(&key) == Some(&value)
@@ -1077,6 +2866,206 @@ mod quickcheck {
         }
     }
 
+    quickcheck! {
+        fn check_split(xs: Vec<(isize, char)>, key: isize) -> bool
+        {
+            let input = filter_input(xs);
+            let m: TreeMap<isize, char> = input.iter().cloned().collect();
+
+            let (left, pair, right) = m.split(&key);
+
+            balanced(&left.root) && balanced(&right.root)
+                && left.keys().all(|&k| k < key)
+                && right.keys().all(|&k| k > key)
+                && pair.map(|(&k, &v)| (k, v)) == input.iter().cloned().find(|&(k, _)| k == key)
+                && left.len() + right.len() + pair.iter().count() == m.len()
+        }
+    }
+
+    quickcheck! {
+        fn check_join(xs: Vec<(isize, char)>, ys: Vec<(isize, char)>, value: char) -> bool
+        {
+            let left: TreeMap<isize, char> =
+                xs.into_iter().map(|(k, v)| (-k.abs() - 1, v)).collect();
+            let right: TreeMap<isize, char> =
+                ys.into_iter().map(|(k, v)| (k.abs() + 1, v)).collect();
+
+            let m = TreeMap::join(&left, 0, value, &right);
+
+            let expected: Vec<_> = left.iter()
+                                       .chain(Some((&0, &value)))
+                                       .chain(right.iter())
+                                       .collect();
+
+            balanced(&m.root) && m.iter().collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_union_with(xs: Vec<(isize, isize)>, ys: Vec<(isize, isize)>) -> bool
+        {
+            let a: TreeMap<isize, isize> = xs.into_iter().collect();
+            let b: TreeMap<isize, isize> = ys.into_iter().collect();
+
+            let m = a.union_with(&b, |x, y| x - y);
+
+            balanced(&m.root)
+                && m.len() == a.keys().chain(b.keys()).collect::<TreeSet<_>>().len()
+                && m.iter().all(|(k, &v)| match (a.get(k), b.get(k)) {
+                    (Some(&x), Some(&y)) => v == x - y,
+                    (Some(&x), None) => v == x,
+                    (None, Some(&y)) => v == y,
+                    (None, None) => false
+                })
+        }
+    }
+
+    quickcheck! {
+        fn check_intersection_with(xs: Vec<(isize, char)>, ys: Vec<(isize, bool)>) -> bool
+        {
+            let a: TreeMap<isize, char> = xs.into_iter().collect();
+            let b: TreeMap<isize, bool> = ys.into_iter().collect();
+
+            let m = a.intersection_with(&b, |&c, &flag| (c, flag));
+
+            let expected: Vec<_> = a.iter()
+                                    .filter_map(|(&k, &c)| b.get(&k).map(|&flag| (k, (c, flag))))
+                                    .collect();
+
+            balanced(&m.root) && m.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_difference_with(xs: Vec<(isize, char)>, ys: Vec<(isize, bool)>) -> bool
+        {
+            let a: TreeMap<isize, char> = xs.into_iter().collect();
+            let b: TreeMap<isize, bool> = ys.into_iter().collect();
+
+            let m = a.difference_with(&b, |&c, &keep| if keep { Some(c) } else { None });
+
+            let expected: Vec<_> = a.iter()
+                                    .filter(|&(k, _)| b.get(k) != Some(&false))
+                                    .map(|(&k, &c)| (k, c))
+                                    .collect();
+
+            balanced(&m.root) && m.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_merge_with_key(xs: Vec<(isize, isize)>, ys: Vec<(isize, isize)>) -> bool
+        {
+            let a: TreeMap<isize, isize> = xs.into_iter().collect();
+            let b: TreeMap<isize, isize> = ys.into_iter().collect();
+
+            let m = a.merge_with_key(&b,
+                                     |&k, &x, &y| if k % 2 == 0 { Some(x + y) } else { None },
+                                     |m| m,
+                                     |_| TreeMap::new());
+
+            let expected: Vec<_> = a.iter().filter_map(|(&k, &x)| match b.get(&k) {
+                Some(&y) => if k % 2 == 0 { Some((k, x + y)) } else { None },
+                None => Some((k, x))
+            }).collect();
+
+            balanced(&m.root) && m.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_from_sorted_iter(xs: Vec<(isize, char)>) -> bool
+        {
+            let mut input = xs.clone();
+            input.sort_by_key(|pair| pair.0);
+
+            let m = TreeMap::<_, _>::from_sorted_iter(input.clone());
+            let expected: TreeMap<isize, char> =
+                input.iter().fold(TreeMap::new(), |m, &(k, v)| m.insert(k, v));
+
+            balanced(&m.root) && m == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_from_strictly_sorted_iter(xs: Vec<(isize, char)>) -> bool
+        {
+            let strictly_sorted = xs.windows(2).all(|w| w[0].0 < w[1].0);
+
+            match TreeMap::<_, _>::from_strictly_sorted_iter(xs.clone()) {
+                Some(m) => strictly_sorted && balanced(&m.root)
+                    && m.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == xs,
+                None => !strictly_sorted
+            }
+        }
+    }
+
+    quickcheck! {
+        fn check_from_iter_sorted(xs: Vec<(isize, char)>) -> bool
+        {
+            let mut input = filter_input(xs);
+            input.sort();
+
+            let m: TreeMap<isize, char> = input.iter().cloned().collect();
+
+            balanced(&m.root) && m.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == input
+        }
+    }
+
+    quickcheck! {
+        fn check_get_index(xs: Vec<(isize, char)>) -> bool
+        {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            m.iter().enumerate().all(|(i, pair)| m.get_index(i) == Some(pair))
+                && m.get_index(m.len()).is_none()
+        }
+    }
+
+    quickcheck! {
+        fn check_index_of(xs: Vec<(isize, char)>, key: isize) -> bool
+        {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            m.keys().enumerate().all(|(i, k)| m.index_of(k) == Some(i))
+                && m.index_of(&key) == m.keys().position(|&k| k == key)
+        }
+    }
+
+    quickcheck! {
+        fn check_remove_index(xs: Vec<(isize, char)>, index: usize) -> bool
+        {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            match m.remove_index(index) {
+                Some((removed, (&k, &v))) => {
+                    let mut expected: Vec<_> = m.iter().map(|(&k, &v)| (k, v)).collect();
+                    let pair = expected.remove(index);
+
+                    balanced(&removed.root) && pair == (k, v)
+                        && removed.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected
+                },
+                None => index >= m.len()
+            }
+        }
+    }
+
+    quickcheck! {
+        fn check_split_at(xs: Vec<(isize, char)>, index: usize) -> bool
+        {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            let (left, right) = m.split_at(index);
+            let expected: Vec<_> = m.iter().collect();
+            let mid = if index < m.len() { index } else { m.len() };
+
+            balanced(&left.root) && balanced(&right.root)
+                && left.iter().collect::<Vec<_>>() == expected[..mid]
+                && right.iter().collect::<Vec<_>>() == expected[mid..]
+                && m.take(index) == left && m.skip(index) == right
+        }
+    }
+
     quickcheck! {
         fn check_insert_or_update(xs: Vec<(char, isize)>, key: char) -> bool
         {
@@ -1095,4 +3084,304 @@ mod quickcheck {
             }
         }
     }
+
+    quickcheck! {
+        fn check_insert_mut(xs: Vec<(isize, char)>) -> bool {
+            let mut expected = TreeMap::new();
+            let mut m = TreeMap::new();
+            let mut snapshots = Vec::new();
+
+            for (i, &(k, v)) in xs.iter().enumerate() {
+                if i % 4 == 0 {
+                    snapshots.push((m.clone(), expected.clone()));
+                }
+                if m.insert_mut(k, v) != expected.get(&k).cloned() {
+                    return false;
+                }
+                expected = expected.insert(k, v);
+            }
+
+            balanced(&m.root) && m == expected
+                && snapshots.into_iter().all(|(snapshot, e)| snapshot == e)
+        }
+    }
+
+    quickcheck! {
+        fn check_remove_mut(xs: Vec<(isize, char)>, ys: Vec<isize>) -> bool {
+            let mut expected: TreeMap<isize, char> = xs.into_iter().collect();
+            let mut m = expected.clone();
+            let original = expected.clone();
+
+            for k in ys {
+                let removed = m.remove_mut(&k);
+                if removed != expected.get(&k).cloned() || !balanced(&m.root) {
+                    return false;
+                }
+                if let Some((new_expected, _)) = expected.remove(&k) {
+                    expected = new_expected;
+                }
+            }
+
+            let restored: TreeMap<isize, char> = original.iter().map(|(&k, &v)| (k, v)).collect();
+            m == expected && original == restored
+        }
+    }
+
+    quickcheck! {
+        fn check_builder(xs: Vec<(isize, char)>, ys: Vec<isize>) -> bool {
+            let mut builder = TreeMapBuilder::new();
+            builder.extend(xs.iter().cloned());
+            for k in &ys {
+                builder.remove(k);
+            }
+            let m = builder.freeze();
+
+            let mut expected: TreeMap<isize, char> = xs.into_iter().collect();
+            for k in &ys {
+                if let Some((new_expected, _)) = expected.remove(k) {
+                    expected = new_expected;
+                }
+            }
+
+            balanced(&m.root) && m == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_cursor(xs: Vec<(isize, char)>, key: isize) -> bool {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+            let expected: Vec<_> = m.iter().collect();
+            // the index `expected.len()` is the ghost position
+            let n = expected.len() + 1;
+            let lower = expected.iter().position(|&(&k, _)| k >= key).unwrap_or(n - 1);
+            let upper = expected.iter().position(|&(&k, _)| k > key).unwrap_or(n - 1);
+
+            let check = |cursor: &TreeMapCursor<isize, char>, index: usize| {
+                cursor.rank() == if index < n - 1 { Some(index) } else { None }
+                    && cursor.key() == expected.get(index).map(|p| p.0)
+                    && cursor.value() == expected.get(index).map(|p| p.1)
+            };
+
+            let mut cursor = m.lower_bound(&key);
+            for i in 0..n + 1 {
+                if !check(&cursor, (lower + i) % n) {
+                    return false;
+                }
+                cursor.move_next();
+            }
+
+            let mut cursor = m.upper_bound(&key);
+            for i in 0..n + 1 {
+                if !check(&cursor, (upper + n - i % n) % n) {
+                    return false;
+                }
+                cursor.move_prev();
+            }
+
+            match m.find(&key) {
+                Some(cursor) => lower < upper && check(&cursor, lower),
+                None => lower == upper
+            }
+        }
+    }
+
+    quickcheck! {
+        fn check_get_le_lt_ge_gt(xs: Vec<(isize, char)>, key: isize) -> bool {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            m.get_le(&key) == m.iter().rev().find(|p| *p.0 <= key)
+                && m.get_lt(&key) == m.iter().rev().find(|p| *p.0 < key)
+                && m.get_ge(&key) == m.iter().find(|p| *p.0 >= key)
+                && m.get_gt(&key) == m.iter().find(|p| *p.0 > key)
+        }
+    }
+
+    quickcheck! {
+        fn check_entry(xs: Vec<(isize, char)>, key: isize, value: char) -> bool {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            let inserted = m.entry(key).or_insert(value);
+            let modified = m.entry(key).and_modify(|_| value);
+            let handled = match m.entry(key) {
+                Entry::Occupied(e) => {
+                    let found = e.key() == &key && m.get(&key) == Some(e.get());
+                    let removed = e.remove();
+                    found && balanced(&removed.root) && removed.len() + 1 == m.len()
+                        && removed == m.remove(&key).unwrap().0
+                },
+                Entry::Vacant(e) => {
+                    let found = e.key() == &key && !m.contains_key(&key);
+                    let inserted = e.insert(value);
+                    found && balanced(&inserted.root) && inserted.len() == m.len() + 1
+                        && inserted == m.insert(key, value)
+                }
+            };
+
+            balanced(&inserted.root) && balanced(&modified.root) && handled
+                && inserted == m.insert_if_absent(key, value).unwrap_or_else(|| m.clone())
+                && modified == m.update(&key, |_| value).unwrap_or_else(|| m.clone())
+        }
+    }
+
+    quickcheck! {
+        fn check_alter(xs: Vec<(isize, char)>, key: isize, value: Option<char>) -> bool {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+
+            let altered = m.alter(&key, |v| {
+                if v != m.get(&key) {
+                    panic!("the value of the key is not given");
+                }
+                value
+            });
+            let expected = match value {
+                Some(v) => m.insert(key, v),
+                None => m.remove(&key).map_or_else(|| m.clone(), |p| p.0)
+            };
+
+            balanced(&altered.root) && altered.len() == expected.len() && altered == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_iter_double_ended(xs: Vec<(isize, char)>, dirs: Vec<bool>) -> bool {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+            let mut expected: Vec<(&isize, &char)> = m.iter().collect();
+            let mut iter = m.iter();
+
+            for back in dirs {
+                let (actual, elem) = if back {
+                    (iter.next_back(), expected.pop())
+                } else if expected.is_empty() {
+                    (iter.next(), None)
+                } else {
+                    (iter.next(), Some(expected.remove(0)))
+                };
+                if actual != elem || iter.len() != expected.len() {
+                    return false;
+                }
+            }
+
+            iter.size_hint() == (expected.len(), Some(expected.len()))
+                && iter.collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_map_values(xs: Vec<(isize, isize)>) -> bool {
+            let m: TreeMap<isize, isize> = xs.into_iter().collect();
+            let mapped = m.map_values(|&v| v.wrapping_mul(3));
+            let with_key = m.map_with_key(|&k, &v| k.wrapping_add(v));
+
+            let expected: Vec<(isize, isize)> =
+                m.iter().map(|(&k, &v)| (k, v.wrapping_mul(3))).collect();
+            let expected_with_key: Vec<(isize, isize)> =
+                m.iter().map(|(&k, &v)| (k, k.wrapping_add(v))).collect();
+
+            same_shape(&m.root, &mapped.root)
+                && mapped.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected
+                && with_key.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected_with_key
+        }
+    }
+
+    quickcheck! {
+        fn check_filter(xs: Vec<(isize, isize)>, modulo: u8) -> bool {
+            let modulo = modulo as isize + 1;
+            let m: TreeMap<isize, isize> = xs.into_iter().collect();
+            let keep = |v: isize| v % modulo == 0;
+
+            let filtered = m.filter(|_, &v| keep(v));
+            let (yes, no) = m.partition(|_, &v| keep(v));
+            let mut retained = m.clone();
+            retained.retain(|_, &v| keep(v));
+            let mut owned: TreeMap<isize, isize> = m.iter().map(|(&k, &v)| (k, v)).collect();
+            owned.retain(|_, &v| keep(v));
+            let halved = m.filter_map(|_, &v| if keep(v) { Some(v / modulo) } else { None });
+
+            let expected: Vec<(isize, isize)> =
+                m.iter().map(|(&k, &v)| (k, v)).filter(|p| keep(p.1)).collect();
+            let rest: Vec<(isize, isize)> =
+                m.iter().map(|(&k, &v)| (k, v)).filter(|p| !keep(p.1)).collect();
+            let expected_halved: Vec<(isize, isize)> =
+                expected.iter().map(|&(k, v)| (k, v / modulo)).collect();
+
+            balanced(&filtered.root) && balanced(&yes.root) && balanced(&no.root)
+                && balanced(&halved.root) && balanced(&retained.root) && balanced(&owned.root)
+                && filtered.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected
+                && yes == filtered && retained == filtered && owned == filtered
+                && no.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == rest
+                && halved.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>() == expected_halved
+        }
+    }
+
+    quickcheck! {
+        fn check_into_iter(xs: Vec<(isize, char)>, dirs: Vec<bool>) -> bool {
+            let m: TreeMap<isize, char> = xs.into_iter().collect();
+            let mut expected: Vec<(isize, char)> = m.iter().map(|(&k, &v)| (k, v)).collect();
+            let mut iter = m.clone().into_iter();
+
+            for back in dirs {
+                let (actual, elem) = if back {
+                    (iter.next_back(), expected.pop())
+                } else if expected.is_empty() {
+                    (iter.next(), None)
+                } else {
+                    (iter.next(), Some(expected.remove(0)))
+                };
+                if actual != elem || iter.len() != expected.len() {
+                    return false;
+                }
+            }
+
+            iter.collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_comparator(xs: Vec<(isize, char)>, ys: Vec<(isize, char)>, key: isize) -> bool {
+            let rev = |a: &isize, b: &isize| b.cmp(a);
+            let empty: TreeMap<_, _, RcKind, _> = TreeMap::with_comparator(rev);
+            let m = xs.iter().fold(empty.clone(), |m, &(k, v)| m.insert(k, v));
+            let n = ys.iter().fold(empty, |m, &(k, v)| m.insert(k, v));
+            let expected: TreeMap<isize, char> = xs.into_iter().collect();
+
+            let union = m.union_with(&n, |&a, _| a);
+            let removed = m.remove(&key).map(|p| p.0);
+
+            balanced(&m.root) && balanced(&union.root)
+                && m.iter().eq(expected.rev_iter())
+                && m.get(&key) == expected.get(&key)
+                && m.get_le(&key) == expected.get_ge(&key)
+                && union.iter().all(|(k, v)| m.get(k).or_else(|| n.get(k)) == Some(v))
+                && union.keys().zip(union.keys().skip(1)).all(|(a, b)| a > b)
+                && removed.map_or(true, |r| balanced(&r.root) && !r.contains_key(&key))
+                && m.range(Bound::Included(&key), Bound::Unbounded)
+                    .eq(expected.range(Bound::Unbounded, Bound::Included(&key)).rev())
+        }
+    }
+    quickcheck! {
+        fn check_diff(xs: Vec<(isize, char)>, ys: Vec<(isize, char)>, zs: Vec<isize>) -> bool {
+            let old: TreeMap<isize, char> = xs.into_iter().collect();
+            let inserted = ys.into_iter().fold(old.clone(), |m, (k, v)| m.insert(k, v));
+            let new = zs.iter().fold(inserted, |m, k| m.remove(k).map_or(m.clone(), |p| p.0));
+
+            let mut expected = Vec::new();
+            for (k, v) in old.iter() {
+                match new.get(k) {
+                    None => expected.push(DiffItem::Removed(k, v)),
+                    Some(w) if v != w => expected.push(DiffItem::Changed(k, v, w)),
+                    Some(_) => ()
+                }
+            }
+            for (k, v) in new.iter() {
+                if !old.contains_key(k) {
+                    expected.push(DiffItem::Added(k, v));
+                }
+            }
+            expected.sort_by_key(|item| match *item {
+                DiffItem::Added(k, _) | DiffItem::Removed(k, _) | DiffItem::Changed(k, _, _) => *k
+            });
+
+            old.diff(&new).collect::<Vec<_>>() == expected
+        }
+    }
 }
@@ -0,0 +1,134 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A measure which summarizes the elements of a tree into a value of a monoid.
+///
+/// The nodes of an augmented tree cache the summary of their subtrees, so that the summary of
+/// any range of the keys can be computed in O(log n) time. The summary is recomputed whenever the
+/// tree is rebalanced, so `combine` should be cheap.
+///
+/// `combine` must be associative, and `empty` must be the identity of `combine`. The summary of a
+/// range is the combination of the measures of its elements in the key order, so `combine` does
+/// not have to be commutative.
+///
+/// The type of the summary is given by the supertrait `Summarize`.
+///
+/// The unit type `()` is the trivial measure, which caches nothing.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::AugmentedMap;
+/// use immutable_map::Bound;
+/// use immutable_map::measure::{Measure, Summarize};
+///
+/// struct Sum;
+///
+/// impl<K> Summarize<K, u64> for Sum {
+///     type Summary = u64;
+/// }
+///
+/// impl<K> Measure<K, u64> for Sum {
+///     fn empty() -> u64 {
+///         0
+///     }
+///
+///     fn measure(_: &K, value: &u64) -> u64 {
+///         *value
+///     }
+///
+///     fn combine(a: &u64, b: &u64) -> u64 {
+///         a + b
+///     }
+/// }
+///
+/// let map: AugmentedMap<_, _, Sum> = (1..11).map(|t| (t, t * 10)).collect();
+///
+/// assert_eq!(550, map.summary());
+/// assert_eq!(120, map.aggregate(Bound::Included(&3), Bound::Excluded(&6)));
+/// ```
+pub trait Measure<K, V>: Summarize<K, V> {
+    /// Returns the summary of no elements.
+    fn empty() -> Self::Summary;
+
+    /// Returns the summary of a single element.
+    fn measure(key: &K, value: &V) -> Self::Summary;
+
+    /// Combines the summaries of two adjacent ranges, `a` coming before `b`.
+    fn combine(a: &Self::Summary, b: &Self::Summary) -> Self::Summary;
+}
+
+/// The type of the summaries of a measure.
+///
+/// The trees name the type of their summaries without computing them, so the bounds which the
+/// computation needs, such as `Ord` on the keys, can be put on the impl of `Measure` alone. A
+/// collection using such a measure then needs the bounds only on the methods which compute the
+/// summaries, and not on the collection type itself. The summaries need not be `Clone`.
+pub trait Summarize<K, V> {
+    /// The type of the summary.
+    type Summary;
+}
+
+impl<K, V> Summarize<K, V> for () {
+    type Summary = ();
+}
+
+impl<K, V> Measure<K, V> for () {
+    fn empty() {}
+
+    fn measure(_: &K, _: &V) {}
+
+    fn combine(_: &(), _: &()) {}
+}
+
+// an odd multiplier, which is invertible modulo 2^64
+const HASH_MULTIPLIER: u64 = 0x0000_0100_0000_01b3;
+
+/// A measure which caches the hash of the elements of each subtree.
+///
+/// The summary is a polynomial hash `(h, m)` of the elements in the key order, where `m` is the
+/// multiplier raised to the number of the elements. The hash depends only on the elements and
+/// not on the shape of the tree, so equal maps have equal summaries. `AugmentedMap<K, V,
+/// CachedHash>` implements `Hash` by writing the cached summary, which takes O(1) time, and the
+/// updated map recomputes the summaries only along the modified paths.
+///
+/// The element hashes are computed with `DefaultHasher::new()`, so the cached hashes are
+/// consistent within a single build of the program, but should not be persisted.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashSet;
+/// use immutable_map::AugmentedMap;
+/// use immutable_map::measure::CachedHash;
+///
+/// let a: AugmentedMap<_, _, CachedHash> = (0..100).map(|i| (i, i * 2)).collect();
+/// let b: AugmentedMap<_, _, CachedHash> = (0..100).rev().map(|i| (i, i * 2)).collect();
+///
+/// let mut seen = HashSet::new();
+/// seen.insert(a);
+/// assert!(seen.contains(&b));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct CachedHash;
+
+impl<K, V> Summarize<K, V> for CachedHash {
+    type Summary = (u64, u64);
+}
+
+impl<K: Hash, V: Hash> Measure<K, V> for CachedHash {
+    fn empty() -> (u64, u64) {
+        (0, 1)
+    }
+
+    fn measure(key: &K, value: &V) -> (u64, u64) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        value.hash(&mut hasher);
+        (hasher.finish(), HASH_MULTIPLIER)
+    }
+
+    fn combine(a: &(u64, u64), b: &(u64, u64)) -> (u64, u64) {
+        (a.0.wrapping_mul(b.1).wrapping_add(b.0), a.1.wrapping_mul(b.1))
+    }
+}
@@ -0,0 +1,572 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Debug;
+use std::iter;
+use std::iter::FromIterator;
+
+use Bound;
+use compare::{Compare, Natural};
+use map::{TreeMap, TreeMapIter, TreeMapKeys, TreeMapRange};
+use pointer::{PointerKind, RcKind, ArcKind};
+use set::{TreeSet, TreeSetIter};
+
+/// An immutable multimap based on weight-balanced binary tree.
+///
+/// Each key is associated with a non-empty set of values. The keys are ordered by the comparator
+/// of the multimap, and the values of each key are ordered by `Ord`. A key is removed as soon as
+/// its last value is removed.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::TreeMultiMap;
+///
+/// let multimap_0 = TreeMultiMap::new();
+///
+/// // `insert` returns new copies with the given pair inserted, and does not change the original
+/// // multimap
+/// let multimap_1 = multimap_0.insert(1, "One");
+/// let multimap_2 = multimap_1.insert(1, "Uno");
+///
+/// assert_eq!(1, multimap_1.len());
+/// assert_eq!(2, multimap_2.len());
+/// assert_eq!(vec![&"One", &"Uno"], multimap_2.get_all(&1).unwrap().iter().collect::<Vec<_>>());
+/// ```
+pub struct TreeMultiMap<K, V, P: PointerKind = RcKind, C = Natural> {
+    map: TreeMap<K, TreeSet<V, P>, P, C>,
+    // the number of the key-value pairs
+    len: usize
+}
+
+/// A `TreeMultiMap` which is `Send` and `Sync`, as both its keys and its value sets are linked
+/// with `Arc`.
+pub type ArcTreeMultiMap<K, V> = TreeMultiMap<K, V, ArcKind>;
+
+pub type TreeMultiMapIter<'r, K, V, P = RcKind> =
+    TreeMultiMapPairs<'r, K, V, P, TreeMapIter<'r, K, TreeSet<V, P>, P>>;
+pub type TreeMultiMapRange<'r, K, V, P = RcKind, C = Natural> =
+    TreeMultiMapPairs<'r, K, V, P, TreeMapRange<'r, K, TreeSet<V, P>, P, C>>;
+pub type TreeMultiMapKeys<'r, K, V, P = RcKind> = TreeMapKeys<'r, K, TreeSet<V, P>, P>;
+
+/// An iterator over the key-value pairs of a `TreeMultiMap`, sorted by key and then by value.
+#[derive(Clone)]
+pub struct TreeMultiMapPairs<'r, K: 'r, V: 'r, P: PointerKind, I> {
+    groups: I,
+    // the groups partially taken from either end
+    front: Option<(&'r K, TreeSetIter<'r, V, P>)>,
+    back: Option<(&'r K, TreeSetIter<'r, V, P>)>
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, I> TreeMultiMapPairs<'r, K, V, P, I> {
+    fn new(groups: I) -> TreeMultiMapPairs<'r, K, V, P, I> {
+        TreeMultiMapPairs { groups, front: None, back: None }
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, I> Iterator for TreeMultiMapPairs<'r, K, V, P, I>
+    where I: Iterator<Item=(&'r K, &'r TreeSet<V, P>)>
+{
+    type Item = (&'r K, &'r V);
+
+    fn next(&mut self) -> Option<(&'r K, &'r V)> {
+        loop {
+            if let Some((k, ref mut values)) = self.front {
+                if let Some(v) = values.next() {
+                    return Some((k, v));
+                }
+            }
+            match self.groups.next() {
+                Some((k, values)) => self.front = Some((k, values.iter())),
+                // the rest of the pairs are in the group partially taken from the back
+                None => return match self.back {
+                    Some((k, ref mut values)) => values.next().map(|v| (k, v)),
+                    None => None
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pending = self.front.as_ref().map_or(0, |g| g.1.len())
+            + self.back.as_ref().map_or(0, |g| g.1.len());
+        match self.groups.size_hint() {
+            (_, Some(0)) => (pending, Some(pending)),
+            _ => (pending, None)
+        }
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, I> DoubleEndedIterator for TreeMultiMapPairs<'r, K, V, P, I>
+    where I: DoubleEndedIterator<Item=(&'r K, &'r TreeSet<V, P>)>
+{
+    fn next_back(&mut self) -> Option<(&'r K, &'r V)> {
+        loop {
+            if let Some((k, ref mut values)) = self.back {
+                if let Some(v) = values.next_back() {
+                    return Some((k, v));
+                }
+            }
+            match self.groups.next_back() {
+                Some((k, values)) => self.back = Some((k, values.iter())),
+                None => return match self.front {
+                    Some((k, ref mut values)) => values.next_back().map(|v| (k, v)),
+                    None => None
+                }
+            }
+        }
+    }
+}
+
+impl<K, V> TreeMultiMap<K, V> {
+    /// Makes a new empty TreeMultiMap
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new();
+    /// let new_multimap = multimap.insert(1, "One");
+    /// ```
+    pub fn new() -> TreeMultiMap<K, V> {
+        TreeMultiMap { map: TreeMap::new(), len: 0 }
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMultiMap<K, V, P, C> {
+    /// Makes a new empty TreeMultiMap which orders the keys with the given comparator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let rev = |a: &i32, b: &i32| b.cmp(a);
+    /// let multimap: TreeMultiMap<_, _, RcKind, _> = TreeMultiMap::with_comparator(rev)
+    ///     .insert(1, 'a')
+    ///     .insert(2, 'b')
+    ///     .insert(2, 'c');
+    ///
+    /// let pairs: Vec<_> = multimap.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(2, 'b'), (2, 'c'), (1, 'a')]);
+    /// ```
+    pub fn with_comparator(cmp: C) -> TreeMultiMap<K, V, P, C> {
+        TreeMultiMap { map: TreeMap::with_comparator(cmp), len: 0 }
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMultiMap<K, V, P, C> {
+    /// Returns the number of the key-value pairs in the multimap. This operation takes O(1)
+    /// time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'a').insert(1, 'b').insert(2, 'c');
+    /// assert_eq!(3, multimap.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of the distinct keys in the multimap.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'a').insert(1, 'b').insert(2, 'c');
+    /// assert_eq!(2, multimap.keys_len());
+    /// ```
+    pub fn keys_len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the multimap contains no pairs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let empty_multimap = TreeMultiMap::new();
+    /// let new_multimap = empty_multimap.insert(1, 'a');
+    ///
+    /// assert!(empty_multimap.is_empty());
+    /// assert!(!new_multimap.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets an iterator over the key-value pairs of the multimap, sorted by key and then by
+    /// value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(2, 'c').insert(1, 'b').insert(1, 'a');
+    ///
+    /// let pairs: Vec<_> = multimap.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(1, 'a'), (1, 'b'), (2, 'c')]);
+    /// ```
+    pub fn iter<'r>(&'r self) -> TreeMultiMapIter<'r, K, V, P> {
+        TreeMultiMapPairs::new(self.map.iter())
+    }
+
+    /// Gets an iterator over the distinct keys of the multimap, in sorted order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(2, 'c').insert(1, 'b').insert(1, 'a');
+    ///
+    /// assert_eq!(vec![&1, &2], multimap.keys().collect::<Vec<_>>());
+    /// ```
+    pub fn keys<'r>(&'r self) -> TreeMultiMapKeys<'r, K, V, P> {
+        self.map.keys()
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMultiMap<K, V, P, C> where V: Ord, C: Compare<K> {
+    /// Returns the set of the values associated with the key, or `None` if the multimap does
+    /// not contain the key. The returned set is never empty.
+    ///
+    /// The key may be any borrowed form of the multimap's key type, but the ordering on the
+    /// borrowed form must match the ordering on the key type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'b').insert(1, 'a');
+    ///
+    /// assert_eq!(vec![&'a', &'b'], multimap.get_all(&1).unwrap().iter().collect::<Vec<_>>());
+    /// assert!(multimap.get_all(&2).is_none());
+    /// ```
+    pub fn get_all<Q: ?Sized>(&self, key: &Q) -> Option<&TreeSet<V, P>>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        self.map.get(key)
+    }
+
+    /// Returns true if the multimap contains at least one value for the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'a');
+    ///
+    /// assert!(multimap.contains_key(&1));
+    /// assert!(!multimap.contains_key(&2));
+    /// ```
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns true if the multimap contains the key-value pair.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'a');
+    ///
+    /// assert!(multimap.contains(&1, &'a'));
+    /// assert!(!multimap.contains(&1, &'b'));
+    /// ```
+    pub fn contains<Q: ?Sized>(&self, key: &Q, value: &V) -> bool
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        self.map.get(key).map_or(false, |values| values.contains(value))
+    }
+
+    /// Constructs a double-ended iterator over the key-value pairs whose keys are in a sub-range
+    /// of the keys, starting at min, and ending at max. The bounds are treated in the same way
+    /// as `TreeMap::range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    /// use immutable_map::Bound::*;
+    ///
+    /// let multimap = TreeMultiMap::new()
+    ///     .insert(1, 'a')
+    ///     .insert(2, 'b')
+    ///     .insert(2, 'c')
+    ///     .insert(3, 'd');
+    ///
+    /// let range = multimap.range(Included(&2), Unbounded);
+    ///
+    /// let pairs: Vec<_> = range.map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(pairs, [(2, 'b'), (2, 'c'), (3, 'd')]);
+    /// ```
+    pub fn range<'r, Q>(&'r self, min: Bound<&Q>, max: Bound<&Q>)
+            -> TreeMultiMapRange<'r, K, V, P, C>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        TreeMultiMapPairs::new(self.map.range(min, max))
+    }
+}
+
+impl<K, V, P: PointerKind, C> TreeMultiMap<K, V, P, C>
+    where K: Clone, V: Ord + Clone, C: Compare<K> + Clone
+{
+    /// Returns a new multimap with the key-value pair inserted. Returns the multimap itself if
+    /// it already contains the pair.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'a').insert(1, 'b').insert(1, 'a');
+    ///
+    /// assert_eq!(2, multimap.len());
+    /// ```
+    pub fn insert(&self, key: K, value: V) -> TreeMultiMap<K, V, P, C> {
+        let values = match self.map.get(&key) {
+            Some(values) if values.contains(&value) => return self.clone(),
+            Some(values) => values.insert(value),
+            None => iter::once(value).collect()
+        };
+        TreeMultiMap { map: self.map.insert(key, values), len: self.len + 1 }
+    }
+
+    /// Returns a new multimap with the key-value pair removed, or `None` if the multimap does
+    /// not contain the pair. The key is removed if it has no more values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'a').insert(1, 'b');
+    ///
+    /// let removed = multimap.remove(&1, &'a').unwrap();
+    /// assert_eq!(1, removed.len());
+    ///
+    /// let empty = removed.remove(&1, &'b').unwrap();
+    /// assert!(!empty.contains_key(&1));
+    ///
+    /// assert!(multimap.remove(&2, &'a').is_none());
+    /// ```
+    pub fn remove<Q: ?Sized>(&self, key: &Q, value: &V) -> Option<TreeMultiMap<K, V, P, C>>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        let values = match self.map.get(key).and_then(|values| values.remove(value)) {
+            None => return None,
+            Some((values, _)) => values
+        };
+        let map = if values.is_empty() {
+            self.map.remove(key).map(|p| p.0)
+        } else {
+            self.map.update(key, |_| values.clone())
+        };
+        map.map(|map| TreeMultiMap { map, len: self.len - 1 })
+    }
+
+    /// Returns a new multimap with all the values of the key removed, and the set of the removed
+    /// values. Returns `None` if the multimap does not contain the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeMultiMap;
+    ///
+    /// let multimap = TreeMultiMap::new().insert(1, 'a').insert(1, 'b').insert(2, 'c');
+    ///
+    /// let (removed, values) = multimap.remove_all(&1).unwrap();
+    /// assert_eq!(1, removed.len());
+    /// assert_eq!(2, values.len());
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn remove_all<Q: ?Sized>(&self, key: &Q)
+            -> Option<(TreeMultiMap<K, V, P, C>, &TreeSet<V, P>)>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        self.map.remove(key).map(|(map, values)|
+            (TreeMultiMap { map, len: self.len - values.len() }, values)
+        )
+    }
+}
+
+impl<K, V, P: PointerKind, C: Clone> Clone for TreeMultiMap<K, V, P, C> {
+    fn clone(&self) -> TreeMultiMap<K, V, P, C> {
+        TreeMultiMap { map: self.map.clone(), len: self.len }
+    }
+}
+
+impl<K, V, P: PointerKind, C: Default> Default for TreeMultiMap<K, V, P, C> {
+    fn default() -> TreeMultiMap<K, V, P, C> {
+        TreeMultiMap { map: TreeMap::default(), len: 0 }
+    }
+}
+
+impl<K: Debug, V: Debug, P: PointerKind, C> Debug for TreeMultiMap<K, V, P, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+impl<'r, K, V, P: PointerKind, C> IntoIterator for &'r TreeMultiMap<K, V, P, C> {
+    type Item = (&'r K, &'r V);
+    type IntoIter = TreeMultiMapIter<'r, K, V, P>;
+
+    fn into_iter(self) -> TreeMultiMapIter<'r, K, V, P> {
+        self.iter()
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, P: PointerKind, C> PartialEq for TreeMultiMap<K, V, P, C> {
+    fn eq(&self, other: &TreeMultiMap<K, V, P, C>) -> bool {
+        self.len == other.len && self.map == other.map
+    }
+}
+
+impl<K: Eq, V: Eq, P: PointerKind, C> Eq for TreeMultiMap<K, V, P, C> {}
+
+impl<K, V, P: PointerKind, C> FromIterator<(K, V)> for TreeMultiMap<K, V, P, C>
+    where K: Clone, V: Ord + Clone, C: Compare<K> + Clone + Default
+{
+    fn from_iter<T>(iter: T) -> TreeMultiMap<K, V, P, C> where T: IntoIterator<Item=(K, V)> {
+        let mut m = TreeMultiMap::default();
+        for (k, v) in iter {
+            m = m.insert(k, v);
+        }
+        m
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TreeMultiMap;
+
+    #[test]
+    fn test_iter_both_ends() {
+        let m = TreeMultiMap::new().insert(1, 'a').insert(1, 'b').insert(2, 'c').insert(2, 'd');
+        let mut iter = m.iter();
+
+        assert_eq!(Some((&1, &'a')), iter.next());
+        assert_eq!(Some((&2, &'d')), iter.next_back());
+        assert_eq!(Some((&1, &'b')), iter.next());
+        assert_eq!(Some((&2, &'c')), iter.next());
+        assert_eq!(None, iter.next());
+        assert_eq!(None, iter.next_back());
+    }
+
+    #[test]
+    fn test_remove_last_value() {
+        // the pairs are kept once, and a key goes away with its last value
+        let m = TreeMultiMap::new().insert(1, 'a').insert(1, 'a').insert(2, 'b');
+        let removed = m.remove(&2, &'b').unwrap();
+
+        assert_eq!(2, m.len());
+        assert_eq!(1, removed.keys_len());
+        assert!(!removed.contains_key(&2));
+        assert!(removed.get_all(&2).is_none());
+        assert!(removed.remove(&1, &'b').is_none());
+    }
+}
+
+#[cfg(test)]
+mod quickcheck {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use multimap::TreeMultiMap;
+    use Bound;
+
+    fn model(pairs: &[(u8, u8)], removed: &[(u8, u8)]) -> BTreeMap<u8, BTreeSet<u8>> {
+        let mut m: BTreeMap<u8, BTreeSet<u8>> = BTreeMap::new();
+        for &(k, v) in pairs {
+            m.entry(k).or_default().insert(v);
+        }
+        for &(k, v) in removed {
+            if let Some(values) = m.get_mut(&k) {
+                values.remove(&v);
+            }
+        }
+        m.retain(|_, values| !values.is_empty());
+        m
+    }
+
+    quickcheck! {
+        fn check_insert_remove(xs: Vec<(u8, u8)>, ys: Vec<(u8, u8)>) -> bool {
+            let mut m: TreeMultiMap<u8, u8> = xs.iter().cloned().collect();
+            for &(k, v) in &ys {
+                m = m.remove(&k, &v).unwrap_or(m);
+            }
+
+            let expected = model(&xs, &ys);
+            let pairs: Vec<(u8, u8)> = m.iter().map(|(&k, &v)| (k, v)).collect();
+            let expected_pairs: Vec<(u8, u8)> = expected.iter()
+                .flat_map(|(&k, values)| values.iter().map(move |&v| (k, v)))
+                .collect();
+
+            m.len() == expected_pairs.len()
+                && m.keys_len() == expected.len()
+                && pairs == expected_pairs
+                && m.keys().all(|k| m.get_all(k).map_or(false, |values| !values.is_empty()))
+        }
+    }
+
+    quickcheck! {
+        fn check_range(xs: Vec<(u8, u8)>, min: Bound<u8>, max: Bound<u8>, dirs: Vec<bool>)
+                -> bool
+        {
+            let m: TreeMultiMap<u8, u8> = xs.iter().cloned().collect();
+            let in_range = |k: u8| match min {
+                Bound::Included(lower) => lower <= k,
+                Bound::Excluded(lower) => lower < k,
+                Bound::Unbounded => true
+            } && match max {
+                Bound::Included(upper) => k <= upper,
+                Bound::Excluded(upper) => k < upper,
+                Bound::Unbounded => true
+            };
+            let min = match min {
+                Bound::Included(ref k) => Bound::Included(k),
+                Bound::Excluded(ref k) => Bound::Excluded(k),
+                Bound::Unbounded => Bound::Unbounded
+            };
+            let max = match max {
+                Bound::Included(ref k) => Bound::Included(k),
+                Bound::Excluded(ref k) => Bound::Excluded(k),
+                Bound::Unbounded => Bound::Unbounded
+            };
+
+            let mut expected: Vec<(&u8, &u8)> = m.iter().filter(|p| in_range(*p.0)).collect();
+            let mut iter = m.range(min, max);
+
+            for back in dirs {
+                let (actual, elem) = if back {
+                    (iter.next_back(), expected.pop())
+                } else if expected.is_empty() {
+                    (iter.next(), None)
+                } else {
+                    (iter.next(), Some(expected.remove(0)))
+                };
+                if actual != elem {
+                    return false;
+                }
+            }
+
+            iter.collect::<Vec<_>>() == expected
+        }
+    }
+}
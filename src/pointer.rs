@@ -0,0 +1,89 @@
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// A kind of reference-counted pointer which links the nodes of the trees.
+///
+/// The maps and sets are generic over the pointer kind, so that the same implementation can be
+/// shared between the single-threaded `Rc`-backed collections and the thread-safe `Arc`-backed
+/// collections.
+pub trait PointerKind: Clone + Copy + Debug + Default + 'static {
+    /// The pointer type which points to a value of type `T`.
+    type Pointer<T>: Deref<Target=T> + Clone;
+
+    /// Allocates the value and returns the pointer to it.
+    fn new<T>(value: T) -> Self::Pointer<T>;
+
+    /// Returns true if the two pointers point to the same allocation.
+    fn ptr_eq<T>(a: &Self::Pointer<T>, b: &Self::Pointer<T>) -> bool;
+
+    /// Returns a mutable reference to the value, cloning the value first if the pointer is not
+    /// the only pointer to the allocation.
+    fn make_mut<T: Clone>(ptr: &mut Self::Pointer<T>) -> &mut T;
+
+    /// Returns a mutable reference to the value if the pointer is the only pointer to the
+    /// allocation.
+    fn get_mut<T>(ptr: &mut Self::Pointer<T>) -> Option<&mut T>;
+
+    /// Returns the value if the pointer is the only pointer to the allocation. Otherwise, returns
+    /// the pointer back.
+    fn try_unwrap<T>(ptr: Self::Pointer<T>) -> Result<T, Self::Pointer<T>>;
+}
+
+/// The pointer kind of `Rc`. The collections using this pointer kind are not thread-safe.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct RcKind;
+
+impl PointerKind for RcKind {
+    type Pointer<T> = Rc<T>;
+
+    fn new<T>(value: T) -> Rc<T> {
+        Rc::new(value)
+    }
+
+    fn ptr_eq<T>(a: &Rc<T>, b: &Rc<T>) -> bool {
+        Rc::ptr_eq(a, b)
+    }
+
+    fn make_mut<T: Clone>(ptr: &mut Rc<T>) -> &mut T {
+        Rc::make_mut(ptr)
+    }
+
+    fn get_mut<T>(ptr: &mut Rc<T>) -> Option<&mut T> {
+        Rc::get_mut(ptr)
+    }
+
+    fn try_unwrap<T>(ptr: Rc<T>) -> Result<T, Rc<T>> {
+        Rc::try_unwrap(ptr)
+    }
+}
+
+/// The pointer kind of `Arc`. The collections using this pointer kind are `Send` and `Sync` if
+/// their elements are `Send` and `Sync`.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct ArcKind;
+
+impl PointerKind for ArcKind {
+    type Pointer<T> = Arc<T>;
+
+    fn new<T>(value: T) -> Arc<T> {
+        Arc::new(value)
+    }
+
+    fn ptr_eq<T>(a: &Arc<T>, b: &Arc<T>) -> bool {
+        Arc::ptr_eq(a, b)
+    }
+
+    fn make_mut<T: Clone>(ptr: &mut Arc<T>) -> &mut T {
+        Arc::make_mut(ptr)
+    }
+
+    fn get_mut<T>(ptr: &mut Arc<T>) -> Option<&mut T> {
+        Arc::get_mut(ptr)
+    }
+
+    fn try_unwrap<T>(ptr: Arc<T>) -> Result<T, Arc<T>> {
+        Arc::try_unwrap(ptr)
+    }
+}
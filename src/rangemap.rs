@@ -0,0 +1,522 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::FromIterator;
+use std::mem;
+
+use tree;
+use tree::NodeRef;
+use Bound;
+use compare::Compare;
+use pointer::{PointerKind, RcKind, ArcKind};
+
+// orders the disjoint ranges by their starts
+#[derive(Clone, Copy)]
+struct ByStart;
+
+impl<T: Ord> Compare<(T, T)> for ByStart {
+    fn compare(&self, a: &(T, T), b: &(T, T)) -> Ordering {
+        a.0.cmp(&b.0)
+    }
+}
+
+/// An immutable map from disjoint half-open ranges to values, based on weight-balanced binary
+/// tree.
+///
+/// A range is a pair `(start, end)`, which contains the points from `start` inclusive to `end`
+/// exclusive. Inserting a range overwrites the overlapped parts of the existing ranges, and
+/// removing a range splits the partially removed ones. The adjacent ranges with equal values
+/// are always merged into one, so the maps with the same mapping from points to values have
+/// the same ranges.
+///
+/// An empty range, where `start >= end`, contains no points. Inserting or removing an empty
+/// range leaves the map unchanged, and the gaps of an empty range are empty.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::RangeMap;
+///
+/// let map = RangeMap::new()
+///     .insert((0, 10), "free")
+///     .insert((3, 5), "used")
+///     .insert((5, 7), "used");
+///
+/// assert_eq!(vec![(&(0, 3), &"free"), (&(3, 7), &"used"), (&(7, 10), &"free")],
+///            map.iter().collect::<Vec<_>>());
+/// assert_eq!(Some(&"used"), map.get(&6));
+/// ```
+pub struct RangeMap<T, V, P: PointerKind = RcKind> {
+    root: Option<NodeRef<(T, T), V, P>>
+}
+
+/// A `RangeMap` sharing its nodes through `Arc`, usable from several threads.
+pub type ArcRangeMap<T, V> = RangeMap<T, V, ArcKind>;
+
+pub type RangeMapIter<'r, T, V, P = RcKind> = tree::Iter<'r, (T, T), V, P>;
+
+/// An iterator over the gaps between the ranges of a `RangeMap` or a `RangeSet` within a
+/// range, in sorted order.
+#[derive(Clone)]
+pub struct RangeMapGaps<'r, T: 'r, V: 'r, P: PointerKind = RcKind> {
+    ranges: tree::Range<'r, (T, T), V, P, ByStart>,
+    // the start of the next gap, or the end of the range if the gaps are exhausted
+    cursor: T,
+    end: T
+}
+
+impl<'r, T: 'r, V: 'r, P: PointerKind> Iterator for RangeMapGaps<'r, T, V, P>
+    where T: Ord + Clone
+{
+    type Item = (T, T);
+
+    fn next(&mut self) -> Option<(T, T)> {
+        while self.cursor < self.end {
+            match self.ranges.next() {
+                Some((k, _)) => {
+                    let gap_end = k.0.clone();
+                    let gap_start = mem::replace(&mut self.cursor, k.1.clone());
+                    if gap_start < gap_end {
+                        return Some((gap_start, gap_end));
+                    }
+                },
+                None => {
+                    let gap_start = mem::replace(&mut self.cursor, self.end.clone());
+                    return Some((gap_start, self.end.clone()));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<T, V> RangeMap<T, V> {
+    /// Makes a new empty RangeMap
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new();
+    /// let new_map = map.insert((1, 2), "a");
+    /// ```
+    pub fn new() -> RangeMap<T, V> {
+        RangeMap { root: None }
+    }
+}
+
+impl<T, V, P: PointerKind> RangeMap<T, V, P> {
+    /// Returns the number of disjoint ranges in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((1, 3), "a").insert((3, 5), "a").insert((6, 8), "b");
+    /// assert_eq!(2, map.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        tree::size(&self.root)
+    }
+
+    /// Returns true if the map contains no ranges.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let empty_map = RangeMap::new();
+    /// let new_map = empty_map.insert((1, 2), "a");
+    ///
+    /// assert!(empty_map.is_empty());
+    /// assert!(!new_map.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Gets an iterator over the ranges of the map and their values, in sorted order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((4, 6), "b").insert((1, 3), "a");
+    ///
+    /// assert_eq!(vec![(&(1, 3), &"a"), (&(4, 6), &"b")], map.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn iter<'r>(&'r self) -> RangeMapIter<'r, T, V, P> {
+        tree::Iter::new(&self.root)
+    }
+}
+
+impl<T: Ord, V, P: PointerKind> RangeMap<T, V, P> {
+    /// Returns the range containing the given point and its value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((1, 3), "a").insert((3, 5), "b");
+    ///
+    /// assert_eq!(Some((&(3, 5), &"b")), map.get_range(&3));
+    /// assert_eq!(None, map.get_range(&5));
+    /// ```
+    pub fn get_range(&self, point: &T) -> Option<(&(T, T), &V)> {
+        match tree::find_last(&self.root, |k| k.0 <= *point) {
+            Some(elem) if *point < (elem.0).1 => Some((&elem.0, &elem.1)),
+            _ => None
+        }
+    }
+
+    /// Returns the value of the range containing the given point.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((1, 3), "a");
+    ///
+    /// assert_eq!(Some(&"a"), map.get(&2));
+    /// assert_eq!(None, map.get(&3));
+    /// ```
+    pub fn get(&self, point: &T) -> Option<&V> {
+        self.get_range(point).map(|p| p.1)
+    }
+
+    /// Returns true if any range of the map contains the given point.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((1, 3), "a");
+    ///
+    /// assert!(map.contains(&1));
+    /// assert!(!map.contains(&3));
+    /// ```
+    pub fn contains(&self, point: &T) -> bool {
+        self.get_range(point).is_some()
+    }
+}
+
+impl<T: Ord + Clone, V, P: PointerKind> RangeMap<T, V, P> {
+    /// Gets an iterator over the maximal parts of the range from `start` inclusive to `end`
+    /// exclusive which are not covered by any range of the map. The iterator yields nothing if
+    /// the range is empty, that is, `start >= end`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((2, 4), "a").insert((6, 8), "b");
+    ///
+    /// assert_eq!(vec![(0, 2), (4, 6), (8, 10)], map.gaps(&0, &10).collect::<Vec<_>>());
+    /// assert_eq!(vec![(4, 5)], map.gaps(&3, &5).collect::<Vec<_>>());
+    /// assert_eq!(0, map.gaps(&5, &5).count());
+    /// ```
+    pub fn gaps<'r>(&'r self, start: &T, end: &T) -> RangeMapGaps<'r, T, V, P> {
+        // the range starting before `start` may cover the beginning of the gaps
+        let cursor = match tree::find_last(&self.root, |k| k.0 < *start) {
+            Some(elem) if (elem.0).1 > *start => (elem.0).1.clone(),
+            _ => start.clone()
+        };
+        let min = (start.clone(), start.clone());
+        let max = (end.clone(), end.clone());
+        let ranges = tree::Range::new(&self.root, Bound::Included(&min), Bound::Excluded(&max),
+                                      &ByStart);
+        RangeMapGaps { ranges, cursor, end: end.clone() }
+    }
+}
+
+impl<T: Ord + Clone, V: Clone, P: PointerKind> RangeMap<T, V, P> {
+    /// Returns a new copy of the map with the points from `start` inclusive to `end` exclusive
+    /// removed. The ranges partially overlapping the removed range are split, and the parts
+    /// outside of the removed range are kept. Removing an empty range, where `start >= end`,
+    /// returns an unchanged copy of the map. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((0, 10), "a");
+    /// let new_map = map.remove(&3, &5);
+    ///
+    /// assert_eq!(vec![(&(0, 3), &"a"), (&(5, 10), &"a")], new_map.iter().collect::<Vec<_>>());
+    /// assert_eq!(1, map.len());
+    /// ```
+    pub fn remove(&self, start: &T, end: &T) -> RangeMap<T, V, P> {
+        if start >= end {
+            return self.clone();
+        }
+
+        // `left` has the ranges starting before `start`, `middle` has the ranges starting
+        // within the removed range, and `right` has the rest
+        let (left, found, right) = tree::split(&self.root, &(start.clone(), start.clone()),
+                                               &ByStart);
+        let right = match found {
+            Some(elem) => Some(NodeRef::new(tree::join(elem.clone(), &None, &right))),
+            None => right
+        };
+        let (middle, found, right) = tree::split(&right, &(end.clone(), end.clone()), &ByStart);
+        let mut right = match found {
+            Some(elem) => Some(NodeRef::new(tree::join(elem.clone(), &None, &right))),
+            None => right
+        };
+
+        // the last range of `left` may overlap the beginning of the removed range, and either it
+        // or the last range of `middle` may extend beyond the end of the removed range
+        let mut left = left;
+        if let Some(last) = tree::find_last(&left, |_| true).cloned() {
+            let ((last_start, last_end), value) = last;
+            if last_end > *start {
+                if last_end > *end {
+                    let tail = ((end.clone(), last_end), value.clone());
+                    right = Some(NodeRef::new(tree::join(tail, &None, &right)));
+                }
+                let head = ((last_start, start.clone()), value);
+                left = Some(NodeRef::new(tree::insert(&left, head, &ByStart)));
+            }
+        }
+        if let Some(last) = tree::find_last(&middle, |_| true) {
+            if (last.0).1 > *end {
+                let tail = ((end.clone(), (last.0).1.clone()), last.1.clone());
+                right = Some(NodeRef::new(tree::join(tail, &None, &right)));
+            }
+        }
+
+        RangeMap { root: tree::merge(&left, &right) }
+    }
+}
+
+impl<T: Ord + Clone, V: Clone + PartialEq, P: PointerKind> RangeMap<T, V, P> {
+    /// Returns a new copy of the map with the range mapped to the value. The overlapped parts
+    /// of the existing ranges are overwritten, and the range is merged with the adjacent ranges
+    /// of the equal value. Inserting an empty range, where `start >= end`, returns an unchanged
+    /// copy of the map. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeMap;
+    ///
+    /// let map = RangeMap::new().insert((0, 4), 'a').insert((6, 8), 'a');
+    ///
+    /// let filled = map.insert((4, 6), 'a');
+    /// assert_eq!(vec![(&(0, 8), &'a')], filled.iter().collect::<Vec<_>>());
+    ///
+    /// let overwritten = map.insert((2, 7), 'b');
+    /// assert_eq!(vec![(&(0, 2), &'a'), (&(2, 7), &'b'), (&(7, 8), &'a')],
+    ///            overwritten.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn insert(&self, range: (T, T), value: V) -> RangeMap<T, V, P> {
+        let (mut start, mut end) = range;
+        if start >= end {
+            return self.clone();
+        }
+
+        let mut root = self.remove(&start, &end).root;
+
+        let left = match tree::find_last(&root, |k| k.0 < start) {
+            Some(elem) if (elem.0).1 == start && elem.1 == value => Some(elem.0.clone()),
+            _ => None
+        };
+        if let Some(key) = left {
+            root = tree::remove(&root, &key, &ByStart).unwrap().0;
+            start = key.0;
+        }
+
+        let right = match tree::find_exact(&root, |k| end.cmp(&k.0)) {
+            Some(elem) if elem.1 == value => Some(elem.0.clone()),
+            _ => None
+        };
+        if let Some(key) = right {
+            root = tree::remove(&root, &key, &ByStart).unwrap().0;
+            end = key.1;
+        }
+
+        RangeMap { root: Some(NodeRef::new(tree::insert(&root, ((start, end), value), &ByStart))) }
+    }
+}
+
+impl<T, V, P: PointerKind> Clone for RangeMap<T, V, P> {
+    fn clone(&self) -> RangeMap<T, V, P> {
+        RangeMap { root: self.root.clone() }
+    }
+}
+
+impl<T, V, P: PointerKind> Default for RangeMap<T, V, P> {
+    fn default() -> RangeMap<T, V, P> {
+        RangeMap { root: None }
+    }
+}
+
+impl<T: Debug, V: Debug, P: PointerKind> Debug for RangeMap<T, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'r, T, V, P: PointerKind> IntoIterator for &'r RangeMap<T, V, P> {
+    type Item = (&'r (T, T), &'r V);
+    type IntoIter = RangeMapIter<'r, T, V, P>;
+
+    fn into_iter(self) -> RangeMapIter<'r, T, V, P> {
+        self.iter()
+    }
+}
+
+impl<T: PartialEq, V: PartialEq, P: PointerKind> PartialEq for RangeMap<T, V, P> {
+    fn eq(&self, other: &RangeMap<T, V, P>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, V: Eq, P: PointerKind> Eq for RangeMap<T, V, P> {}
+
+/// Inserts the ranges in order, so the later ranges overwrite the earlier ones.
+impl<T, V, P: PointerKind> FromIterator<((T, T), V)> for RangeMap<T, V, P>
+    where T: Ord + Clone, V: Clone + PartialEq
+{
+    fn from_iter<I>(iter: I) -> RangeMap<T, V, P> where I: IntoIterator<Item=((T, T), V)> {
+        let mut m = RangeMap { root: None };
+        for (range, v) in iter {
+            m = m.insert(range, v);
+        }
+        m
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tree::balanced;
+
+    use super::RangeMap;
+
+    #[test]
+    fn test_remove_inside() {
+        let map = RangeMap::new().insert((0, 10), 'a').insert((20, 30), 'b');
+
+        let removed = map.remove(&5, &25);
+        assert_eq!(vec![(&(0, 5), &'a'), (&(25, 30), &'b')],
+                   removed.iter().collect::<Vec<_>>());
+
+        let split = map.remove(&3, &4);
+        assert_eq!(vec![(&(0, 3), &'a'), (&(4, 10), &'a'), (&(20, 30), &'b')],
+                   split.iter().collect::<Vec<_>>());
+
+        assert_eq!(map, map.remove(&10, &20));
+        assert_eq!(map, map.remove(&5, &5));
+    }
+
+    #[test]
+    fn test_coalesce_only_equal_values() {
+        let map = RangeMap::new().insert((0, 2), 'a').insert((2, 4), 'b').insert((4, 6), 'b');
+
+        assert_eq!(vec![(&(0, 2), &'a'), (&(2, 6), &'b')], map.iter().collect::<Vec<_>>());
+        assert_eq!(1, map.insert((2, 6), 'a').len());
+    }
+
+    #[test]
+    fn test_gaps_inside_range() {
+        let map = RangeMap::new().insert((0, 10), ());
+
+        assert_eq!(0, map.gaps(&2, &8).count());
+        assert_eq!(vec![(10, 12)], map.gaps(&2, &12).collect::<Vec<_>>());
+        assert_eq!(0, map.gaps(&5, &5).count());
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let map: RangeMap<_, _> = (0..100).map(|i| ((i, i + 1), i / 10)).collect();
+
+        assert!(balanced(&map.root));
+        assert_eq!(10, map.len());
+        assert_eq!(Some((&(30, 40), &3)), map.get_range(&35));
+    }
+
+    #[test]
+    fn test_overwrite_middle() {
+        let map = RangeMap::new().insert((0, 10), 'a');
+
+        // overwriting splits the range, and restoring the value merges it back
+        let overwritten = map.insert((3, 5), 'b');
+        assert_eq!(vec![(&(0, 3), &'a'), (&(3, 5), &'b'), (&(5, 10), &'a')],
+                   overwritten.iter().collect::<Vec<_>>());
+        assert_eq!(map, overwritten.insert((3, 5), 'a'));
+        assert_eq!(Some(&'b'), overwritten.get(&4));
+    }
+}
+
+#[cfg(test)]
+mod quickcheck {
+    use rangemap::RangeMap;
+    use tree::balanced;
+
+    const SIZE: usize = 64;
+
+    // applies the operations to a map and to an array of the values of the points
+    fn build(ops: &[(bool, u8, u8, u8)]) -> (RangeMap<u8, u8>, Vec<Option<u8>>) {
+        let mut map = RangeMap::new();
+        let mut model = vec![None; SIZE];
+        for &(insert, a, b, v) in ops {
+            let (start, end) = (a % SIZE as u8, b % SIZE as u8);
+            let value = v % 3;
+            if insert {
+                map = map.insert((start, end), value);
+            } else {
+                map = map.remove(&start, &end);
+            }
+            for p in start..end {
+                model[p as usize] = if insert { Some(value) } else { None };
+            }
+        }
+        (map, model)
+    }
+
+    // checks that the ranges are non-empty, sorted, and merged when adjacent with equal values
+    fn canonical(map: &RangeMap<u8, u8>) -> bool {
+        let ranges: Vec<_> = map.iter().collect();
+        ranges.iter().all(|r| (r.0).0 < (r.0).1)
+            && ranges.windows(2).all(|w| {
+                (w[0].0).1 < (w[1].0).0 || ((w[0].0).1 == (w[1].0).0 && w[0].1 != w[1].1)
+            })
+    }
+
+    quickcheck! {
+        fn check_insert_remove(ops: Vec<(bool, u8, u8, u8)>) -> bool {
+            let (map, model) = build(&ops);
+            balanced(&map.root) && canonical(&map)
+                && (0..SIZE as u8).all(|p| map.get(&p) == model[p as usize].as_ref())
+        }
+
+        fn check_gaps(ops: Vec<(bool, u8, u8, u8)>, a: u8, b: u8) -> bool {
+            let (map, model) = build(&ops);
+            let (start, end) = (a % SIZE as u8, b % SIZE as u8);
+
+            let mut expected: Vec<(u8, u8)> = Vec::new();
+            for p in start..end {
+                if model[p as usize].is_some() {
+                    continue;
+                }
+                if let Some(last) = expected.last_mut() {
+                    if last.1 == p {
+                        last.1 = p + 1;
+                        continue;
+                    }
+                }
+                expected.push((p, p + 1));
+            }
+            map.gaps(&start, &end).collect::<Vec<_>>() == expected
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::FromIterator;
+
+use tree;
+use rangemap::{RangeMap, RangeMapGaps};
+use pointer::{PointerKind, RcKind, ArcKind};
+
+/// An immutable set of disjoint half-open ranges, based on weight-balanced binary tree.
+///
+/// A range is a pair `(start, end)`, which contains the points from `start` inclusive to `end`
+/// exclusive. The overlapping and adjacent ranges are merged on insertion, and removing a range
+/// splits the partially removed ones, so the set always holds the fewest ranges covering its
+/// points. This is a `RangeMap` whose values are all `()`.
+///
+/// An empty range, where `start >= end`, contains no points. Inserting or removing an empty
+/// range leaves the set unchanged, and the gaps of an empty range are empty.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::RangeSet;
+///
+/// let free = RangeSet::new().insert((0, 256));
+///
+/// // allocating splits the free range, and releasing merges it back
+/// let allocated = free.remove(&16, &32);
+/// assert_eq!(vec![&(0, 16), &(32, 256)], allocated.iter().collect::<Vec<_>>());
+/// assert_eq!(free, allocated.insert((16, 32)));
+/// ```
+pub struct RangeSet<T, P: PointerKind = RcKind> {
+    map: RangeMap<T, (), P>
+}
+
+/// The `RangeSet` over an `ArcRangeMap`, for the sets sent to other threads.
+pub type ArcRangeSet<T> = RangeSet<T, ArcKind>;
+
+pub type RangeSetIter<'r, T, P = RcKind> = tree::Keys<tree::Iter<'r, (T, T), (), P>>;
+pub type RangeSetGaps<'r, T, P = RcKind> = RangeMapGaps<'r, T, (), P>;
+
+impl<T> RangeSet<T> {
+    /// Makes a new empty RangeSet
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new();
+    /// let new_set = set.insert((1, 2));
+    /// ```
+    pub fn new() -> RangeSet<T> {
+        RangeSet { map: RangeMap::new() }
+    }
+}
+
+impl<T, P: PointerKind> RangeSet<T, P> {
+    /// Returns the number of disjoint ranges in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new().insert((1, 3)).insert((3, 5)).insert((6, 8));
+    /// assert_eq!(2, set.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the set contains no ranges.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let empty_set = RangeSet::new();
+    /// let new_set = empty_set.insert((1, 2));
+    ///
+    /// assert!(empty_set.is_empty());
+    /// assert!(!new_set.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Gets an iterator over the ranges of the set, in sorted order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new().insert((4, 6)).insert((1, 3));
+    ///
+    /// assert_eq!(vec![&(1, 3), &(4, 6)], set.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn iter<'r>(&'r self) -> RangeSetIter<'r, T, P> {
+        tree::Keys::new(self.map.iter())
+    }
+}
+
+impl<T: Ord, P: PointerKind> RangeSet<T, P> {
+    /// Returns the range containing the given point.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new().insert((1, 3)).insert((5, 7));
+    ///
+    /// assert_eq!(Some(&(5, 7)), set.get_range(&6));
+    /// assert_eq!(None, set.get_range(&3));
+    /// ```
+    pub fn get_range(&self, point: &T) -> Option<&(T, T)> {
+        self.map.get_range(point).map(|p| p.0)
+    }
+
+    /// Returns true if any range of the set contains the given point.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new().insert((1, 3));
+    ///
+    /// assert!(set.contains(&1));
+    /// assert!(!set.contains(&3));
+    /// ```
+    pub fn contains(&self, point: &T) -> bool {
+        self.map.contains(point)
+    }
+}
+
+impl<T: Ord + Clone, P: PointerKind> RangeSet<T, P> {
+    /// Gets an iterator over the maximal parts of the range from `start` inclusive to `end`
+    /// exclusive which are not covered by the set. The iterator yields nothing if the range is
+    /// empty, that is, `start >= end`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new().insert((2, 4)).insert((6, 8));
+    ///
+    /// assert_eq!(vec![(0, 2), (4, 6), (8, 10)], set.gaps(&0, &10).collect::<Vec<_>>());
+    /// ```
+    pub fn gaps<'r>(&'r self, start: &T, end: &T) -> RangeSetGaps<'r, T, P> {
+        self.map.gaps(start, end)
+    }
+
+    /// Returns a new copy of the set with the range added. The range is merged with the
+    /// overlapping and adjacent ranges of the set. Inserting an empty range, where
+    /// `start >= end`, returns an unchanged copy of the set. This operation takes O(log n)
+    /// time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new().insert((0, 2)).insert((4, 6)).insert((8, 10));
+    /// let new_set = set.insert((1, 5));
+    ///
+    /// assert_eq!(vec![&(0, 6), &(8, 10)], new_set.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn insert(&self, range: (T, T)) -> RangeSet<T, P> {
+        RangeSet { map: self.map.insert(range, ()) }
+    }
+
+    /// Returns a new copy of the set with the points from `start` inclusive to `end` exclusive
+    /// removed. The ranges partially overlapping the removed range are split. Removing an empty
+    /// range, where `start >= end`, returns an unchanged copy of the set. This operation takes
+    /// O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::RangeSet;
+    ///
+    /// let set = RangeSet::new().insert((0, 10));
+    /// let new_set = set.remove(&3, &5);
+    ///
+    /// assert_eq!(vec![&(0, 3), &(5, 10)], new_set.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn remove(&self, start: &T, end: &T) -> RangeSet<T, P> {
+        RangeSet { map: self.map.remove(start, end) }
+    }
+}
+
+impl<T, P: PointerKind> Clone for RangeSet<T, P> {
+    fn clone(&self) -> RangeSet<T, P> {
+        RangeSet { map: self.map.clone() }
+    }
+}
+
+impl<T, P: PointerKind> Default for RangeSet<T, P> {
+    fn default() -> RangeSet<T, P> {
+        RangeSet { map: RangeMap::default() }
+    }
+}
+
+impl<T: Debug, P: PointerKind> Debug for RangeSet<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<'r, T, P: PointerKind> IntoIterator for &'r RangeSet<T, P> {
+    type Item = &'r (T, T);
+    type IntoIter = RangeSetIter<'r, T, P>;
+
+    fn into_iter(self) -> RangeSetIter<'r, T, P> {
+        self.iter()
+    }
+}
+
+impl<T: PartialEq, P: PointerKind> PartialEq for RangeSet<T, P> {
+    fn eq(&self, other: &RangeSet<T, P>) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<T: Eq, P: PointerKind> Eq for RangeSet<T, P> {}
+
+impl<T: Ord + Clone, P: PointerKind> FromIterator<(T, T)> for RangeSet<T, P> {
+    fn from_iter<I>(iter: I) -> RangeSet<T, P> where I: IntoIterator<Item=(T, T)> {
+        RangeSet { map: iter.into_iter().map(|range| (range, ())).collect() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RangeSet;
+
+    #[test]
+    fn test_merge_overlapping() {
+        let set: RangeSet<_> = vec![(5, 8), (0, 3), (2, 6), (10, 12), (12, 15)].into_iter()
+            .collect();
+
+        assert_eq!(vec![&(0, 8), &(10, 15)], set.iter().collect::<Vec<_>>());
+        assert_eq!(vec![(8, 10)], set.gaps(&0, &15).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_fill_gap() {
+        let set = RangeSet::new().insert((0, 2)).insert((4, 6)).insert((8, 10));
+
+        // filling a gap exactly merges the ranges on both sides
+        let filled = set.insert((2, 4));
+        assert_eq!(vec![&(0, 6), &(8, 10)], filled.iter().collect::<Vec<_>>());
+        assert_eq!(Some(&(0, 6)), filled.get_range(&3));
+        assert_eq!(set, filled.remove(&2, &4));
+    }
+}
+
+#[cfg(test)]
+mod quickcheck {
+    use rangeset::RangeSet;
+
+    quickcheck! {
+        fn check_points(ops: Vec<(bool, u8, u8)>) -> bool {
+            let mut set = RangeSet::new();
+            let mut model = [false; 256];
+            for (insert, start, end) in ops {
+                set = if insert { set.insert((start, end)) } else { set.remove(&start, &end) };
+                for p in start..end {
+                    model[p as usize] = insert;
+                }
+            }
+
+            let covered: usize = set.iter().map(|r| (r.1 - r.0) as usize).sum();
+            let gaps: usize = set.gaps(&0, &255).map(|r| (r.1 - r.0) as usize).sum();
+            (0..255).all(|p| set.contains(&p) == model[p as usize])
+                && covered == model.iter().filter(|&&b| b).count()
+                && gaps == model[..255].iter().filter(|&&b| !b).count()
+        }
+    }
+}
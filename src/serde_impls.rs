@@ -0,0 +1,217 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer, SerializeMap, SerializeSeq};
+
+use augmented::AugmentedMap;
+use compare::Compare;
+use map::TreeMap;
+use measure::Measure;
+use pointer::PointerKind;
+use set::TreeSet;
+
+// the maximum capacity preallocated from the size hint of the input, which may be forged
+const MAX_PREALLOCATION: usize = 4096;
+
+impl<K, V, P: PointerKind, C> Serialize for TreeMap<K, V, P, C>
+    where K: Serialize, V: Serialize
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<V, P: PointerKind, C> Serialize for TreeSet<V, P, C> where V: Serialize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for v in self.iter() {
+            seq.serialize_element(v)?;
+        }
+        seq.end()
+    }
+}
+
+impl<K, V, A: Measure<K, V>, P: PointerKind, C> Serialize for AugmentedMap<K, V, A, P, C>
+    where K: Serialize, V: Serialize
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+// collects the entries of the input, and builds the map with `FromIterator`. the map is built in
+// O(n) time if the keys are sorted, and the entries are inserted one by one otherwise. the last
+// value is kept for the duplicated keys.
+#[allow(clippy::type_complexity)]
+struct MapVisitor<K, V, T> {
+    marker: PhantomData<fn() -> (K, V, T)>
+}
+
+impl<'de, K, V, T> Visitor<'de> for MapVisitor<K, V, T>
+    where K: Deserialize<'de>, V: Deserialize<'de>, T: FromIterator<(K, V)>
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<T, M::Error> {
+        let capacity = access.size_hint().unwrap_or(0).min(MAX_PREALLOCATION);
+        let mut elems = Vec::with_capacity(capacity);
+        while let Some(elem) = access.next_entry()? {
+            elems.push(elem);
+        }
+        Ok(elems.into_iter().collect())
+    }
+}
+
+// collects the elements of the input, and builds the set with `FromIterator` in the same way as
+// `MapVisitor`.
+struct SeqVisitor<V, T> {
+    marker: PhantomData<fn() -> (V, T)>
+}
+
+impl<'de, V, T> Visitor<'de> for SeqVisitor<V, T>
+    where V: Deserialize<'de>, T: FromIterator<V>
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut access: S) -> Result<T, S::Error> {
+        let capacity = access.size_hint().unwrap_or(0).min(MAX_PREALLOCATION);
+        let mut elems = Vec::with_capacity(capacity);
+        while let Some(elem) = access.next_element()? {
+            elems.push(elem);
+        }
+        Ok(elems.into_iter().collect())
+    }
+}
+
+impl<'de, K, V, P: PointerKind, C> Deserialize<'de> for TreeMap<K, V, P, C>
+    where K: Deserialize<'de> + Clone, V: Deserialize<'de> + Clone,
+          C: Compare<K> + Clone + Default
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TreeMap<K, V, P, C>, D::Error> {
+        deserializer.deserialize_map(MapVisitor { marker: PhantomData })
+    }
+}
+
+impl<'de, V, P: PointerKind, C> Deserialize<'de> for TreeSet<V, P, C>
+    where V: Deserialize<'de> + Clone, C: Compare<V> + Clone + Default
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TreeSet<V, P, C>, D::Error> {
+        deserializer.deserialize_seq(SeqVisitor { marker: PhantomData })
+    }
+}
+
+impl<'de, K, V, A: Measure<K, V>, P: PointerKind, C> Deserialize<'de>
+    for AugmentedMap<K, V, A, P, C>
+    where K: Deserialize<'de> + Clone, V: Deserialize<'de> + Clone,
+          C: Compare<K> + Clone + Default
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D)
+        -> Result<AugmentedMap<K, V, A, P, C>, D::Error>
+    {
+        deserializer.deserialize_map(MapVisitor { marker: PhantomData })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_test::{Token, assert_tokens, assert_de_tokens};
+
+    use map::{TreeMap, ArcTreeMap};
+    use set::TreeSet;
+    use augmented::AugmentedMap;
+
+    #[test]
+    fn test_map_tokens() {
+        let map = TreeMap::new().insert(2, 'b').insert(1, 'a');
+
+        assert_tokens(&map, &[
+            Token::Map { len: Some(2) },
+            Token::I32(1),
+            Token::Char('a'),
+            Token::I32(2),
+            Token::Char('b'),
+            Token::MapEnd
+        ]);
+    }
+
+    #[test]
+    fn test_map_unsorted_input() {
+        let map: ArcTreeMap<i32, char> = ArcTreeMap::default().insert(1, 'a').insert(2, 'c');
+
+        // unsorted input is accepted, and the last value of a duplicated key is kept
+        assert_de_tokens(&map, &[
+            Token::Map { len: Some(3) },
+            Token::I32(2),
+            Token::Char('b'),
+            Token::I32(1),
+            Token::Char('a'),
+            Token::I32(2),
+            Token::Char('c'),
+            Token::MapEnd
+        ]);
+    }
+
+    #[test]
+    fn test_set_tokens() {
+        let set = TreeSet::new().insert(3).insert(1).insert(2);
+
+        assert_tokens(&set, &[
+            Token::Seq { len: Some(3) },
+            Token::I32(1),
+            Token::I32(2),
+            Token::I32(3),
+            Token::SeqEnd
+        ]);
+        assert_de_tokens(&set, &[
+            Token::Seq { len: Some(4) },
+            Token::I32(3),
+            Token::I32(1),
+            Token::I32(3),
+            Token::I32(2),
+            Token::SeqEnd
+        ]);
+    }
+
+    #[test]
+    fn test_augmented_map_tokens() {
+        let map: AugmentedMap<i32, char, ()> = AugmentedMap::new().insert(1, 'a');
+
+        assert_tokens(&map, &[
+            Token::Map { len: Some(1) },
+            Token::I32(1),
+            Token::Char('a'),
+            Token::MapEnd
+        ]);
+    }
+
+    #[test]
+    fn test_sorted_input() {
+        let map: TreeMap<i32, i32> = (0..100).map(|i| (i, i * i)).collect();
+        let mut tokens = vec![Token::Map { len: Some(100) }];
+        for i in 0..100 {
+            tokens.push(Token::I32(i));
+            tokens.push(Token::I32(i * i));
+        }
+        tokens.push(Token::MapEnd);
+
+        assert_tokens(&map, &tokens);
+    }
+}
@@ -2,12 +2,14 @@ use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
 use std::iter::{FromIterator, Peekable};
-use std::rc::Rc;
 
 use tree;
-use tree::TreeNode;
+use tree::NodeRef;
 use Bound;
+use compare::{Compare, Natural};
+use pointer::{PointerKind, RcKind, ArcKind};
 
 /// An immutable set based on weight-balanced binary tree.
 /// See https://yoichihirai.com/bst.pdf for the balancing algorithm.
@@ -27,18 +29,79 @@ use Bound;
 /// assert!(!set_1.contains(&4));
 /// assert!(set_2.contains(&4));
 /// ```
-#[derive(Clone, Default)]
-pub struct TreeSet<V> {
-    root: Option<Rc<TreeNode<V, ()>>>,
+pub struct TreeSet<V, P: PointerKind = RcKind, C = Natural> {
+    root: Option<NodeRef<V, (), P>>,
+    cmp: C
 }
 
-pub type TreeSetIter<'r, V> = tree::Keys<tree::Iter<'r, V, ()>>;
-pub type TreeSetRevIter<'r, V> = tree::Keys<tree::RevIter<'r, V, ()>>;
-pub type TreeSetRange<'r, V> = tree::Keys<tree::Range<'r, V, ()>>;
+/// A thread-safe variant of `TreeSet`, which shares its nodes with `Arc` instead of `Rc`.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+/// use immutable_map::ArcTreeSet;
+///
+/// let set = ArcTreeSet::default().insert(1).insert(2);
+///
+/// let set_in_thread = set.clone();
+/// let handle = thread::spawn(move || set_in_thread.insert(3));
+/// let new_set = handle.join().unwrap();
+///
+/// assert_eq!(2, set.len());
+/// assert!(new_set.contains(&3));
+/// ```
+pub type ArcTreeSet<V> = TreeSet<V, ArcKind>;
+
+pub type TreeSetIter<'r, V, P = RcKind> = tree::Keys<tree::Iter<'r, V, (), P>>;
+pub type TreeSetRevIter<'r, V, P = RcKind> = tree::Keys<tree::RevIter<'r, V, (), P>>;
+pub type TreeSetRange<'r, V, P = RcKind, C = Natural> = tree::Keys<tree::Range<'r, V, (), P, C>>;
+pub type TreeSetIntoIter<V, P = RcKind> = tree::IntoKeys<tree::IntoIter<V, (), P>>;
+
+/// A cursor over the elements of a `TreeSet`, which can move in both directions.
+///
+/// A cursor points to an element of the set, or to the "ghost" position, which is after the last
+/// element and before the first element. Moving the cursor takes amortized O(1) time, and
+/// O(log n) time in the worst case.
+pub struct TreeSetCursor<'r, V: 'r, P: PointerKind = RcKind> {
+    cursor: tree::Cursor<'r, V, (), P>
+}
+
+impl<'r, V: 'r, P: PointerKind> TreeSetCursor<'r, V, P> {
+    /// Returns the current element, or `None` at the ghost position.
+    pub fn value(&self) -> Option<&'r V> {
+        self.cursor.key()
+    }
+
+    /// Returns the position of the current element in the set, or `None` at the ghost position.
+    pub fn rank(&self) -> Option<usize> {
+        self.cursor.rank()
+    }
+
+    /// Moves to the next element. Moving from the ghost position goes to the first element, and
+    /// moving from the last element goes to the ghost position.
+    pub fn move_next(&mut self) {
+        self.cursor.move_next()
+    }
+
+    /// Moves to the previous element. Moving from the ghost position goes to the last element,
+    /// and moving from the first element goes to the ghost position.
+    pub fn move_prev(&mut self) {
+        self.cursor.move_prev()
+    }
+}
+
+impl<'r, V: 'r, P: PointerKind> Clone for TreeSetCursor<'r, V, P> {
+    fn clone(&self) -> TreeSetCursor<'r, V, P> {
+        TreeSetCursor { cursor: self.cursor.clone() }
+    }
+}
 
 impl<V> TreeSet<V> {
     /// Makes a new empty TreeSet
     ///
+    /// The sets shared with `Arc` are made with `ArcTreeSet::default()`.
+    ///
     /// # Examples
     ///
     /// ```
@@ -48,8 +111,32 @@ impl<V> TreeSet<V> {
     /// let new_set = set.insert(1);
     /// ```
     pub fn new() -> TreeSet<V> {
-        TreeSet { root: None }
+        TreeSet { root: None, cmp: Natural }
     }
+}
+
+impl<V, P: PointerKind, C> TreeSet<V, P, C> {
+    /// Makes a new empty TreeSet which orders the values with the given comparator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    /// use immutable_map::pointer::{RcKind, ArcKind};
+    ///
+    /// let rev = |a: &i32, b: &i32| b.cmp(a);
+    /// let set: TreeSet<_, RcKind, _> = TreeSet::with_comparator(rev).insert(1).insert(2);
+    /// let arc_set: TreeSet<_, ArcKind, _> = TreeSet::with_comparator(rev).insert(1).insert(2);
+    ///
+    /// assert_eq!(vec![&2, &1], set.iter().collect::<Vec<_>>());
+    /// assert_eq!(vec![&2, &1], arc_set.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn with_comparator(cmp: C) -> TreeSet<V, P, C> {
+        TreeSet { root: None, cmp }
+    }
+}
+
+impl<V, P: PointerKind, C> TreeSet<V, P, C> {
 
     /// Returns the number of elements in the set.
     ///
@@ -98,7 +185,7 @@ impl<V> TreeSet<V> {
     /// let first_value = set.iter().next().unwrap();
     /// assert_eq!(1, *first_value);
     /// ```
-    pub fn iter<'r>(&'r self) -> TreeSetIter<'r, V> {
+    pub fn iter<'r>(&'r self) -> TreeSetIter<'r, V, P> {
         tree::Keys::new(tree::Iter::new(&self.root))
     }
 
@@ -118,26 +205,142 @@ impl<V> TreeSet<V> {
     /// let first_value = set.rev_iter().next().unwrap();
     /// assert_eq!(3, *first_value);
     /// ```
-    pub fn rev_iter<'r>(&'r self) -> TreeSetRevIter<'r, V> {
-        tree::Keys::new(tree::RevIter::new(&self.root))
+    pub fn rev_iter<'r>(&'r self) -> TreeSetRevIter<'r, V, P> {
+        tree::Keys::new(tree::Iter::new(&self.root).rev())
+    }
+
+    /// Returns the value at the given position in the order of the set, or `None` if the index
+    /// is out of bounds. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(2).insert(3).insert(1);
+    ///
+    /// assert_eq!(Some(&2), set.get_index(1));
+    /// assert_eq!(None, set.get_index(3));
+    /// ```
+    pub fn get_index(&self, index: usize) -> Option<&V> {
+        tree::find_index(&self.root, index).map(|p| &p.0)
     }
 }
 
-impl<V: Ord> TreeSet<V> {
+impl<V, P: PointerKind, C> TreeSet<V, P, C> where C: Clone {
+    // makes the set with the given root, which orders the values with the comparator of this set
+    fn with_root(&self, root: Option<NodeRef<V, (), P>>) -> TreeSet<V, P, C> {
+        TreeSet { root, cmp: self.cmp.clone() }
+    }
+}
+
+impl<V, P: PointerKind, C> TreeSet<V, P, C> where C: Compare<V> {
     /// Returns a reference to the value in the set, if any, that is equal to the given value.
     ///
     /// The value may be any borrowed form of the set's value type, but the ordering on the
     /// borrowed form must match the ordering on the value type.
-    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
-        where V: Borrow<Q>
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+        where V: Borrow<Q>, C: Compare<Q>
     {
-        fn f<'r, V: Borrow<Q>, Q: Ord + ?Sized>(node: &'r Option<Rc<TreeNode<V, ()>>>, key: &Q)
-                -> Option<&'r V>
+        fn f<'r, V: Borrow<Q>, P: PointerKind, Q: ?Sized, C>(node: &'r Option<NodeRef<V, (), P>>,
+                                                          key: &Q,
+                                                          cmp: &C)
+                -> Option<&'r V> where C: Compare<Q>
         {
-            tree::find_exact(node, |x| key.cmp(x.borrow())).map(|p| &p.0)
+            tree::find_exact(node, |x| cmp.compare(key, x.borrow())).map(|p| &p.0)
         }
 
-        f(&self.root, key)
+        f(&self.root, key, &self.cmp)
+    }
+
+    /// Returns the greatest value not larger than the given value.
+    ///
+    /// The value may be any borrowed form of the set's value type, but the ordering on the
+    /// borrowed form must match the ordering on the value type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(10).insert(20);
+    ///
+    /// assert_eq!(Some(&10), set.get_le(&15));
+    /// assert_eq!(Some(&20), set.get_le(&20));
+    /// assert_eq!(None, set.get_le(&5));
+    /// ```
+    pub fn get_le<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_last(&self.root, |v| self.cmp.compare(v.borrow(), key) != Ordering::Greater)
+            .map(|p| &p.0)
+    }
+
+    /// Returns the greatest value smaller than the given value.
+    ///
+    /// The value may be any borrowed form of the set's value type, but the ordering on the
+    /// borrowed form must match the ordering on the value type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(10).insert(20);
+    ///
+    /// assert_eq!(Some(&10), set.get_lt(&20));
+    /// assert_eq!(None, set.get_lt(&10));
+    /// ```
+    pub fn get_lt<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_last(&self.root, |v| self.cmp.compare(v.borrow(), key) == Ordering::Less)
+            .map(|p| &p.0)
+    }
+
+    /// Returns the smallest value not smaller than the given value.
+    ///
+    /// The value may be any borrowed form of the set's value type, but the ordering on the
+    /// borrowed form must match the ordering on the value type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(10).insert(20);
+    ///
+    /// assert_eq!(Some(&20), set.get_ge(&15));
+    /// assert_eq!(Some(&10), set.get_ge(&10));
+    /// assert_eq!(None, set.get_ge(&25));
+    /// ```
+    pub fn get_ge<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_first(&self.root, |v| self.cmp.compare(v.borrow(), key) != Ordering::Less)
+            .map(|p| &p.0)
+    }
+
+    /// Returns the smallest value larger than the given value.
+    ///
+    /// The value may be any borrowed form of the set's value type, but the ordering on the
+    /// borrowed form must match the ordering on the value type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(10).insert(20);
+    ///
+    /// assert_eq!(Some(&20), set.get_gt(&10));
+    /// assert_eq!(None, set.get_gt(&20));
+    /// ```
+    pub fn get_gt<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::find_first(&self.root, |v| self.cmp.compare(v.borrow(), key) == Ordering::Greater)
+            .map(|p| &p.0)
     }
 
     /// Returns true if the value is in the set, if any, that is equal to the given value.
@@ -155,16 +358,39 @@ impl<V: Ord> TreeSet<V> {
     /// assert_eq!(true, set.contains("Two"));
     /// assert_eq!(false, set.contains("Four"));
     /// ```
-    pub fn contains<Q: Ord + ?Sized>(&self, key: &Q) -> bool
-        where V: Borrow<Q>
+    pub fn contains<Q: ?Sized>(&self, key: &Q) -> bool
+        where V: Borrow<Q>, C: Compare<Q>
     {
         self.get(key).is_some()
     }
 
+    /// Returns the position of the value in the order of the set, or `None` if the set does not
+    /// contain the value. This operation takes O(log n) time.
+    ///
+    /// The value may be any borrowed form of the set's value type, but the ordering on the
+    /// borrowed form must match the ordering on the value type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(2).insert(3).insert(1);
+    ///
+    /// assert_eq!(Some(2), set.index_of(&3));
+    /// assert_eq!(None, set.index_of(&4));
+    /// ```
+    pub fn index_of<Q: ?Sized>(&self, key: &Q) -> Option<usize>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::rank(&self.root, key, &self.cmp)
+    }
+
     /// Constructs a double-ended iterator over a sub-range of elements in the set, starting at
     /// min, and ending at max. If min is Unbounded, then it will be treated as "negative
     /// infinity", and if max is Unbounded, then it will be treated as "positive infinity". Thus
-    /// range(Unbounded, Unbounded) will yield the whole collection.
+    /// range(Unbounded, Unbounded) will yield the whole collection. The length of the range is
+    /// computed in O(log n) time.
     ///
     /// # Examples
     ///
@@ -174,6 +400,8 @@ impl<V: Ord> TreeSet<V> {
     ///
     /// let set = TreeSet::new().insert(8).insert(3).insert(5);
     ///
+    /// assert_eq!(2, set.range(Included(&4), Included(&8)).len());
+    ///
     /// for elem in set.range(Included(&4), Included(&8)) {
     ///     println!("{}", elem);
     /// }
@@ -182,11 +410,205 @@ impl<V: Ord> TreeSet<V> {
     ///
     /// assert_eq!(values, [5, 8]);
     /// ```
-    pub fn range<'r, Q: Ord>(&'r self, min: Bound<&Q>, max: Bound<&Q>)
-            -> TreeSetRange<'r, V>
-        where V: Borrow<Q>
+    pub fn range<'r, Q>(&'r self, min: Bound<&Q>, max: Bound<&Q>)
+            -> TreeSetRange<'r, V, P, C>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::Keys::new(tree::Range::new(&self.root, min, max, &self.cmp))
+    }
+
+    /// Returns the number of the elements in the sub-range of the set, which is bounded by min
+    /// and max in the same way as `range`. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    /// use immutable_map::Bound::*;
+    ///
+    /// let set: TreeSet<_> = (0..100).map(|i| i * 2).collect();
+    ///
+    /// assert_eq!(5, set.range_count(Included(&10), Excluded(&20)));
+    /// assert_eq!(90, set.range_count(Excluded(&19), Unbounded));
+    /// ```
+    pub fn range_count<Q: ?Sized>(&self, min: Bound<&Q>, max: Bound<&Q>) -> usize
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::range_count(&self.root, min, max, &self.cmp)
+    }
+
+    /// Returns a cursor pointing to the first element not smaller than the given value. If there
+    /// is no such element, the cursor points to the ghost position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(10).insert(20).insert(30);
+    ///
+    /// let mut cursor = set.lower_bound(&15);
+    /// assert_eq!(Some(&20), cursor.value());
+    /// assert_eq!(Some(1), cursor.rank());
+    ///
+    /// cursor.move_prev();
+    /// assert_eq!(Some(&10), cursor.value());
+    /// cursor.move_prev();
+    /// assert_eq!(None, cursor.value());
+    /// cursor.move_prev();
+    /// assert_eq!(Some(&30), cursor.value());
+    /// ```
+    pub fn lower_bound<'r, Q: ?Sized>(&'r self, key: &Q) -> TreeSetCursor<'r, V, P>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        TreeSetCursor { cursor: tree::Cursor::lower_bound(&self.root, key, &self.cmp) }
+    }
+
+    /// Returns a cursor pointing to the first element larger than the given value. If there is
+    /// no such element, the cursor points to the ghost position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(10).insert(20).insert(30);
+    ///
+    /// assert_eq!(Some(&30), set.upper_bound(&20).value());
+    /// assert_eq!(None, set.upper_bound(&30).value());
+    /// ```
+    pub fn upper_bound<'r, Q: ?Sized>(&'r self, key: &Q) -> TreeSetCursor<'r, V, P>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        TreeSetCursor { cursor: tree::Cursor::upper_bound(&self.root, key, &self.cmp) }
+    }
+
+    /// Returns a cursor pointing to the given value, or `None` if the set does not contain the
+    /// value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(10).insert(20).insert(30);
+    ///
+    /// let mut cursor = set.find(&20).unwrap();
+    /// cursor.move_next();
+    /// assert_eq!(Some(&30), cursor.value());
+    ///
+    /// assert!(set.find(&15).is_none());
+    /// ```
+    pub fn find<'r, Q: ?Sized>(&'r self, key: &Q) -> Option<TreeSetCursor<'r, V, P>>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::Cursor::find(&self.root, key, &self.cmp)
+            .map(|cursor| TreeSetCursor { cursor })
+    }
+
+    /// Builds a set from the values in ascending order, in O(n) time.
+    ///
+    /// If the input has several equal values, the last one is kept in the set, as if the values
+    /// were inserted one by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the values are not in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set: TreeSet<_> = TreeSet::from_sorted_iter(vec![1, 2, 2, 3]);
+    ///
+    /// assert_eq!(vec![&1, &2, &3], set.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn from_sorted_iter<I>(iter: I) -> TreeSet<V, P, C>
+        where I: IntoIterator<Item=V>, C: Default
+    {
+        TreeSet::from_sorted_iter_by(iter, C::default())
+    }
+
+    /// Builds a set from the values in ascending order of the given comparator, in O(n) time.
+    ///
+    /// If the input has several equal values, the last one is kept in the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the values are not in ascending order of the comparator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let set: TreeSet<_, RcKind, _> =
+    ///     TreeSet::from_sorted_iter_by(vec![3, 2, 2, 1], |a: &i32, b: &i32| b.cmp(a));
+    ///
+    /// assert_eq!(vec![&3, &2, &1], set.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn from_sorted_iter_by<I>(iter: I, cmp: C) -> TreeSet<V, P, C>
+        where I: IntoIterator<Item=V>
+    {
+        let mut elems: Vec<(V, ())> = iter.into_iter().map(|v| (v, ())).collect();
+        assert!(tree::is_sorted(&elems, &cmp), "values are not in ascending order");
+        tree::dedup_sorted(&mut elems, &cmp);
+        TreeSet { root: tree::from_sorted_vec(elems), cmp }
+    }
+
+    /// Builds a set from the values in ascending order, in O(n) time.
+    ///
+    /// Returns `None` if the values are not in strictly ascending order, including when the input
+    /// has several equal values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set: TreeSet<_> = TreeSet::from_strictly_sorted_iter(vec![1, 2, 3]).unwrap();
+    /// assert!(set.contains(&2));
+    ///
+    /// assert!(TreeSet::<_>::from_strictly_sorted_iter(vec![1, 1, 2]).is_none());
+    /// assert!(TreeSet::<_>::from_strictly_sorted_iter(vec![2, 1]).is_none());
+    /// ```
+    pub fn from_strictly_sorted_iter<I>(iter: I) -> Option<TreeSet<V, P, C>>
+        where I: IntoIterator<Item=V>, C: Default
+    {
+        TreeSet::from_strictly_sorted_iter_by(iter, C::default())
+    }
+
+    /// Builds a set from the values in ascending order of the given comparator, in O(n) time.
+    ///
+    /// Returns `None` if the values are not in strictly ascending order of the comparator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    /// use immutable_map::pointer::RcKind;
+    ///
+    /// let rev = |a: &i32, b: &i32| b.cmp(a);
+    ///
+    /// let set: Option<TreeSet<_, RcKind, _>> =
+    ///     TreeSet::from_strictly_sorted_iter_by(vec![2, 1], rev);
+    /// assert!(set.unwrap().contains(&1));
+    ///
+    /// let unordered: Option<TreeSet<_, RcKind, _>> =
+    ///     TreeSet::from_strictly_sorted_iter_by(vec![1, 2], rev);
+    /// assert!(unordered.is_none());
+    /// ```
+    pub fn from_strictly_sorted_iter_by<I>(iter: I, cmp: C) -> Option<TreeSet<V, P, C>>
+        where I: IntoIterator<Item=V>
     {
-        tree::Keys::new(tree::Range::new(&self.root, min, max))
+        let elems: Vec<(V, ())> = iter.into_iter().map(|v| (v, ())).collect();
+        if tree::is_strictly_sorted(&elems, &cmp) {
+            Some(TreeSet { root: tree::from_sorted_vec(elems), cmp })
+        } else {
+            None
+        }
     }
 
     /// Visits the values representing the intersection, in ascending order.
@@ -202,10 +624,11 @@ impl<V: Ord> TreeSet<V> {
     /// let intersection: Vec<_> = a.intersection(&b).cloned().collect();
     /// assert_eq!(intersection, [2]);
     /// ```
-    pub fn intersection<'r>(&'r self, other: &'r TreeSet<V>) -> Intersection<'r, V> {
+    pub fn intersection<'r>(&'r self, other: &'r TreeSet<V, P, C>) -> Intersection<'r, V, P, C> {
         Intersection {
             a: tree::Iter::new(&self.root).peekable(),
-            b: tree::Iter::new(&other.root).peekable()
+            b: tree::Iter::new(&other.root).peekable(),
+            cmp: &self.cmp
         }
     }
 
@@ -222,10 +645,11 @@ impl<V: Ord> TreeSet<V> {
     /// let union: Vec<_> = a.union(&b).cloned().collect();
     /// assert_eq!(union, [1, 2, 3]);
     /// ```
-    pub fn union<'r>(&'r self, other: &'r TreeSet<V>) -> Union<'r, V> {
+    pub fn union<'r>(&'r self, other: &'r TreeSet<V, P, C>) -> Union<'r, V, P, C> {
         Union {
             a: tree::Iter::new(&self.root).peekable(),
-            b: tree::Iter::new(&other.root).peekable()
+            b: tree::Iter::new(&other.root).peekable(),
+            cmp: &self.cmp
         }
     }
 
@@ -242,10 +666,11 @@ impl<V: Ord> TreeSet<V> {
     /// let difference: Vec<_> = a.difference(&b).cloned().collect();
     /// assert_eq!(difference, [1]);
     /// ```
-    pub fn difference<'r>(&'r self, other: &'r TreeSet<V>) -> Difference<'r, V> {
+    pub fn difference<'r>(&'r self, other: &'r TreeSet<V, P, C>) -> Difference<'r, V, P, C> {
         Difference {
             a: tree::Iter::new(&self.root).peekable(),
-            b: tree::Iter::new(&other.root).peekable()
+            b: tree::Iter::new(&other.root).peekable(),
+            cmp: &self.cmp
         }
     }
 
@@ -262,10 +687,13 @@ impl<V: Ord> TreeSet<V> {
     /// let symm_diff: Vec<_> = a.symmetric_difference(&b).cloned().collect();
     /// assert_eq!(symm_diff, [1, 3]);
     /// ```
-    pub fn symmetric_difference<'r>(&'r self, other: &'r TreeSet<V>) -> SymmetricDifference<'r, V> {
+    pub fn symmetric_difference<'r>(&'r self, other: &'r TreeSet<V, P, C>)
+            -> SymmetricDifference<'r, V, P, C>
+    {
         SymmetricDifference {
             a: tree::Iter::new(&self.root).peekable(),
-            b: tree::Iter::new(&other.root).peekable()
+            b: tree::Iter::new(&other.root).peekable(),
+            cmp: &self.cmp
         }
     }
 
@@ -284,7 +712,7 @@ impl<V: Ord> TreeSet<V> {
     /// assert_eq!(false, a.is_disjoint(&b));
     /// assert_eq!(true, a.is_disjoint(&c));
     /// ```
-    pub fn is_disjoint(&self, other: &TreeSet<V>) -> bool {
+    pub fn is_disjoint(&self, other: &TreeSet<V, P, C>) -> bool {
         self.intersection(other).next().is_none()
     }
 
@@ -302,7 +730,7 @@ impl<V: Ord> TreeSet<V> {
     /// assert_eq!(true, a.is_subset(&sup));
     /// assert_eq!(false, b.is_subset(&sup));
     /// ```
-    pub fn is_subset(&self, other: &TreeSet<V>) -> bool {
+    pub fn is_subset(&self, other: &TreeSet<V, P, C>) -> bool {
         self.difference(other).next().is_none()
     }
 
@@ -320,12 +748,12 @@ impl<V: Ord> TreeSet<V> {
     /// assert_eq!(true, a.is_superset(&sub));
     /// assert_eq!(false, b.is_superset(&sub));
     /// ```
-    pub fn is_superset(&self, other: &TreeSet<V>) -> bool {
+    pub fn is_superset(&self, other: &TreeSet<V, P, C>) -> bool {
         other.difference(self).next().is_none()
     }
 }
 
-impl<V: Ord> TreeSet<V> where V: Clone {
+impl<V, P: PointerKind, C> TreeSet<V, P, C> where V: Clone, C: Compare<V> + Clone {
     /// Returns a new set with the value added to the set, replacing the existing value, if any.
     ///
     /// # Examples
@@ -339,10 +767,35 @@ impl<V: Ord> TreeSet<V> where V: Clone {
     /// assert_eq!(false, empty_set.contains(&3));
     /// assert_eq!(true, new_set.contains(&3));
     /// ```
-    pub fn insert(&self, value: V) -> TreeSet<V>
+    pub fn insert(&self, value: V) -> TreeSet<V, P, C>
     {
-        let root = tree::insert(&self.root, (value, ()));
-        TreeSet { root: Some(Rc::new(root)) }
+        let root = tree::insert(&self.root, (value, ()), &self.cmp);
+        self.with_root(Some(NodeRef::new(root)))
+    }
+
+    /// Inserts the value into the set in place. Returns true if the set did not have the value.
+    ///
+    /// The nodes which are not shared with other sets are modified in place, and only the shared
+    /// nodes are copied. Other sets sharing the nodes with this set are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let mut set = TreeSet::new();
+    /// assert!(set.insert_mut(1));
+    ///
+    /// let old_set = set.clone();
+    /// assert!(!set.insert_mut(1));
+    /// assert!(set.insert_mut(2));
+    ///
+    /// assert!(set.contains(&2));
+    /// assert!(!old_set.contains(&2));
+    /// ```
+    pub fn insert_mut(&mut self, value: V) -> bool
+    {
+        tree::insert_mut(&mut self.root, (value, ()), &self.cmp).is_none()
     }
 
     /// Return a new copy of `TreeSet` with the value inserted.
@@ -362,10 +815,10 @@ impl<V: Ord> TreeSet<V> where V: Clone {
     ///
     /// assert_eq!(true, new_set.contains(&1));
     /// ```
-    pub fn insert_if_absent(&self, value: V) -> Option<TreeSet<V>>
+    pub fn insert_if_absent(&self, value: V) -> Option<TreeSet<V, P, C>>
     {
-        tree::insert_if_absent(&self.root, (value, ())).map(|root|
-            TreeSet { root: Some(Rc::new(root)) }
+        tree::insert_if_absent(&self.root, (value, ()), &self.cmp).map(|root|
+            self.with_root(Some(NodeRef::new(root)))
         )
     }
 
@@ -386,12 +839,12 @@ impl<V: Ord> TreeSet<V> where V: Clone {
     /// assert_eq!(false, set.contains(&1));
     /// assert_eq!(&1, removed);
     /// ```
-    pub fn delete_min(&self) -> Option<(TreeSet<V>, &V)>
+    pub fn delete_min(&self) -> Option<(TreeSet<V, P, C>, &V)>
     {
         if let Some(ref root) = self.root {
-            let (new_root, v) = tree::delete_min(&root);
+            let (new_root, v) = tree::delete_min(root);
             Some((
-                TreeSet { root: new_root },
+                self.with_root(new_root),
                 &v.0
             ))
         } else {
@@ -416,12 +869,12 @@ impl<V: Ord> TreeSet<V> where V: Clone {
     /// assert_eq!(false, set.contains(&3));
     /// assert_eq!(&3, removed);
     /// ```
-    pub fn delete_max(&self) -> Option<(TreeSet<V>, &V)>
+    pub fn delete_max(&self) -> Option<(TreeSet<V, P, C>, &V)>
     {
         if let Some(ref root) = self.root {
-            let (new_root, v) = tree::delete_max(&root);
+            let (new_root, v) = tree::delete_max(root);
             Some((
-                TreeSet { root: new_root },
+                self.with_root(new_root),
                 &v.0
             ))
         } else {
@@ -433,86 +886,486 @@ impl<V: Ord> TreeSet<V> where V: Clone {
     ///
     /// Returns `None` if the original set did not contain the value
     ///
-    /// The value may be any borrowed form of the set's value type, but the ordering on the
-    /// borrowed form must match the ordering on the value type.
+    /// The value may be any borrowed form of the set's value type, but the ordering on the
+    /// borrowed form must match the ordering on the value type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let empty_set = TreeSet::new();
+    /// assert_eq!(None, empty_set.remove(&2));
+    ///
+    /// let set = empty_set.insert(2).insert(3).insert(1);
+    ///
+    /// let (new_set, removed) = set.remove(&2).unwrap();
+    ///
+    /// assert_eq!(false, new_set.contains(&2));
+    /// assert_eq!(&2, removed);
+    /// ```
+    pub fn remove<Q: ?Sized>(&self, key: &Q) -> Option<(TreeSet<V, P, C>, &V)>
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        tree::remove(&self.root, key, &self.cmp).map(|(new_root, v)|
+            (self.with_root(new_root), &v.0)
+        )
+    }
+
+    /// Removes the value from the set in place. Returns true if the set had the value.
+    ///
+    /// The nodes which are not shared with other sets are modified in place, and only the shared
+    /// nodes are copied. Other sets sharing the nodes with this set are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let mut set = TreeSet::new().insert(2).insert(3).insert(1);
+    /// let old_set = set.clone();
+    ///
+    /// assert!(set.remove_mut(&2));
+    /// assert!(!set.remove_mut(&2));
+    ///
+    /// assert!(!set.contains(&2));
+    /// assert!(old_set.contains(&2));
+    /// ```
+    pub fn remove_mut<Q: ?Sized>(&mut self, key: &Q) -> bool
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        self.contains(key) && tree::remove_mut(&mut self.root, key, &self.cmp).is_some()
+    }
+
+    /// Returns the new set with the value at the given position removed, and the removed value.
+    /// This operation takes O(log n) time.
+    ///
+    /// Returns `None` if the index is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(2).insert(3).insert(1);
+    ///
+    /// let (new_set, removed) = set.remove_index(1).unwrap();
+    ///
+    /// assert_eq!(false, new_set.contains(&2));
+    /// assert_eq!(&2, removed);
+    /// assert_eq!(None, set.remove_index(3));
+    /// ```
+    pub fn remove_index(&self, index: usize) -> Option<(TreeSet<V, P, C>, &V)>
+    {
+        tree::remove_index(&self.root, index).map(|(new_root, v)|
+            (self.with_root(new_root), &v.0)
+        )
+    }
+
+    /// Splits the set at the given position. Returns the set with the first `index` values, and
+    /// the set with the rest. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(2).insert(3).insert(1);
+    ///
+    /// let (left, right) = set.split_at(1);
+    ///
+    /// assert_eq!(vec![&1], left.iter().collect::<Vec<_>>());
+    /// assert_eq!(vec![&2, &3], right.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn split_at(&self, index: usize) -> (TreeSet<V, P, C>, TreeSet<V, P, C>)
+    {
+        let (left, right) = tree::split_at(&self.root, index);
+        (self.with_root(left), self.with_root(right))
+    }
+
+    /// Returns the set with the first `n` values. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(2).insert(3).insert(1);
+    ///
+    /// assert_eq!(vec![&1, &2], set.take(2).iter().collect::<Vec<_>>());
+    /// ```
+    pub fn take(&self, n: usize) -> TreeSet<V, P, C>
+    {
+        self.split_at(n).0
+    }
+
+    /// Returns the set without the first `n` values. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(2).insert(3).insert(1);
+    ///
+    /// assert_eq!(vec![&3], set.skip(2).iter().collect::<Vec<_>>());
+    /// ```
+    pub fn skip(&self, n: usize) -> TreeSet<V, P, C>
+    {
+        self.split_at(n).1
+    }
+
+    /// Splits the set at the given value. Returns the set with the values smaller than `key`,
+    /// the value equal to `key` if any, and the set with the values larger than `key`.
+    ///
+    /// This operation takes O(log n) time, and the resulting sets share most of their nodes
+    /// with the original set.
+    ///
+    /// The value may be any borrowed form of the set's value type, but the ordering on the
+    /// borrowed form must match the ordering on the value type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let set = TreeSet::new().insert(1).insert(2).insert(3).insert(4);
+    ///
+    /// let (left, found, right) = set.split(&2);
+    ///
+    /// assert_eq!(vec![&1], left.iter().collect::<Vec<_>>());
+    /// assert_eq!(Some(&2), found);
+    /// assert_eq!(vec![&3, &4], right.iter().collect::<Vec<_>>());
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn split<Q: ?Sized>(&self, key: &Q) -> (TreeSet<V, P, C>, Option<&V>, TreeSet<V, P, C>)
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        let (left, found, right) = tree::split(&self.root, key, &self.cmp);
+        (self.with_root(left), found.map(|p| &p.0), self.with_root(right))
+    }
+
+    /// Joins the two sets with the value in the middle, and returns the new set.
+    ///
+    /// This operation takes O(log n) time, and the resulting set shares most of its nodes
+    /// with `left` and `right`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any value of `left` is not smaller than `value`, or any value of `right` is not
+    /// larger than `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let left = TreeSet::new().insert(1);
+    /// let right = TreeSet::new().insert(3).insert(4);
+    ///
+    /// let set = TreeSet::join(&left, 2, &right);
+    ///
+    /// let values: Vec<_> = set.iter().cloned().collect();
+    /// assert_eq!(values, [1, 2, 3, 4]);
+    /// ```
+    pub fn join(left: &TreeSet<V, P, C>, value: V, right: &TreeSet<V, P, C>) -> TreeSet<V, P, C>
+    {
+        if let Some(ref l) = left.root {
+            assert!(left.cmp.compare(&tree::find_max(l).0, &value) == Ordering::Less,
+                    "left set has a value not smaller than the value");
+        }
+        if let Some(ref r) = right.root {
+            assert!(left.cmp.compare(&value, &tree::find_min(r).0) == Ordering::Less,
+                    "right set has a value not larger than the value");
+        }
+        left.with_root(Some(NodeRef::new(tree::join((value, ()), &left.root, &right.root))))
+    }
+
+    /// Returns a new set with the values of both `self` and `other`.
+    ///
+    /// Unlike `union`, this builds the resulting set directly from the subtrees of both sets,
+    /// which takes O(m log(n/m + 1)) time where m <= n are the sizes of the two sets. The
+    /// subtrees which are not affected by the operation are shared with the original sets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let a = TreeSet::new().insert(1).insert(2);
+    /// let b = TreeSet::new().insert(2).insert(3);
+    ///
+    /// let union = a.union_set(&b);
+    /// assert_eq!(vec![&1, &2, &3], union.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn union_set(&self, other: &TreeSet<V, P, C>) -> TreeSet<V, P, C> {
+        self.with_root(tree::union(&self.root, &other.root, &self.cmp))
+    }
+
+    /// Returns a new set with the values which are both in `self` and `other`.
+    ///
+    /// Unlike `intersection`, this builds the resulting set directly from the subtrees of both
+    /// sets, which takes O(m log(n/m + 1)) time where m <= n are the sizes of the two sets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let a = TreeSet::new().insert(1).insert(2);
+    /// let b = TreeSet::new().insert(2).insert(3);
+    ///
+    /// let intersection = a.intersection_set(&b);
+    /// assert_eq!(vec![&2], intersection.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn intersection_set(&self, other: &TreeSet<V, P, C>) -> TreeSet<V, P, C> {
+        self.with_root(tree::intersection(&self.root, &other.root, &self.cmp))
+    }
+
+    /// Returns a new set with the values which are in `self` but not in `other`.
+    ///
+    /// Unlike `difference`, this builds the resulting set directly from the subtrees of both
+    /// sets, which takes O(m log(n/m + 1)) time where m <= n are the sizes of the two sets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeSet;
+    ///
+    /// let a = TreeSet::new().insert(1).insert(2);
+    /// let b = TreeSet::new().insert(2).insert(3);
+    ///
+    /// let difference = a.difference_set(&b);
+    /// assert_eq!(vec![&1], difference.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn difference_set(&self, other: &TreeSet<V, P, C>) -> TreeSet<V, P, C> {
+        self.with_root(tree::difference(&self.root, &other.root, &self.cmp))
+    }
+
+    /// Returns a new set with the values which are in either `self` or `other`, but not in both.
+    ///
+    /// Unlike `symmetric_difference`, this builds the resulting set directly from the subtrees
+    /// of both sets, which takes O(m log(n/m + 1)) time where m <= n are the sizes of the two
+    /// sets.
     ///
     /// # Examples
     ///
     /// ```
     /// use immutable_map::TreeSet;
     ///
-    /// let empty_set = TreeSet::new();
-    /// assert_eq!(None, empty_set.remove(&2));
-    ///
-    /// let set = empty_set.insert(2).insert(3).insert(1);
-    ///
-    /// let (new_set, removed) = set.remove(&2).unwrap();
+    /// let a = TreeSet::new().insert(1).insert(2);
+    /// let b = TreeSet::new().insert(2).insert(3);
     ///
-    /// assert_eq!(false, new_set.contains(&2));
-    /// assert_eq!(&2, removed);
+    /// let symm_diff = a.symmetric_difference_set(&b);
+    /// assert_eq!(vec![&1, &3], symm_diff.iter().collect::<Vec<_>>());
     /// ```
-    pub fn remove<Q: Ord + ?Sized>(&self, key: &Q) -> Option<(TreeSet<V>, &V)>
-        where V: Borrow<Q>
-    {
-        tree::remove(&self.root, key).map(|(new_root, v)|
-            (TreeSet { root: new_root }, &v.0)
-        )
+    pub fn symmetric_difference_set(&self, other: &TreeSet<V, P, C>) -> TreeSet<V, P, C> {
+        self.with_root(tree::symmetric_difference(&self.root, &other.root, &self.cmp))
+    }
+}
+
+impl<V, P: PointerKind, C: Clone> Clone for TreeSet<V, P, C> {
+    fn clone(&self) -> TreeSet<V, P, C> {
+        TreeSet { root: self.root.clone(), cmp: self.cmp.clone() }
+    }
+}
+
+impl<V, P: PointerKind, C: Default> Default for TreeSet<V, P, C> {
+    fn default() -> TreeSet<V, P, C> {
+        TreeSet { root: None, cmp: C::default() }
     }
 }
 
-impl<V: Debug + Ord> Debug for TreeSet<V> {
+impl<V: Debug, P: PointerKind, C> Debug for TreeSet<V, P, C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_set().entries(self.iter()).finish()
     }
 }
 
-impl<'r, V: Ord> IntoIterator for &'r TreeSet<V> {
+impl<'r, V, P: PointerKind, C> IntoIterator for &'r TreeSet<V, P, C> {
     type Item = &'r V;
-    type IntoIter = tree::Keys<tree::Iter<'r, V, ()>>;
+    type IntoIter = tree::Keys<tree::Iter<'r, V, (), P>>;
 
-    fn into_iter(self) -> tree::Keys<tree::Iter<'r, V, ()>> {
+    fn into_iter(self) -> tree::Keys<tree::Iter<'r, V, (), P>> {
         self.iter()
     }
 }
 
-impl <V: PartialEq> PartialEq for TreeSet<V> {
-    fn eq(&self, other: &TreeSet<V>) -> bool {
+/// Consumes the set and yields the values in ascending order.
+///
+/// The nodes which are not shared with other sets are moved out without cloning the values, and
+/// only the shared nodes are cloned.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::TreeSet;
+///
+/// let set = TreeSet::new().insert(2).insert(3).insert(1);
+///
+/// let mut iter = set.into_iter();
+/// assert_eq!(Some(1), iter.next());
+/// assert_eq!(Some(3), iter.next_back());
+/// assert_eq!(1, iter.len());
+/// assert_eq!(Some(2), iter.next());
+/// assert_eq!(None, iter.next());
+/// ```
+impl<V: Clone, P: PointerKind, C> IntoIterator for TreeSet<V, P, C> {
+    type Item = V;
+    type IntoIter = TreeSetIntoIter<V, P>;
+
+    fn into_iter(self) -> TreeSetIntoIter<V, P> {
+        tree::IntoKeys::new(tree::IntoIter::new(self.root))
+    }
+}
+
+impl <V: PartialEq, P: PointerKind, C> PartialEq for TreeSet<V, P, C> {
+    fn eq(&self, other: &TreeSet<V, P, C>) -> bool {
         self.len() == other.len()
             && self.iter().zip(other.iter()).all(|(a, b)| a == b)
     }
 }
 
-impl <V: Eq> Eq for TreeSet<V> {}
+impl <V: Eq, P: PointerKind, C> Eq for TreeSet<V, P, C> {}
 
-impl <V: PartialOrd> PartialOrd for TreeSet<V> {
-    fn partial_cmp(&self, other: &TreeSet<V>) -> Option<Ordering> {
+impl <V: PartialOrd, P: PointerKind, C> PartialOrd for TreeSet<V, P, C> {
+    fn partial_cmp(&self, other: &TreeSet<V, P, C>) -> Option<Ordering> {
         self.iter().partial_cmp(other.iter())
     }
 }
 
-impl <V: Ord> Ord for TreeSet<V> {
-    fn cmp(&self, other: &TreeSet<V>) -> Ordering {
+impl <V: Ord, P: PointerKind, C> Ord for TreeSet<V, P, C> {
+    fn cmp(&self, other: &TreeSet<V, P, C>) -> Ordering {
         self.iter().cmp(other.iter())
     }
 }
 
-impl <V: Ord + Clone> FromIterator<V> for TreeSet<V> {
-    fn from_iter<T>(iter: T) -> TreeSet<V> where T: IntoIterator<Item=V> {
-        let mut s = TreeSet::new();
-        for v in iter {
-            s = s.insert(v);
+impl<V: Hash, P: PointerKind, C> Hash for TreeSet<V, P, C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for v in self.iter() {
+            v.hash(state);
+        }
+    }
+}
+
+/// Builds the set in O(n) time if the values are sorted, and by inserting them one by one
+/// otherwise. The values are collected into a `Vec` first to check their order, so this takes
+/// O(n) extra memory while building.
+impl <V, P: PointerKind, C> FromIterator<V> for TreeSet<V, P, C>
+    where V: Clone, C: Compare<V> + Clone + Default
+{
+    fn from_iter<T>(iter: T) -> TreeSet<V, P, C> where T: IntoIterator<Item=V> {
+        let cmp = C::default();
+        let mut elems: Vec<(V, ())> = iter.into_iter().map(|v| (v, ())).collect();
+        if tree::is_sorted(&elems, &cmp) {
+            tree::dedup_sorted(&mut elems, &cmp);
+            return TreeSet { root: tree::from_sorted_vec(elems), cmp };
+        }
+
+        let mut s = TreeSet { root: None, cmp };
+        for (v, _) in elems {
+            s.insert_mut(v);
         }
         s
     }
 }
 
+/// A builder which builds a `TreeSet` by inserting and removing the values in place.
+///
+/// Building a set with `TreeSet::insert` allocates a new path of nodes for every value. The
+/// builder owns the set being built, so the nodes are modified in place and most insertions
+/// allocate only the new node. `freeze` returns the built set.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::set::TreeSetBuilder;
+///
+/// let mut builder = TreeSetBuilder::new();
+/// for i in 0..100 {
+///     builder.insert(i);
+/// }
+/// builder.remove(&50);
+///
+/// let set = builder.freeze();
+/// assert_eq!(99, set.len());
+/// assert!(set.contains(&7));
+/// assert!(!set.contains(&50));
+/// ```
+pub struct TreeSetBuilder<V, P: PointerKind = RcKind, C = Natural> {
+    set: TreeSet<V, P, C>
+}
+
+impl<V> TreeSetBuilder<V> {
+    /// Makes a new builder with an empty set
+    ///
+    /// As with `TreeSet::new`, the builders of the sets shared with `Arc` are made with
+    /// `Default`.
+    pub fn new() -> TreeSetBuilder<V> {
+        TreeSetBuilder { set: TreeSet::new() }
+    }
+}
+
+impl<V, P: PointerKind, C> TreeSetBuilder<V, P, C> {
+    /// Returns the number of elements in the set being built.
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    /// Returns true if the set being built contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// Returns the built set.
+    pub fn freeze(self) -> TreeSet<V, P, C> {
+        self.set
+    }
+}
+
+impl<V: Clone, P: PointerKind, C: Compare<V> + Clone> TreeSetBuilder<V, P, C> {
+    /// Inserts the value. Returns true if the set did not have the value.
+    pub fn insert(&mut self, value: V) -> bool {
+        self.set.insert_mut(value)
+    }
+
+    /// Removes the value. Returns true if the set had the value.
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> bool
+        where V: Borrow<Q>, C: Compare<Q>
+    {
+        self.set.remove_mut(key)
+    }
+}
+
+impl<V, P: PointerKind, C: Default> Default for TreeSetBuilder<V, P, C> {
+    fn default() -> TreeSetBuilder<V, P, C> {
+        TreeSetBuilder { set: TreeSet { root: None, cmp: C::default() } }
+    }
+}
+
+impl<V, P: PointerKind, C> From<TreeSet<V, P, C>> for TreeSetBuilder<V, P, C> {
+    fn from(set: TreeSet<V, P, C>) -> TreeSetBuilder<V, P, C> {
+        TreeSetBuilder { set }
+    }
+}
+
+impl<V: Clone, P: PointerKind, C: Compare<V> + Clone> Extend<V> for TreeSetBuilder<V, P, C> {
+    fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item=V> {
+        for v in iter {
+            self.insert(v);
+        }
+    }
+}
+
 #[derive(Clone)]
-pub struct Intersection<'r, V: 'r> {
-    a: Peekable<tree::Iter<'r, V, ()>>,
-    b: Peekable<tree::Iter<'r, V, ()>>
+pub struct Intersection<'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    a: Peekable<tree::Iter<'r, V, (), P>>,
+    b: Peekable<tree::Iter<'r, V, (), P>>,
+    cmp: &'r C
 }
 
-impl<'r, V: Ord + 'r> Iterator for Intersection<'r, V> {
+impl<'r, V: 'r, P: PointerKind, C: Compare<V> + 'r> Iterator for Intersection<'r, V, P, C> {
     type Item = &'r V;
 
     fn next(&mut self) -> Option<&'r V> {
@@ -520,7 +1373,7 @@ impl<'r, V: Ord + 'r> Iterator for Intersection<'r, V> {
             let cmp = match (self.a.peek(), self.b.peek()) {
                 (None, _) => return None,
                 (_, None) => return None,
-                (Some(a), Some(b)) => a.cmp(b)
+                (Some(a), Some(b)) => self.cmp.compare(a.0, b.0)
             };
 
             match cmp {
@@ -540,45 +1393,45 @@ impl<'r, V: Ord + 'r> Iterator for Intersection<'r, V> {
 }
 
 #[derive(Clone)]
-pub struct Union<'r, V: 'r> {
-    a: Peekable<tree::Iter<'r, V, ()>>,
-    b: Peekable<tree::Iter<'r, V, ()>>
+pub struct Union<'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    a: Peekable<tree::Iter<'r, V, (), P>>,
+    b: Peekable<tree::Iter<'r, V, (), P>>,
+    cmp: &'r C
 }
 
-impl <'r, V: Ord + 'r> Iterator for Union<'r, V> {
+impl<'r, V: 'r, P: PointerKind, C: Compare<V> + 'r> Iterator for Union<'r, V, P, C> {
     type Item = &'r V;
 
     fn next(&mut self) -> Option<&'r V> {
-        loop {
-            let cmp = match (self.a.peek(), self.b.peek()) {
-                (_, None) => Ordering::Less,
-                (None, _) => Ordering::Greater,
-                (Some(a), Some(b)) => a.cmp(b)
-            };
+        let cmp = match (self.a.peek(), self.b.peek()) {
+            (_, None) => Ordering::Less,
+            (None, _) => Ordering::Greater,
+            (Some(a), Some(b)) => self.cmp.compare(a.0, b.0)
+        };
 
-            match cmp {
-                Ordering::Less => {
-                    return self.a.next().map(|pair| pair.0);
-                },
-                Ordering::Equal => {
-                    self.b.next();
-                    return self.a.next().map(|pair| pair.0);
-                },
-                Ordering::Greater => {
-                    return self.b.next().map(|pair| pair.0);
-                }
+        match cmp {
+            Ordering::Less => {
+                self.a.next().map(|pair| pair.0)
+            },
+            Ordering::Equal => {
+                self.b.next();
+                self.a.next().map(|pair| pair.0)
+            },
+            Ordering::Greater => {
+                self.b.next().map(|pair| pair.0)
             }
         }
     }
 }
 
 #[derive(Clone)]
-pub struct Difference<'r, V: 'r> {
-    a: Peekable<tree::Iter<'r, V, ()>>,
-    b: Peekable<tree::Iter<'r, V, ()>>
+pub struct Difference<'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    a: Peekable<tree::Iter<'r, V, (), P>>,
+    b: Peekable<tree::Iter<'r, V, (), P>>,
+    cmp: &'r C
 }
 
-impl<'r, V: Ord + 'r> Iterator for Difference<'r, V> {
+impl<'r, V: 'r, P: PointerKind, C: Compare<V> + 'r> Iterator for Difference<'r, V, P, C> {
     type Item = &'r V;
 
     fn next(&mut self) -> Option<&'r V> {
@@ -586,7 +1439,7 @@ impl<'r, V: Ord + 'r> Iterator for Difference<'r, V> {
             let cmp = match (self.a.peek(), self.b.peek()) {
                 (_, None) => Ordering::Less,
                 (None, _) => return None,
-                (Some(a), Some(b)) => a.cmp(b)
+                (Some(a), Some(b)) => self.cmp.compare(a.0, b.0)
             };
 
             match cmp {
@@ -606,12 +1459,13 @@ impl<'r, V: Ord + 'r> Iterator for Difference<'r, V> {
 }
 
 #[derive(Clone)]
-pub struct SymmetricDifference<'r, V: 'r> {
-    a: Peekable<tree::Iter<'r, V, ()>>,
-    b: Peekable<tree::Iter<'r, V, ()>>
+pub struct SymmetricDifference<'r, V: 'r, P: PointerKind = RcKind, C: 'r = Natural> {
+    a: Peekable<tree::Iter<'r, V, (), P>>,
+    b: Peekable<tree::Iter<'r, V, (), P>>,
+    cmp: &'r C
 }
 
-impl<'r, V: Ord + 'r> Iterator for SymmetricDifference<'r, V> {
+impl<'r, V: 'r, P: PointerKind, C: Compare<V> + 'r> Iterator for SymmetricDifference<'r, V, P, C> {
     type Item = &'r V;
 
     fn next(&mut self) -> Option<&'r V> {
@@ -619,7 +1473,7 @@ impl<'r, V: Ord + 'r> Iterator for SymmetricDifference<'r, V> {
             let cmp = match (self.a.peek(), self.b.peek()) {
                 (_, None) => Ordering::Less,
                 (None, _) => Ordering::Greater,
-                (Some(a), Some(b)) => a.cmp(b)
+                (Some(a), Some(b)) => self.cmp.compare(a.0, b.0)
             };
 
             match cmp {
@@ -640,10 +1494,15 @@ impl<'r, V: Ord + 'r> Iterator for SymmetricDifference<'r, V> {
 
 #[cfg(test)]
 mod test {
-    use tree::balanced;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::rc::Rc;
+    use std::thread;
+
+    use tree::{balanced, NodeRef};
     use Bound;
 
-    use super::TreeSet;
+    use super::{TreeSet, ArcTreeSet};
 
     #[test]
     fn test_insert() {
@@ -852,11 +1711,92 @@ mod test {
 
         assert!(a != b);
     }
+
+    #[test]
+    fn test_union_set_sharing() {
+        let a: TreeSet<usize> = (0..100).collect();
+        let b = a.insert(200);
+
+        let union = a.union_set(&b);
+        assert_eq!(101, union.len());
+        assert!(balanced(&union.root));
+
+        let same = a.union_set(&a);
+        assert!(NodeRef::ptr_eq(a.root.as_ref().unwrap(), same.root.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn test_split_join() {
+        let set: TreeSet<usize> = (0..20).collect();
+
+        let (left, found, right) = set.split(&12);
+        assert_eq!(Some(&12), found);
+        assert_eq!((0..12).collect::<Vec<_>>(), left.iter().cloned().collect::<Vec<_>>());
+        assert_eq!((13..20).collect::<Vec<_>>(), right.iter().cloned().collect::<Vec<_>>());
+
+        let joined = TreeSet::join(&left, 12, &right);
+        assert_eq!(set, joined);
+        assert!(balanced(&joined.root));
+    }
+
+    #[test]
+    fn test_arc_threads() {
+        let set: ArcTreeSet<usize> = (0..100).collect();
+
+        let handles: Vec<_> = (0..4).map(|i| {
+            let s = set.clone();
+            thread::spawn(move || s.remove(&i).unwrap().0)
+        }).collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            let s = handle.join().unwrap();
+            assert!(!s.contains(&i));
+            assert_eq!(99, s.len());
+            assert!(balanced(&s.root));
+        }
+        assert_eq!(100, set.len());
+    }
+
+    #[test]
+    fn test_into_iter_moves_unshared() {
+        let set: TreeSet<Rc<usize>> = (0..100).map(Rc::new).collect();
+        let shared = set.clone();
+
+        // every node is shared with `shared`, so the values are cloned
+        for (i, v) in set.into_iter().enumerate() {
+            assert_eq!(i, *v);
+            assert_eq!(2, Rc::strong_count(&v));
+        }
+        // `shared` is the only owner of the nodes now, so the values are moved out
+        for (i, v) in shared.into_iter().enumerate() {
+            assert_eq!(i, *v);
+            assert_eq!(1, Rc::strong_count(&v));
+        }
+    }
+
+    fn hash<T: Hash>(t: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        t.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_hash() {
+        let a: TreeSet<_> = (0..100).collect();
+        let b = (0..100).rev().fold(TreeSet::new(), |s, i| s.insert(i));
+        let c = a.remove(&50).unwrap().0;
+
+        assert_eq!(hash(&a), hash(&b));
+        assert!(hash(&a) != hash(&c));
+    }
 }
 
 #[cfg(test)]
 mod quickcheck {
-    use set::TreeSet;
+    use compare::{Natural, Reverse};
+    use pointer::RcKind;
+    use set::{TreeSet, TreeSetBuilder, TreeSetCursor};
+    use tree::balanced;
     use Bound;
 
     use quickcheck::TestResult;
@@ -1227,6 +2167,54 @@ mod quickcheck {
         }
     }
 
+    quickcheck! {
+        fn check_intersection_set(input0: Vec<isize>, input1: Vec<isize>) -> bool {
+            let x_set: TreeSet<isize> = input0.into_iter().collect();
+            let y_set: TreeSet<isize> = input1.into_iter().collect();
+
+            let expected: Vec<isize> = x_set.intersection(&y_set).cloned().collect();
+            let res = x_set.intersection_set(&y_set);
+
+            balanced(&res.root) && res.iter().cloned().collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_union_set(input0: Vec<isize>, input1: Vec<isize>) -> bool {
+            let x_set: TreeSet<isize> = input0.into_iter().collect();
+            let y_set: TreeSet<isize> = input1.into_iter().collect();
+
+            let expected: Vec<isize> = x_set.union(&y_set).cloned().collect();
+            let res = x_set.union_set(&y_set);
+
+            balanced(&res.root) && res.iter().cloned().collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_difference_set(input0: Vec<isize>, input1: Vec<isize>) -> bool {
+            let x_set: TreeSet<isize> = input0.into_iter().collect();
+            let y_set: TreeSet<isize> = input1.into_iter().collect();
+
+            let expected: Vec<isize> = x_set.difference(&y_set).cloned().collect();
+            let res = x_set.difference_set(&y_set);
+
+            balanced(&res.root) && res.iter().cloned().collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_symmetric_difference_set(input0: Vec<isize>, input1: Vec<isize>) -> bool {
+            let x_set: TreeSet<isize> = input0.into_iter().collect();
+            let y_set: TreeSet<isize> = input1.into_iter().collect();
+
+            let expected: Vec<isize> = x_set.symmetric_difference(&y_set).cloned().collect();
+            let res = x_set.symmetric_difference_set(&y_set);
+
+            balanced(&res.root) && res.iter().cloned().collect::<Vec<_>>() == expected
+        }
+    }
+
     quickcheck! {
         fn check_is_disjoint(input0: Vec<isize>, input1: Vec<isize>) -> bool {
             let xs = filter_input(input0);
@@ -1269,6 +2257,106 @@ mod quickcheck {
         }
     }
 
+    quickcheck! {
+        fn check_split(xs: Vec<isize>, value: isize) -> bool
+        {
+            let s: TreeSet<isize> = xs.iter().cloned().collect();
+
+            let (left, found, right) = s.split(&value);
+
+            balanced(&left.root) && balanced(&right.root)
+                && left.iter().all(|&x| x < value)
+                && right.iter().all(|&x| x > value)
+                && found.cloned() == xs.iter().cloned().find(|&x| x == value)
+                && left.len() + right.len() + found.iter().count() == s.len()
+        }
+    }
+
+    quickcheck! {
+        fn check_join(xs: Vec<isize>, ys: Vec<isize>) -> bool
+        {
+            let left: TreeSet<isize> = xs.into_iter().map(|x| -x.abs() - 1).collect();
+            let right: TreeSet<isize> = ys.into_iter().map(|y| y.abs() + 1).collect();
+
+            let s = TreeSet::join(&left, 0, &right);
+
+            let expected: Vec<_> = left.iter().chain(Some(&0)).chain(right.iter()).collect();
+
+            balanced(&s.root) && s.iter().collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_from_sorted_iter(xs: Vec<isize>) -> bool
+        {
+            let mut input = xs.clone();
+            input.sort();
+
+            let s = TreeSet::<_>::from_sorted_iter(input.clone());
+            input.dedup();
+
+            balanced(&s.root) && s.iter().cloned().collect::<Vec<_>>() == input
+        }
+    }
+
+    quickcheck! {
+        fn check_from_strictly_sorted_iter(xs: Vec<isize>) -> bool
+        {
+            let strictly_sorted = xs.windows(2).all(|w| w[0] < w[1]);
+
+            match TreeSet::<_>::from_strictly_sorted_iter(xs.clone()) {
+                Some(s) => strictly_sorted && balanced(&s.root)
+                    && s.iter().cloned().collect::<Vec<_>>() == xs,
+                None => !strictly_sorted
+            }
+        }
+    }
+
+    quickcheck! {
+        fn check_index(xs: Vec<isize>, value: isize) -> bool
+        {
+            let s: TreeSet<isize> = xs.into_iter().collect();
+
+            s.iter().enumerate().all(|(i, x)| s.get_index(i) == Some(x) && s.index_of(x) == Some(i))
+                && s.get_index(s.len()).is_none()
+                && s.index_of(&value) == s.iter().position(|&x| x == value)
+        }
+    }
+
+    quickcheck! {
+        fn check_remove_index(xs: Vec<isize>, index: usize) -> bool
+        {
+            let s: TreeSet<isize> = xs.into_iter().collect();
+
+            match s.remove_index(index) {
+                Some((removed, &v)) => {
+                    let mut expected: Vec<isize> = s.iter().cloned().collect();
+                    let x = expected.remove(index);
+
+                    balanced(&removed.root) && x == v
+                        && removed.iter().cloned().collect::<Vec<_>>() == expected
+                },
+                None => index >= s.len()
+            }
+        }
+    }
+
+    quickcheck! {
+        fn check_split_at(xs: Vec<isize>, index: usize) -> bool
+        {
+            let s: TreeSet<isize> = xs.into_iter().collect();
+
+            let (left, right) = s.split_at(index);
+            let expected: Vec<_> = s.iter().collect();
+            let mid = if index < s.len() { index } else { s.len() };
+
+            balanced(&left.root) && balanced(&right.root)
+                && left.iter().collect::<Vec<_>>() == expected[..mid]
+                && right.iter().collect::<Vec<_>>() == expected[mid..]
+                && s.take(index) == left && s.skip(index) == right
+        }
+    }
+
     quickcheck! {
         fn check_insert_if_absent(xs: Vec<isize>, value: isize) -> bool
         {
@@ -1276,12 +2364,146 @@ mod quickcheck {
 
             let s: TreeSet<isize> = input.iter().cloned().collect();
 
-            if input.iter().any(|&x| x == value) {
-                None == s.insert_if_absent(value)
+            if input.contains(&value) {
+                s.insert_if_absent(value).is_none()
             } else {
                 let res = s.insert_if_absent(value);
                 res.is_some() && res.unwrap().contains(&value)
             }
         }
     }
+
+    quickcheck! {
+        fn check_insert_mut(xs: Vec<isize>) -> bool {
+            let mut expected = TreeSet::new();
+            let mut s = TreeSet::new();
+            let mut snapshots = Vec::new();
+
+            for (i, &x) in xs.iter().enumerate() {
+                if i % 4 == 0 {
+                    snapshots.push((s.clone(), expected.clone()));
+                }
+                if s.insert_mut(x) == expected.contains(&x) {
+                    return false;
+                }
+                expected = expected.insert(x);
+            }
+
+            balanced(&s.root) && s == expected
+                && snapshots.into_iter().all(|(snapshot, e)| snapshot == e)
+        }
+    }
+
+    quickcheck! {
+        fn check_builder(xs: Vec<isize>, ys: Vec<isize>) -> bool {
+            let mut builder = TreeSetBuilder::from(TreeSet::new());
+            builder.extend(xs.iter().cloned());
+            let original = builder.freeze();
+            let mut builder = TreeSetBuilder::from(original.clone());
+
+            let mut expected = original.clone();
+            for y in &ys {
+                if builder.remove(y) != expected.contains(y) {
+                    return false;
+                }
+                if let Some((new_expected, _)) = expected.remove(y) {
+                    expected = new_expected;
+                }
+            }
+            let s = builder.freeze();
+
+            let restored: TreeSet<isize> = xs.into_iter().collect();
+            balanced(&s.root) && s == expected && original == restored
+        }
+    }
+
+    quickcheck! {
+        fn check_cursor(xs: Vec<isize>, key: isize) -> bool {
+            let s: TreeSet<isize> = xs.into_iter().collect();
+            let expected: Vec<_> = s.iter().collect();
+            // the index `expected.len()` is the ghost position
+            let n = expected.len() + 1;
+            let lower = expected.iter().position(|&&x| x >= key).unwrap_or(n - 1);
+            let upper = expected.iter().position(|&&x| x > key).unwrap_or(n - 1);
+
+            let check = |cursor: &TreeSetCursor<isize>, index: usize| {
+                cursor.rank() == if index < n - 1 { Some(index) } else { None }
+                    && cursor.value() == expected.get(index).cloned()
+            };
+
+            let mut cursor = s.lower_bound(&key);
+            for i in 0..n + 1 {
+                if !check(&cursor, (lower + i) % n) {
+                    return false;
+                }
+                cursor.move_next();
+            }
+
+            let mut cursor = s.upper_bound(&key);
+            for i in 0..n + 1 {
+                if !check(&cursor, (upper + n - i % n) % n) {
+                    return false;
+                }
+                cursor.move_prev();
+            }
+
+            match s.find(&key) {
+                Some(cursor) => lower < upper && check(&cursor, lower),
+                None => lower == upper
+            }
+        }
+    }
+
+    quickcheck! {
+        fn check_get_le_lt_ge_gt(xs: Vec<isize>, key: isize) -> bool {
+            let s: TreeSet<isize> = xs.into_iter().collect();
+
+            s.get_le(&key) == s.iter().rev().find(|&&x| x <= key)
+                && s.get_lt(&key) == s.iter().rev().find(|&&x| x < key)
+                && s.get_ge(&key) == s.iter().find(|&&x| x >= key)
+                && s.get_gt(&key) == s.iter().find(|&&x| x > key)
+        }
+    }
+
+    quickcheck! {
+        fn check_into_iter(xs: Vec<isize>, dirs: Vec<bool>) -> bool {
+            let s: TreeSet<isize> = xs.into_iter().collect();
+            let mut expected: Vec<isize> = s.iter().cloned().collect();
+            let mut iter = s.clone().into_iter();
+
+            for back in dirs {
+                let (actual, elem) = if back {
+                    (iter.next_back(), expected.pop())
+                } else if expected.is_empty() {
+                    (iter.next(), None)
+                } else {
+                    (iter.next(), Some(expected.remove(0)))
+                };
+                if actual != elem || iter.len() != expected.len() {
+                    return false;
+                }
+            }
+
+            iter.collect::<Vec<_>>() == expected
+        }
+    }
+
+    quickcheck! {
+        fn check_comparator(xs: Vec<isize>, ys: Vec<isize>) -> bool {
+            let a: TreeSet<isize, RcKind, Reverse<Natural>> = xs.iter().cloned().collect();
+            let b: TreeSet<isize, RcKind, Reverse<Natural>> = ys.iter().cloned().collect();
+            let c: TreeSet<isize> = xs.into_iter().collect();
+            let d: TreeSet<isize> = ys.into_iter().collect();
+
+            balanced(&a.root) && a.iter().eq(c.rev_iter())
+                && a.union(&b).eq(a.union_set(&b).iter())
+                && a.union(&b).eq(c.union_set(&d).rev_iter())
+                && a.intersection(&b).eq(a.intersection_set(&b).iter())
+                && a.intersection(&b).eq(c.intersection_set(&d).rev_iter())
+                && a.difference(&b).eq(a.difference_set(&b).iter())
+                && a.difference(&b).eq(c.difference_set(&d).rev_iter())
+                && a.symmetric_difference(&b).eq(a.symmetric_difference_set(&b).iter())
+                && a.symmetric_difference(&b).eq(c.symmetric_difference_set(&d).rev_iter())
+        }
+    }
 }
@@ -1,35 +1,113 @@
 use std::borrow::Borrow;
 use std::cmp::Ordering;
-use std::rc::Rc;
-
+use std::collections::VecDeque;
+use std::iter::Rev;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Deref;
+use std::ptr;
 use Bound;
+use compare::Compare;
+use measure::{Measure, Summarize};
+use pointer::PointerKind;
 
 static DELTA: usize = 3;
 static GAMMA: usize = 2;
 
-#[derive(Clone, Debug)]
-pub struct TreeNode<K, V> {
+// a shared pointer to a node, which is `Rc` or `Arc` depending on the pointer kind.
+pub struct NodeRef<K, V, P: PointerKind, A: Summarize<K, V> = ()> {
+    ptr: P::Pointer<TreeNode<K, V, P, A>>
+}
+
+impl<K, V, P: PointerKind, A: Measure<K, V>> NodeRef<K, V, P, A> {
+    pub fn new(node: TreeNode<K, V, P, A>) -> NodeRef<K, V, P, A> {
+        NodeRef { ptr: P::new(node) }
+    }
+
+    pub fn ptr_eq(a: &NodeRef<K, V, P, A>, b: &NodeRef<K, V, P, A>) -> bool {
+        P::ptr_eq(&a.ptr, &b.ptr)
+    }
+
+    // returns the mutable reference to the node, copying the node if it is shared.
+    pub fn make_mut(&mut self) -> &mut TreeNode<K, V, P, A> where K: Clone, V: Clone {
+        P::make_mut(&mut self.ptr)
+    }
+
+    // returns the mutable reference to the node if it is not shared with other trees.
+    pub fn get_mut(&mut self) -> Option<&mut TreeNode<K, V, P, A>> {
+        P::get_mut(&mut self.ptr)
+    }
+
+    pub fn unwrap_or_clone(self) -> TreeNode<K, V, P, A> where K: Clone, V: Clone {
+        match P::try_unwrap(self.ptr) {
+            Ok(node) => node,
+            Err(ptr) => (*ptr).clone()
+        }
+    }
+}
+
+impl<K, V, P: PointerKind, A: Summarize<K, V>> Clone for NodeRef<K, V, P, A> {
+    fn clone(&self) -> NodeRef<K, V, P, A> {
+        NodeRef { ptr: self.ptr.clone() }
+    }
+}
+
+impl<K, V, P: PointerKind, A: Summarize<K, V>> Deref for NodeRef<K, V, P, A> {
+    type Target = TreeNode<K, V, P, A>;
+
+    fn deref(&self) -> &TreeNode<K, V, P, A> {
+        &self.ptr
+    }
+}
+
+pub struct TreeNode<K, V, P: PointerKind, A: Summarize<K, V> = ()> {
     size: usize,
+    // the summary of the elements of this subtree
+    summary: A::Summary,
     elem: (K, V),
-    left: Option<Rc<TreeNode<K, V>>>,
-    right: Option<Rc<TreeNode<K, V>>>
+    left: Option<NodeRef<K, V, P, A>>,
+    right: Option<NodeRef<K, V, P, A>>
 }
 
-impl<K, V> TreeNode<K, V> {
-    fn new(elem: (K, V), left: Option<Rc<TreeNode<K, V>>>, right: Option<Rc<TreeNode<K, V>>>)
-        -> TreeNode<K, V>
+impl<K, V, P: PointerKind, A: Measure<K, V>> TreeNode<K, V, P, A> {
+    fn new(elem: (K, V), left: Option<NodeRef<K, V, P, A>>, right: Option<NodeRef<K, V, P, A>>)
+        -> TreeNode<K, V, P, A>
     {
         TreeNode {
-            elem: elem,
             size: size(&left) + size(&right) + 1,
-            left: left,
-            right: right
+            summary: summarize(&elem, &left, &right),
+            elem,
+            left,
+            right
+        }
+    }
+
+    // recompute the cached size and summary after the children or the element are replaced.
+    fn refresh(&mut self) {
+        self.size = size(&self.left) + size(&self.right) + 1;
+        self.summary = summarize(&self.elem, &self.left, &self.right);
+    }
+}
+
+impl<K, V, P: PointerKind, A: Measure<K, V>> Clone for TreeNode<K, V, P, A>
+    where K: Clone, V: Clone
+{
+    // the summaries are not required to be `Clone`, so the summary is recomputed from the
+    // cached summaries of the children.
+    fn clone(&self) -> TreeNode<K, V, P, A> {
+        TreeNode {
+            size: self.size,
+            summary: summarize(&self.elem, &self.left, &self.right),
+            elem: self.elem.clone(),
+            left: self.left.clone(),
+            right: self.right.clone()
         }
     }
 }
 
-pub fn find_exact<K, V, F>(node: &Option<Rc<TreeNode<K, V>>>, mut f: F) -> Option<&(K, V)>
-    where F: FnMut(&K) -> Ordering
+pub fn find_exact<K, V, P: PointerKind, A, F>(node: &Option<NodeRef<K, V, P, A>>, mut f: F)
+        -> Option<&(K, V)>
+    where F: FnMut(&K) -> Ordering, A: Measure<K, V>
 {
     let mut cursor = node;
     loop {
@@ -44,75 +122,105 @@ pub fn find_exact<K, V, F>(node: &Option<Rc<TreeNode<K, V>>>, mut f: F) -> Optio
     }
 }
 
-pub fn delete_min<K, V>(node: &TreeNode<K, V>) -> (Option<Rc<TreeNode<K, V>>>, &(K, V))
-    where K: Clone, V: Clone
+// find the first element whose key satisfies `f`.
+// assumes that the keys which satisfy `f` come after the keys which do not.
+pub fn find_first<K, V, P: PointerKind, A, F>(node: &Option<NodeRef<K, V, P, A>>, mut f: F)
+        -> Option<&(K, V)>
+    where F: FnMut(&K) -> bool, A: Measure<K, V>
+{
+    let mut cursor = node;
+    let mut found = None;
+    while let Some(ref n) = *cursor {
+        if f(&n.elem.0) {
+            found = Some(&n.elem);
+            cursor = &n.left;
+        } else {
+            cursor = &n.right;
+        }
+    }
+    found
+}
+
+// find the last element whose key satisfies `f`.
+// assumes that the keys which satisfy `f` come before the keys which do not.
+pub fn find_last<K, V, P: PointerKind, A, F>(node: &Option<NodeRef<K, V, P, A>>, mut f: F)
+        -> Option<&(K, V)>
+    where F: FnMut(&K) -> bool, A: Measure<K, V>
+{
+    let mut cursor = node;
+    let mut found = None;
+    while let Some(ref n) = *cursor {
+        if f(&n.elem.0) {
+            found = Some(&n.elem);
+            cursor = &n.right;
+        } else {
+            cursor = &n.left;
+        }
+    }
+    found
+}
+
+#[allow(clippy::type_complexity)]
+pub fn delete_min<K, V, P: PointerKind, A>(node: &TreeNode<K, V, P, A>)
+        -> (Option<NodeRef<K, V, P, A>>, &(K, V))
+    where K: Clone, V: Clone, A: Measure<K, V>
 {
     match node.left {
         None => (node.right.clone(), &node.elem),
         Some(ref l) => {
             let (new_left, v) = delete_min(l);
             let new_node = balance_left(node.elem.clone(), &new_left, &node.right);
-            (Some(Rc::new(new_node)), v)
+            (Some(NodeRef::new(new_node)), v)
         }
     }
 }
 
-pub fn delete_max<K, V>(node: &TreeNode<K, V>) -> (Option<Rc<TreeNode<K, V>>>, &(K, V))
-    where K: Clone, V: Clone
+#[allow(clippy::type_complexity)]
+pub fn delete_max<K, V, P: PointerKind, A>(node: &TreeNode<K, V, P, A>)
+        -> (Option<NodeRef<K, V, P, A>>, &(K, V))
+    where K: Clone, V: Clone, A: Measure<K, V>
 {
     match node.right {
         None => (node.left.clone(), &node.elem),
         Some(ref r) => {
             let (new_right, v) = delete_max(r);
             let new_node = balance_right(node.elem.clone(), &node.left, &new_right);
-            (Some(Rc::new(new_node)), v)
+            (Some(NodeRef::new(new_node)), v)
         }
     }
 }
 
-pub fn insert<K, V>(node: &Option<Rc<TreeNode<K, V>>>, elem: (K, V)) -> TreeNode<K, V>
-    where K: Clone + Ord, V: Clone
+pub fn insert<K, V, P: PointerKind, A, C>(node: &Option<NodeRef<K, V, P, A>>, elem: (K, V), cmp: &C)
+        -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>
 {
     match *node {
-        None => TreeNode {
-            size: 1,
-            elem: elem,
-            left: None,
-            right: None
-        },
-        Some(ref n) => match elem.0.cmp(&n.elem.0) {
+        None => TreeNode::new(elem, None, None),
+        Some(ref n) => match cmp.compare(&elem.0, &n.elem.0) {
             Ordering::Less => {
-                balance_right_move(n.elem.clone(), insert(&n.left, elem), &n.right)
+                balance_right_move(n.elem.clone(), insert(&n.left, elem, cmp), &n.right)
             },
             Ordering::Greater => {
-                balance_left_move(n.elem.clone(), &n.left, insert(&n.right, elem))
+                balance_left_move(n.elem.clone(), &n.left, insert(&n.right, elem, cmp))
             },
-            Ordering::Equal => TreeNode {
-                size: n.size,
-                elem: elem,
-                left: n.left.clone(),
-                right: n.right.clone()
-            }
+            Ordering::Equal => TreeNode::new(elem, n.left.clone(), n.right.clone())
         }
     }
 }
 
-pub fn insert_if_absent<K, V>(node: &Option<Rc<TreeNode<K, V>>>, elem: (K, V))
-        -> Option<TreeNode<K, V>>
-    where K: Clone + Ord, V: Clone
+pub fn insert_if_absent<K, V, P: PointerKind, A, C>(node: &Option<NodeRef<K, V, P, A>>,
+                                                     elem: (K, V),
+                                                     cmp: &C)
+        -> Option<TreeNode<K, V, P, A>>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>
 {
     match *node {
-        None => Some(TreeNode {
-            size: 1,
-            elem: elem,
-            left: None,
-            right: None
-        }),
-        Some(ref n) => match elem.0.cmp(&n.elem.0) {
-            Ordering::Less => insert_if_absent(&n.left, elem).map(|new_left|
+        None => Some(TreeNode::new(elem, None, None)),
+        Some(ref n) => match cmp.compare(&elem.0, &n.elem.0) {
+            Ordering::Less => insert_if_absent(&n.left, elem, cmp).map(|new_left|
                 balance_right_move(n.elem.clone(), new_left, &n.right)
             ),
-            Ordering::Greater => insert_if_absent(&n.right, elem).map(|new_right|
+            Ordering::Greater => insert_if_absent(&n.right, elem, cmp).map(|new_right|
                 balance_left_move(n.elem.clone(), &n.left, new_right)
             ),
             Ordering::Equal => None
@@ -120,83 +228,73 @@ pub fn insert_if_absent<K, V>(node: &Option<Rc<TreeNode<K, V>>>, elem: (K, V))
     }
 }
 
-pub fn update<K, V, Q: ?Sized + Ord, F>(node: &TreeNode<K, V>, key: &Q, mut f: F)
-        -> Option<TreeNode<K, V>>
-    where K: Borrow<Q> + Clone, V: Clone, F: FnMut(&V) -> V
+pub fn update<K, V, P: PointerKind, A, Q: ?Sized, F, C>(node: &TreeNode<K, V, P, A>,
+                                                        key: &Q,
+                                                        mut f: F,
+                                                        cmp: &C)
+        -> Option<TreeNode<K, V, P, A>>
+    where K: Borrow<Q> + Clone, V: Clone, F: FnMut(&V) -> V, C: Compare<Q>, A: Measure<K, V>
 {
-    match key.cmp(node.elem.0.borrow()) {
+    match cmp.compare(key, node.elem.0.borrow()) {
         Ordering::Less => node.left.as_ref().and_then(|l|
-            update(l, key, f).map(|new_left|
-                TreeNode {
-                    size: node.size,
-                    elem: node.elem.clone(),
-                    left: Some(Rc::new(new_left)),
-                    right: node.right.clone()
-                }
+            update(l, key, f, cmp).map(|new_left|
+                TreeNode::new(node.elem.clone(), Some(NodeRef::new(new_left)), node.right.clone())
             )
         ),
         Ordering::Greater => node.right.as_ref().and_then(|r|
-            update(r, key, f).map(|new_right|
-                TreeNode {
-                    size: node.size,
-                    elem: node.elem.clone(),
-                    left: node.left.clone(),
-                    right: Some(Rc::new(new_right)),
-                }
+            update(r, key, f, cmp).map(|new_right|
+                TreeNode::new(node.elem.clone(), node.left.clone(), Some(NodeRef::new(new_right)))
             )
         ),
-        Ordering::Equal => Some(
-            TreeNode {
-                size: node.size,
-                elem: (node.elem.0.clone(), f(&node.elem.1)),
-                left: node.left.clone(),
-                right: node.right.clone()
-            }
-        )
+        Ordering::Equal => Some(TreeNode::new(
+            (node.elem.0.clone(), f(&node.elem.1)),
+            node.left.clone(),
+            node.right.clone()
+        ))
     }
 }
 
-pub fn insert_or_update<K, V, F>(node: &Option<Rc<TreeNode<K, V>>>, key: K, value: V, mut f: F)
-        -> TreeNode<K, V>
-    where K: Ord + Clone, V: Clone, F: FnMut(&V) -> V
+pub fn insert_or_update<K, V, P: PointerKind, A, F, C>(node: &Option<NodeRef<K, V, P, A>>,
+                                                        key: K,
+                                                        value: V,
+                                                        mut f: F,
+                                                        cmp: &C)
+        -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, F: FnMut(&V) -> V, C: Compare<K>, A: Measure<K, V>
 {
     match *node {
-        None => TreeNode {
-            size: 1,
-            elem: (key, value),
-            left: None,
-            right: None
-        },
-        Some(ref n) => match key.cmp(&n.elem.0) {
+        None => TreeNode::new((key, value), None, None),
+        Some(ref n) => match cmp.compare(&key, &n.elem.0) {
             Ordering::Less => {
-                let new_left = insert_or_update(&n.left, key, value, f);
+                let new_left = insert_or_update(&n.left, key, value, f, cmp);
                 balance_right_move(n.elem.clone(), new_left, &n.right)
             },
             Ordering::Greater => {
-                let new_right = insert_or_update(&n.right, key, value, f);
+                let new_right = insert_or_update(&n.right, key, value, f, cmp);
                 balance_left_move(n.elem.clone(), &n.left, new_right)
             },
-            Ordering::Equal => TreeNode {
-                size: n.size,
-                elem: (n.elem.0.clone(), f(&n.elem.1)),
-                left: n.left.clone(),
-                right: n.right.clone()
+            Ordering::Equal => {
+                let elem = (n.elem.0.clone(), f(&n.elem.1));
+                TreeNode::new(elem, n.left.clone(), n.right.clone())
             }
         }
     }
 }
 
-pub fn remove<'r, Q: ?Sized + Ord, K, V>(node: &'r Option<Rc<TreeNode<K, V>>>, key: &Q)
-        -> Option<(Option<Rc<TreeNode<K, V>>>, &'r (K, V))>
-    where K: Clone + Ord + Borrow<Q>, V: Clone
+#[allow(clippy::type_complexity)]
+pub fn remove<'r, Q: ?Sized, K, V, P: PointerKind, A, C>(node: &'r Option<NodeRef<K, V, P, A>>,
+                                                         key: &Q,
+                                                         cmp: &C)
+        -> Option<(Option<NodeRef<K, V, P, A>>, &'r (K, V))>
+    where K: Clone + Borrow<Q>, V: Clone, C: Compare<Q>, A: Measure<K, V>
 {
     if let Some(ref n) = *node {
-        match key.cmp(n.elem.0.borrow()) {
-            Ordering::Less => remove(&n.left, key).map(|(new_left, v)|
-                (Some(Rc::new(balance_left(n.elem.clone(), &new_left, &n.right))), v)
+        match cmp.compare(key, n.elem.0.borrow()) {
+            Ordering::Less => remove(&n.left, key, cmp).map(|(new_left, v)|
+                (Some(NodeRef::new(balance_left(n.elem.clone(), &new_left, &n.right))), v)
             ),
-            Ordering::Greater => remove(&n.right, key).map(|(new_right, v)|
-                (Some(Rc::new(balance_right(n.elem.clone(), &n.left, &new_right))), v)
+            Ordering::Greater => remove(&n.right, key, cmp).map(|(new_right, v)|
+                (Some(NodeRef::new(balance_right(n.elem.clone(), &n.left, &new_right))), v)
             ),
             Ordering::Equal => Some((glue(&n.left, &n.right), &n.elem))
         }
@@ -205,11 +303,844 @@ pub fn remove<'r, Q: ?Sized + Ord, K, V>(node: &'r Option<Rc<TreeNode<K, V>>>, k
     }
 }
 
+// insert the element in place, copying only the nodes shared with other trees.
+// returns the replaced element if the tree already has the key.
+pub fn insert_mut<K, V, P: PointerKind, A, C>(node: &mut Option<NodeRef<K, V, P, A>>,
+                                               elem: (K, V),
+                                               cmp: &C)
+        -> Option<(K, V)>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>
+{
+    match *node {
+        None => {
+            *node = Some(NodeRef::new(TreeNode::new(elem, None, None)));
+            None
+        },
+        Some(ref mut r) => {
+            let n = r.make_mut();
+            let replaced = match cmp.compare(&elem.0, &n.elem.0) {
+                Ordering::Less => insert_mut(&mut n.left, elem, cmp),
+                Ordering::Greater => insert_mut(&mut n.right, elem, cmp),
+                Ordering::Equal => {
+                    let replaced = mem::replace(&mut n.elem, elem);
+                    n.refresh();
+                    return Some(replaced);
+                }
+            };
+            rebalance_mut(n);
+            replaced
+        }
+    }
+}
+
+// remove the element in place, copying only the nodes shared with other trees.
+// assumes that the tree has the key, otherwise the nodes on the path are copied for nothing.
+pub fn remove_mut<Q: ?Sized, K, V, P: PointerKind, A, C>(node: &mut Option<NodeRef<K, V, P, A>>,
+                                                         key: &Q,
+                                                         cmp: &C)
+        -> Option<(K, V)>
+    where K: Clone + Borrow<Q>, V: Clone, C: Compare<Q>, A: Measure<K, V>
+{
+    match *node {
+        None => return None,
+        Some(ref n) if n.size == 1 && cmp.compare(key, n.elem.0.borrow()) == Ordering::Equal => (),
+        Some(ref mut r) => {
+            let n = r.make_mut();
+            let removed = match cmp.compare(key, n.elem.0.borrow()) {
+                Ordering::Less => remove_mut(&mut n.left, key, cmp),
+                Ordering::Greater => remove_mut(&mut n.right, key, cmp),
+                Ordering::Equal => {
+                    let next = if size(&n.left) > size(&n.right) {
+                        delete_max_mut(&mut n.left)
+                    } else {
+                        delete_min_mut(&mut n.right)
+                    };
+                    next.map(|elem| mem::replace(&mut n.elem, elem))
+                }
+            };
+            if removed.is_some() {
+                rebalance_mut(n);
+            }
+            return removed;
+        }
+    }
+
+    // the node is the leaf with the key
+    node.take().map(|r| r.unwrap_or_clone().elem)
+}
+
+fn delete_min_mut<K, V, P: PointerKind, A>(node: &mut Option<NodeRef<K, V, P, A>>) -> Option<(K, V)>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    if let Some(ref mut r) = *node {
+        let n = r.make_mut();
+        if n.left.is_some() {
+            let min = delete_min_mut(&mut n.left);
+            rebalance_mut(n);
+            return min;
+        }
+    }
+
+    node.take().map(|r| {
+        let TreeNode { elem, right, .. } = r.unwrap_or_clone();
+        *node = right;
+        elem
+    })
+}
+
+fn delete_max_mut<K, V, P: PointerKind, A>(node: &mut Option<NodeRef<K, V, P, A>>) -> Option<(K, V)>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    if let Some(ref mut r) = *node {
+        let n = r.make_mut();
+        if n.right.is_some() {
+            let max = delete_max_mut(&mut n.right);
+            rebalance_mut(n);
+            return max;
+        }
+    }
+
+    node.take().map(|r| {
+        let TreeNode { elem, left, .. } = r.unwrap_or_clone();
+        *node = left;
+        elem
+    })
+}
+
+// insert the element as the new leftmost node.
+// assumes that elem < node.leftmost
+fn insert_min<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>, elem: (K, V))
+        -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    match *node {
+        None => TreeNode::new(elem, None, None),
+        Some(ref n) => balance_right_move(n.elem.clone(), insert_min(&n.left, elem), &n.right)
+    }
+}
+
+// insert the element as the new rightmost node.
+// assumes that node.rightmost < elem
+fn insert_max<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>, elem: (K, V))
+        -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    match *node {
+        None => TreeNode::new(elem, None, None),
+        Some(ref n) => balance_left_move(n.elem.clone(), &n.left, insert_max(&n.right, elem))
+    }
+}
+
+// link the two trees together with the element in the middle.
+// assumes that left.rightmost < elem < right.leftmost
+pub fn join<K, V, P: PointerKind, A>(elem: (K, V),
+                     left: &Option<NodeRef<K, V, P, A>>,
+                     right: &Option<NodeRef<K, V, P, A>>) -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    match *left {
+        None => insert_min(right, elem),
+        Some(ref l) => match *right {
+            None => insert_max(left, elem),
+            Some(ref r) =>
+                if !is_balanced(l.size, r.size) {
+                    let new_left = join(elem, left, &r.left);
+                    balance_right_move(r.elem.clone(), new_left, &r.right)
+                } else if !is_balanced(r.size, l.size) {
+                    let new_right = join(elem, &l.right, right);
+                    balance_left_move(l.elem.clone(), &l.left, new_right)
+                } else {
+                    TreeNode::new(elem, left.clone(), right.clone())
+                }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn split<'r, Q: ?Sized, K, V, P: PointerKind, A, C>(node: &'r Option<NodeRef<K, V, P, A>>,
+                                                        key: &Q,
+                                                        cmp: &C)
+        -> (Option<NodeRef<K, V, P, A>>, Option<&'r (K, V)>, Option<NodeRef<K, V, P, A>>)
+    where K: Clone + Borrow<Q>, V: Clone, C: Compare<Q>, A: Measure<K, V>
+{
+    match *node {
+        None => (None, None, None),
+        Some(ref n) => match cmp.compare(key, n.elem.0.borrow()) {
+            Ordering::Less => {
+                let (ll, found, lr) = split(&n.left, key, cmp);
+                (ll, found, Some(NodeRef::new(join(n.elem.clone(), &lr, &n.right))))
+            },
+            Ordering::Greater => {
+                let (rl, found, rr) = split(&n.right, key, cmp);
+                (Some(NodeRef::new(join(n.elem.clone(), &n.left, &rl))), found, rr)
+            },
+            Ordering::Equal => (n.left.clone(), Some(&n.elem), n.right.clone())
+        }
+    }
+}
+
+// merge the two trees together, rebalancing them if the sizes are too different.
+// assumes that left.rightmost < right.leftmost
+pub fn merge<K, V, P: PointerKind, A>(left: &Option<NodeRef<K, V, P, A>>,
+                                      right: &Option<NodeRef<K, V, P, A>>)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    match *left {
+        None => right.clone(),
+        Some(ref l) => match *right {
+            None => left.clone(),
+            Some(ref r) =>
+                if !is_balanced(l.size, r.size) {
+                    let new_left = merge(left, &r.left);
+                    Some(NodeRef::new(balance_right(r.elem.clone(), &new_left, &r.right)))
+                } else if !is_balanced(r.size, l.size) {
+                    let new_right = merge(&l.right, right);
+                    Some(NodeRef::new(balance_left(l.elem.clone(), &l.left, &new_right)))
+                } else {
+                    glue(left, right)
+                }
+        }
+    }
+}
+
+pub fn union<K, V, P: PointerKind, A, C>(a: &Option<NodeRef<K, V, P, A>>,
+                                         b: &Option<NodeRef<K, V, P, A>>,
+                                         cmp: &C)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>
+{
+    match *a {
+        None => b.clone(),
+        Some(ref an) => match *b {
+            None => a.clone(),
+            Some(ref bn) if NodeRef::ptr_eq(an, bn) => a.clone(),
+            Some(_) => {
+                let (bl, _, br) = split(b, &an.elem.0, cmp);
+                let new_left = union(&an.left, &bl, cmp);
+                let new_right = union(&an.right, &br, cmp);
+                Some(NodeRef::new(join(an.elem.clone(), &new_left, &new_right)))
+            }
+        }
+    }
+}
+
+pub fn intersection<K, V, P: PointerKind, A, C>(a: &Option<NodeRef<K, V, P, A>>,
+                                                b: &Option<NodeRef<K, V, P, A>>,
+                                                cmp: &C)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>
+{
+    match *a {
+        None => None,
+        Some(ref an) => match *b {
+            None => None,
+            Some(ref bn) if NodeRef::ptr_eq(an, bn) => a.clone(),
+            Some(_) => {
+                let (bl, found, br) = split(b, &an.elem.0, cmp);
+                let new_left = intersection(&an.left, &bl, cmp);
+                let new_right = intersection(&an.right, &br, cmp);
+                if found.is_some() {
+                    Some(NodeRef::new(join(an.elem.clone(), &new_left, &new_right)))
+                } else {
+                    merge(&new_left, &new_right)
+                }
+            }
+        }
+    }
+}
+
+pub fn difference<K, V, P: PointerKind, A, C>(a: &Option<NodeRef<K, V, P, A>>,
+                                              b: &Option<NodeRef<K, V, P, A>>,
+                                              cmp: &C)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>
+{
+    match *a {
+        None => None,
+        Some(ref an) => match *b {
+            None => a.clone(),
+            Some(ref bn) if NodeRef::ptr_eq(an, bn) => None,
+            Some(ref bn) => {
+                let (al, _, ar) = split(a, &bn.elem.0, cmp);
+                let new_left = difference(&al, &bn.left, cmp);
+                let new_right = difference(&ar, &bn.right, cmp);
+                merge(&new_left, &new_right)
+            }
+        }
+    }
+}
+
+pub fn symmetric_difference<K, V, P: PointerKind, A, C>(a: &Option<NodeRef<K, V, P, A>>,
+                                                        b: &Option<NodeRef<K, V, P, A>>,
+                                                        cmp: &C)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>
+{
+    match *a {
+        None => b.clone(),
+        Some(ref an) => match *b {
+            None => a.clone(),
+            Some(ref bn) if NodeRef::ptr_eq(an, bn) => None,
+            Some(_) => {
+                let (bl, found, br) = split(b, &an.elem.0, cmp);
+                let new_left = symmetric_difference(&an.left, &bl, cmp);
+                let new_right = symmetric_difference(&an.right, &br, cmp);
+                if found.is_some() {
+                    merge(&new_left, &new_right)
+                } else {
+                    Some(NodeRef::new(join(an.elem.clone(), &new_left, &new_right)))
+                }
+            }
+        }
+    }
+}
+
+// combines the two trees of the same type. `f` is called with the key and the values of the keys
+// found in both trees, and the key is removed if it returns `None`. the subtrees with the keys
+// only found in `a` or `b` are kept if `keep_a` or `keep_b` is true, respectively.
+pub fn merge_with<K, V, P: PointerKind, A, F, C>(a: &Option<NodeRef<K, V, P, A>>,
+                                                 b: &Option<NodeRef<K, V, P, A>>,
+                                                 f: &mut F,
+                                                 keep_a: bool,
+                                                 keep_b: bool,
+                                                 cmp: &C)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, C: Compare<K>, A: Measure<K, V>, F: FnMut(&K, &V, &V) -> Option<V>
+{
+    match *a {
+        None => if keep_b { b.clone() } else { None },
+        Some(ref an) => match *b {
+            None => if keep_a { a.clone() } else { None },
+            Some(_) => {
+                let (bl, found, br) = split(b, &an.elem.0, cmp);
+                let new_left = merge_with(&an.left, &bl, f, keep_a, keep_b, cmp);
+                let elem = match found {
+                    Some(b_elem) => f(&an.elem.0, &an.elem.1, &b_elem.1).map(|v|
+                        (an.elem.0.clone(), v)
+                    ),
+                    None if keep_a => Some(an.elem.clone()),
+                    None => None
+                };
+                let new_right = merge_with(&an.right, &br, f, keep_a, keep_b, cmp);
+                match elem {
+                    Some(e) => Some(NodeRef::new(join(e, &new_left, &new_right))),
+                    None => merge(&new_left, &new_right)
+                }
+            }
+        }
+    }
+}
+
+// checks that the keys of `result` are a subset of the keys of `given`. returning `given` itself
+// is checked in constant time, and any other tree by walking both trees in order.
+fn is_key_subset<K, V, W, P: PointerKind, C>(given: &Option<NodeRef<K, V, P>>,
+                                             result: &Option<NodeRef<K, W, P>>,
+                                             cmp: &C) -> bool
+    where C: Compare<K>
+{
+    match *result {
+        None => true,
+        Some(ref r) => match *given {
+            None => false,
+            Some(ref g) => {
+                let same = ptr::eq(&**g as *const TreeNode<K, V, P> as *const u8,
+                                   &**r as *const TreeNode<K, W, P> as *const u8);
+                let mut keys = Iter::new(given).map(|(k, _)| k);
+                same || Iter::new(result).all(|(r, _)|
+                    keys.any(|g| cmp.compare(r, g) == Ordering::Equal)
+                )
+            }
+        }
+    }
+}
+
+// the general combining function which all the merge operations of maps are built on.
+// `only_a` and `only_b` are applied to the largest subtrees with the keys found only in `a` or
+// `b`, and must return trees with the subset of the given keys. panics if they return any other
+// key, which could break the order of the merged tree.
+pub fn merge_with_key<K, V, W, X, P: PointerKind, F, G1, G2, C>(a: &Option<NodeRef<K, V, P>>,
+                                             b: &Option<NodeRef<K, W, P>>,
+                                             f: &mut F,
+                                             only_a: &mut G1,
+                                             only_b: &mut G2,
+                                             cmp: &C)
+        -> Option<NodeRef<K, X, P>>
+    where K: Clone, V: Clone, W: Clone, X: Clone, C: Compare<K>,
+          F: FnMut(&K, &V, &W) -> Option<X>,
+          G1: FnMut(&Option<NodeRef<K, V, P>>) -> Option<NodeRef<K, X, P>>,
+          G2: FnMut(&Option<NodeRef<K, W, P>>) -> Option<NodeRef<K, X, P>>
+{
+    match *a {
+        None => {
+            let result = only_b(b);
+            assert!(is_key_subset(b, &result, cmp),
+                    "only_other returned a map with keys not in the given map");
+            result
+        },
+        Some(ref an) => match *b {
+            None => {
+                let result = only_a(a);
+                assert!(is_key_subset(a, &result, cmp),
+                        "only_self returned a map with keys not in the given map");
+                result
+            },
+            Some(_) => {
+                let (bl, found, br) = split(b, &an.elem.0, cmp);
+                match found {
+                    Some(b_elem) => {
+                        let new_left = merge_with_key(&an.left, &bl, f, only_a, only_b, cmp);
+                        let elem = f(&an.elem.0, &an.elem.1, &b_elem.1).map(|x|
+                            (an.elem.0.clone(), x)
+                        );
+                        let new_right = merge_with_key(&an.right, &br, f, only_a, only_b, cmp);
+                        match elem {
+                            Some(e) => Some(NodeRef::new(join(e, &new_left, &new_right))),
+                            None => merge(&new_left, &new_right)
+                        }
+                    },
+                    None => {
+                        // the keys of `a` between the neighbours of the key in `b` are only in
+                        // `a`, so they are split out and passed to `only_a` as a whole.
+                        let (below, rest) = match bl {
+                            None => (None, a.clone()),
+                            Some(ref l) => match split(a, &find_max(l).0, cmp) {
+                                (ll, Some(e), lr) =>
+                                    (Some(NodeRef::new(insert_max(&ll, e.clone()))), lr),
+                                (ll, None, lr) => (ll, lr)
+                            }
+                        };
+                        let (run, above) = match br {
+                            None => (rest, None),
+                            Some(ref r) => match split(&rest, &find_min(r).0, cmp) {
+                                (rl, Some(e), rr) =>
+                                    (rl, Some(NodeRef::new(insert_min(&rr, e.clone())))),
+                                (rl, None, rr) => (rl, rr)
+                            }
+                        };
+                        let new_left = merge_with_key(&below, &bl, f, only_a, only_b, cmp);
+                        let new_run = only_a(&run);
+                        assert!(is_key_subset(&run, &new_run, cmp),
+                                "only_self returned a map with keys not in the given map");
+                        let new_right = merge_with_key(&above, &br, f, only_a, only_b, cmp);
+                        merge(&merge(&new_left, &new_run), &new_right)
+                    }
+                }
+            }
+        }
+    }
+}
+
+// maps the values of the tree, keeping the shape of the tree. `f` is called in the key order.
+pub fn map<K, V, W, P: PointerKind, F>(node: &Option<NodeRef<K, V, P>>, f: &mut F)
+        -> Option<NodeRef<K, W, P>>
+    where K: Clone, F: FnMut(&K, &V) -> W
+{
+    node.as_ref().map(|n| {
+        let left = map(&n.left, f);
+        let value = f(&n.elem.0, &n.elem.1);
+        let right = map(&n.right, f);
+        NodeRef::new(TreeNode::new((n.elem.0.clone(), value), left, right))
+    })
+}
+
+// removes the elements for which `f` returns false. the subtrees which keep all of their
+// elements are shared with the given tree. `f` is called in the key order.
+pub fn filter<K, V, P: PointerKind, A, F>(node: &Option<NodeRef<K, V, P, A>>, f: &mut F)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, A: Measure<K, V>, F: FnMut(&K, &V) -> bool
+{
+    match *node {
+        None => None,
+        Some(ref n) => {
+            let left = filter(&n.left, f);
+            let keep = f(&n.elem.0, &n.elem.1);
+            let right = filter(&n.right, f);
+            rebuild(n, keep, left, right)
+        }
+    }
+}
+
+// removes the elements for which `f` returns false in place. the nodes not shared with other
+// trees are reused, and the shared subtrees are filtered as with `filter`, sharing the subtrees
+// which keep all of their elements. `f` is called in the key order.
+pub fn filter_mut<K, V, P: PointerKind, A, F>(node: &mut Option<NodeRef<K, V, P, A>>, f: &mut F)
+    where K: Clone, V: Clone, A: Measure<K, V>, F: FnMut(&K, &V) -> bool
+{
+    let new_node = match node.as_mut().map(NodeRef::get_mut) {
+        None => return,
+        Some(None) => filter(node, f),
+        Some(Some(n)) => {
+            filter_mut(&mut n.left, f);
+            let keep = f(&n.elem.0, &n.elem.1);
+            filter_mut(&mut n.right, f);
+            let lsize = size(&n.left);
+            let rsize = size(&n.right);
+            if !keep {
+                merge(&n.left, &n.right)
+            } else if is_balanced(lsize, rsize) && is_balanced(rsize, lsize) {
+                n.refresh();
+                return;
+            } else {
+                Some(NodeRef::new(join(n.elem.clone(), &n.left, &n.right)))
+            }
+        }
+    };
+    *node = new_node;
+}
+
+// splits the tree into the elements for which `f` returns true, and the rest. the subtrees which
+// go entirely to one side are shared with the given tree. `f` is called in the key order.
+#[allow(clippy::type_complexity)]
+pub fn partition<K, V, P: PointerKind, A, F>(node: &Option<NodeRef<K, V, P, A>>, f: &mut F)
+        -> (Option<NodeRef<K, V, P, A>>, Option<NodeRef<K, V, P, A>>)
+    where K: Clone, V: Clone, A: Measure<K, V>, F: FnMut(&K, &V) -> bool
+{
+    match *node {
+        None => (None, None),
+        Some(ref n) => {
+            let (left_in, left_out) = partition(&n.left, f);
+            let keep = f(&n.elem.0, &n.elem.1);
+            let (right_in, right_out) = partition(&n.right, f);
+            (rebuild(n, keep, left_in, right_in), rebuild(n, !keep, left_out, right_out))
+        }
+    }
+}
+
+// rebuilds the node from the filtered subtrees, keeping the element of the node if `keep` is
+// true. the node is shared if nothing is removed from it.
+fn rebuild<K, V, P: PointerKind, A>(node: &NodeRef<K, V, P, A>,
+                                    keep: bool,
+                                    left: Option<NodeRef<K, V, P, A>>,
+                                    right: Option<NodeRef<K, V, P, A>>)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    if !keep {
+        merge(&left, &right)
+    } else if size(&left) + size(&right) + 1 == node.size {
+        Some(node.clone())
+    } else {
+        Some(NodeRef::new(join(node.elem.clone(), &left, &right)))
+    }
+}
+
+// maps the values of the tree, and removes the elements for which `f` returns `None`. `f` is
+// called in the key order.
+pub fn filter_map<K, V, W, P: PointerKind, F>(node: &Option<NodeRef<K, V, P>>, f: &mut F)
+        -> Option<NodeRef<K, W, P>>
+    where K: Clone, W: Clone, F: FnMut(&K, &V) -> Option<W>
+{
+    match *node {
+        None => None,
+        Some(ref n) => {
+            let left = filter_map(&n.left, f);
+            let value = f(&n.elem.0, &n.elem.1);
+            let right = filter_map(&n.right, f);
+            match value {
+                Some(v) => Some(NodeRef::new(join((n.elem.0.clone(), v), &left, &right))),
+                None => merge(&left, &right)
+            }
+        }
+    }
+}
+
+pub fn is_sorted<K, V, C: Compare<K>>(elems: &[(K, V)], cmp: &C) -> bool {
+    elems.windows(2).all(|w| cmp.compare(&w[0].0, &w[1].0) != Ordering::Greater)
+}
+
+pub fn is_strictly_sorted<K, V, C: Compare<K>>(elems: &[(K, V)], cmp: &C) -> bool {
+    elems.windows(2).all(|w| cmp.compare(&w[0].0, &w[1].0) == Ordering::Less)
+}
+
+// removes the consecutive elements with the same key, keeping the last one.
+pub fn dedup_sorted<K, V, C: Compare<K>>(elems: &mut Vec<(K, V)>, cmp: &C) {
+    elems.dedup_by(|next, prev| if cmp.compare(&next.0, &prev.0) == Ordering::Equal {
+        mem::swap(next, prev);
+        true
+    } else {
+        false
+    });
+}
+
+// build a perfectly balanced tree from the sorted elements, in O(n) time.
+// assumes that the elements are strictly sorted
+pub fn from_sorted_vec<K, V, P: PointerKind, A>(elems: Vec<(K, V)>) -> Option<NodeRef<K, V, P, A>>
+    where A: Measure<K, V>
+{
+    fn build<K, V, P: PointerKind, A, I>(iter: &mut I, n: usize) -> Option<NodeRef<K, V, P, A>>
+        where I: Iterator<Item=(K, V)>, A: Measure<K, V>
+    {
+        if n == 0 {
+            return None;
+        }
+
+        let left_size = (n - 1) / 2;
+        let left = build(iter, left_size);
+        let elem = iter.next().expect("iterator is shorter than expected");
+        let right = build(iter, n - 1 - left_size);
+        Some(NodeRef::new(TreeNode::new(elem, left, right)))
+    }
+
+    let n = elems.len();
+    build(&mut elems.into_iter(), n)
+}
+
+pub fn find_index<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>, index: usize)
+        -> Option<&(K, V)>
+    where A: Measure<K, V>
+{
+    let mut cursor = node;
+    let mut idx = index;
+    loop {
+        match *cursor {
+            None => return None,
+            Some(ref n) => {
+                let lsize = size(&n.left);
+                match idx.cmp(&lsize) {
+                    Ordering::Less => cursor = &n.left,
+                    Ordering::Equal => return Some(&n.elem),
+                    Ordering::Greater => {
+                        idx -= lsize + 1;
+                        cursor = &n.right;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// finds the element at the given position, where each element takes as many positions as its
+// measure
+pub fn find_weighted_index<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>,
+                                                    index: usize)
+        -> Option<&(K, V)>
+    where A: Measure<K, V, Summary=usize>
+{
+    let mut cursor = node;
+    let mut idx = index;
+    loop {
+        match *cursor {
+            None => return None,
+            Some(ref n) => {
+                let lsum = summary(&n.left);
+                if idx < lsum {
+                    cursor = &n.left;
+                    continue;
+                }
+                let weight = A::measure(&n.elem.0, &n.elem.1);
+                if idx < lsum + weight {
+                    return Some(&n.elem);
+                }
+                idx -= lsum + weight;
+                cursor = &n.right;
+            }
+        }
+    }
+}
+
+pub fn rank<K, V, P: PointerKind, A, Q: ?Sized, C>(node: &Option<NodeRef<K, V, P, A>>,
+                                                   key: &Q,
+                                                   cmp: &C)
+        -> Option<usize>
+    where K: Borrow<Q>, C: Compare<Q>, A: Measure<K, V>
+{
+    let mut cursor = node;
+    let mut offset = 0;
+    loop {
+        match *cursor {
+            None => return None,
+            Some(ref n) => match cmp.compare(key, n.elem.0.borrow()) {
+                Ordering::Less => cursor = &n.left,
+                Ordering::Equal => return Some(offset + size(&n.left)),
+                Ordering::Greater => {
+                    offset += size(&n.left) + 1;
+                    cursor = &n.right;
+                }
+            }
+        }
+    }
+}
+
+// the number of the elements in the range, which is computed from the ranks of the bounds in
+// O(log n) time
+pub fn range_count<K, V, P: PointerKind, A, Q: ?Sized, C>(node: &Option<NodeRef<K, V, P, A>>,
+                                                          min: Bound<&Q>, max: Bound<&Q>,
+                                                          cmp: &C)
+        -> usize
+    where K: Borrow<Q>, C: Compare<Q>, A: Measure<K, V>
+{
+    let lower = match min {
+        Bound::Unbounded => 0,
+        Bound::Included(key) => count_below(node, key, false, cmp),
+        Bound::Excluded(key) => count_below(node, key, true, cmp)
+    };
+    let upper = match max {
+        Bound::Unbounded => size(node),
+        Bound::Included(key) => count_below(node, key, true, cmp),
+        Bound::Excluded(key) => count_below(node, key, false, cmp)
+    };
+    upper.saturating_sub(lower)
+}
+
+// the number of the elements whose keys are less than the key, or not greater than the key if
+// `inclusive` is true
+fn count_below<K, V, P: PointerKind, A, Q: ?Sized, C>(node: &Option<NodeRef<K, V, P, A>>,
+                                                      key: &Q,
+                                                      inclusive: bool,
+                                                      cmp: &C)
+        -> usize
+    where K: Borrow<Q>, C: Compare<Q>, A: Measure<K, V>
+{
+    let mut cursor = node;
+    let mut count = 0;
+    while let Some(ref n) = *cursor {
+        match cmp.compare(key, n.elem.0.borrow()) {
+            Ordering::Less => cursor = &n.left,
+            Ordering::Equal if !inclusive => return count + size(&n.left),
+            _ => {
+                count += size(&n.left) + 1;
+                cursor = &n.right;
+            }
+        }
+    }
+    count
+}
+
+// the summary of the elements in the range, in O(log n) time.
+// once the range splits at a node, only one bound is left on each side, and the subtrees on the
+// other side of the bound are summarized by their cached summaries.
+pub fn aggregate<K, V, P: PointerKind, A, Q: ?Sized, C>(node: &Option<NodeRef<K, V, P, A>>,
+                                                        min: Bound<&Q>,
+                                                        max: Bound<&Q>,
+                                                        cmp: &C)
+        -> A::Summary
+    where K: Borrow<Q>, C: Compare<Q>, A: Measure<K, V>
+{
+    let n = match *node {
+        None => return A::empty(),
+        Some(ref n) => n
+    };
+
+    let above_min = match min {
+        Bound::Unbounded => true,
+        Bound::Included(lower) => cmp.compare(n.elem.0.borrow(), lower) != Ordering::Less,
+        Bound::Excluded(lower) => cmp.compare(n.elem.0.borrow(), lower) == Ordering::Greater
+    };
+    let below_max = match max {
+        Bound::Unbounded => true,
+        Bound::Included(upper) => cmp.compare(n.elem.0.borrow(), upper) != Ordering::Greater,
+        Bound::Excluded(upper) => cmp.compare(n.elem.0.borrow(), upper) == Ordering::Less
+    };
+
+    if !above_min {
+        aggregate(&n.right, min, max, cmp)
+    } else if !below_max {
+        aggregate(&n.left, min, max, cmp)
+    } else if let (Bound::Unbounded, Bound::Unbounded) = (min, max) {
+        summarize(&n.elem, &n.left, &n.right)
+    } else {
+        let left = aggregate(&n.left, min, Bound::Unbounded, cmp);
+        let right = aggregate(&n.right, Bound::Unbounded, max, cmp);
+        A::combine(&A::combine(&left, &A::measure(&n.elem.0, &n.elem.1)), &right)
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn remove_index<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>, index: usize)
+        -> Option<(Option<NodeRef<K, V, P, A>>, &(K, V))>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    if let Some(ref n) = *node {
+        let lsize = size(&n.left);
+        match index.cmp(&lsize) {
+            Ordering::Less => remove_index(&n.left, index).map(|(new_left, v)|
+                (Some(NodeRef::new(balance_left(n.elem.clone(), &new_left, &n.right))), v)
+            ),
+            Ordering::Greater => remove_index(&n.right, index - lsize - 1).map(|(new_right, v)|
+                (Some(NodeRef::new(balance_right(n.elem.clone(), &n.left, &new_right))), v)
+            ),
+            Ordering::Equal => Some((glue(&n.left, &n.right), &n.elem))
+        }
+    } else {
+        None
+    }
+}
+
+// replaces the element at the given position, keeping the shape of the tree.
+// assumes that index < size(node)
+pub fn replace_index<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>,
+                                              index: usize,
+                                              elem: (K, V))
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    node.as_ref().map(|n| {
+        let lsize = size(&n.left);
+        let new_node = match index.cmp(&lsize) {
+            Ordering::Less => {
+                let new_left = replace_index(&n.left, index, elem);
+                TreeNode::new(n.elem.clone(), new_left, n.right.clone())
+            },
+            Ordering::Greater => {
+                let new_right = replace_index(&n.right, index - lsize - 1, elem);
+                TreeNode::new(n.elem.clone(), n.left.clone(), new_right)
+            },
+            Ordering::Equal => TreeNode::new(elem, n.left.clone(), n.right.clone())
+        };
+        NodeRef::new(new_node)
+    })
+}
+
+// split the tree into the first `index` elements and the rest.
+#[allow(clippy::type_complexity)]
+pub fn split_at<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>, index: usize)
+        -> (Option<NodeRef<K, V, P, A>>, Option<NodeRef<K, V, P, A>>)
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    match *node {
+        None => (None, None),
+        Some(_) if index == 0 => (None, node.clone()),
+        Some(ref n) if index >= n.size => (node.clone(), None),
+        Some(ref n) => {
+            let lsize = size(&n.left);
+            if index <= lsize {
+                let (ll, lr) = split_at(&n.left, index);
+                (ll, Some(NodeRef::new(join(n.elem.clone(), &lr, &n.right))))
+            } else {
+                let (rl, rr) = split_at(&n.right, index - lsize - 1);
+                (Some(NodeRef::new(join(n.elem.clone(), &n.left, &rl))), rr)
+            }
+        }
+    }
+}
+
+pub fn find_min<K, V, P: PointerKind, A>(node: &TreeNode<K, V, P, A>) -> &(K, V)
+    where A: Measure<K, V>
+{
+    let mut cursor = node;
+    while let Some(ref l) = cursor.left {
+        cursor = l;
+    }
+    &cursor.elem
+}
+
+pub fn find_max<K, V, P: PointerKind, A>(node: &TreeNode<K, V, P, A>) -> &(K, V)
+    where A: Measure<K, V>
+{
+    let mut cursor = node;
+    while let Some(ref r) = cursor.right {
+        cursor = r;
+    }
+    &cursor.elem
+}
+
 // merge the two trees together.
 // assumes that left.rightmost < right.leftmost
-fn glue<K, V>(left: &Option<Rc<TreeNode<K, V>>>, right: &Option<Rc<TreeNode<K, V>>>)
-        -> Option<Rc<TreeNode<K, V>>>
-    where K: Clone, V: Clone
+fn glue<K, V, P: PointerKind, A>(left: &Option<NodeRef<K, V, P, A>>,
+                                 right: &Option<NodeRef<K, V, P, A>>)
+        -> Option<NodeRef<K, V, P, A>>
+    where K: Clone, V: Clone, A: Measure<K, V>
 {
     match *left {
         None => right.clone(),
@@ -218,10 +1149,10 @@ fn glue<K, V>(left: &Option<Rc<TreeNode<K, V>>>, right: &Option<Rc<TreeNode<K, V
             Some(ref r) =>
                 if l.size > r.size {
                     let (new_l, elem) = delete_max(l);
-                    Some(Rc::new(balance_left_move(elem.clone(), &new_l, (**r).clone())))
+                    Some(NodeRef::new(balance_left_move(elem.clone(), &new_l, (**r).clone())))
                 } else {
                     let (new_r, elem) = delete_min(r);
-                    Some(Rc::new(balance_right_move(elem.clone(), (**l).clone(), &new_r)))
+                    Some(NodeRef::new(balance_right_move(elem.clone(), (**l).clone(), &new_r)))
                 }
         }
     }
@@ -229,7 +1160,7 @@ fn glue<K, V>(left: &Option<Rc<TreeNode<K, V>>>, right: &Option<Rc<TreeNode<K, V
 
 pub fn is_balanced(a: usize, b: usize) -> bool
 {
-    DELTA * (a + 1) >= b + 1
+    DELTA * (a + 1) > b
 }
 
 fn is_single(a: usize, b: usize) -> bool
@@ -237,17 +1168,46 @@ fn is_single(a: usize, b: usize) -> bool
     a + 1 < GAMMA * (b + 1)
 }
 
-pub fn size<K, V>(node: &Option<Rc<TreeNode<K, V>>>) -> usize {
+pub fn size<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>) -> usize
+    where A: Summarize<K, V>
+{
     match *node {
         None => 0,
         Some(ref n) => n.size
     }
 }
 
-fn balance_left<K, V>(elem: (K, V),
-                      left: &Option<Rc<TreeNode<K, V>>>,
-                      right: &Option<Rc<TreeNode<K, V>>>) -> TreeNode<K, V>
-    where K: Clone, V: Clone
+// the summary of the whole tree, recomputed from the cached summaries of the children of the root.
+pub fn summary<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>) -> A::Summary
+    where A: Measure<K, V>
+{
+    match *node {
+        None => A::empty(),
+        Some(ref n) => summarize(&n.elem, &n.left, &n.right)
+    }
+}
+
+// the summary of the subtree made of the element and the two subtrees.
+fn summarize<K, V, P: PointerKind, A>(elem: &(K, V),
+                                      left: &Option<NodeRef<K, V, P, A>>,
+                                      right: &Option<NodeRef<K, V, P, A>>) -> A::Summary
+    where A: Measure<K, V>
+{
+    let mid = A::measure(&elem.0, &elem.1);
+    let with_left = match *left {
+        None => mid,
+        Some(ref l) => A::combine(&l.summary, &mid)
+    };
+    match *right {
+        None => with_left,
+        Some(ref r) => A::combine(&with_left, &r.summary)
+    }
+}
+
+fn balance_left<K, V, P: PointerKind, A>(elem: (K, V),
+                         left: &Option<NodeRef<K, V, P, A>>,
+                         right: &Option<NodeRef<K, V, P, A>>) -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
 {
     if let Some(ref r) = *right {
         balance_left_move(elem, left, (**r).clone())
@@ -256,21 +1216,21 @@ fn balance_left<K, V>(elem: (K, V),
     }
 }
 
-fn balance_left_move<K, V>(elem: (K, V),
-                           left: &Option<Rc<TreeNode<K, V>>>,
-                           right: TreeNode<K, V>) -> TreeNode<K, V>
-    where K: Clone, V: Clone
+fn balance_left_move<K, V, P: PointerKind, A>(elem: (K, V),
+                              left: &Option<NodeRef<K, V, P, A>>,
+                              right: TreeNode<K, V, P, A>) -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
 {
     let lsize = size(left);
     if is_balanced(lsize, right.size) {
-        TreeNode::new(elem, left.clone(), Some(Rc::new(right)))
+        TreeNode::new(elem, left.clone(), Some(NodeRef::new(right)))
     } else {
-        let TreeNode { elem: r_elem, size: _, left: rl, right: rr } = right;
+        let TreeNode { elem: r_elem, left: rl, right: rr, .. } = right;
         if is_single(size(&rl), size(&rr)) {
             let new_l = TreeNode::new(elem, left.clone(), rl);
             TreeNode::new(
                 r_elem,
-                Some(Rc::new(new_l)),
+                Some(NodeRef::new(new_l)),
                 rr
             )
         } else {
@@ -279,8 +1239,8 @@ fn balance_left_move<K, V>(elem: (K, V),
                 let new_r = TreeNode::new(r_elem, rl_node.right.clone(), rr);
                 TreeNode::new(
                     rl_node.elem.clone(),
-                    Some(Rc::new(new_l)),
-                    Some(Rc::new(new_r))
+                    Some(NodeRef::new(new_l)),
+                    Some(NodeRef::new(new_r))
                 )
             } else {
                 panic!("size invariant does not match!")
@@ -289,10 +1249,10 @@ fn balance_left_move<K, V>(elem: (K, V),
     }
 }
 
-fn balance_right<K, V>(elem: (K, V),
-                       left: &Option<Rc<TreeNode<K, V>>>,
-                       right: &Option<Rc<TreeNode<K, V>>>) -> TreeNode<K, V>
-    where K: Clone, V: Clone
+fn balance_right<K, V, P: PointerKind, A>(elem: (K, V),
+                          left: &Option<NodeRef<K, V, P, A>>,
+                          right: &Option<NodeRef<K, V, P, A>>) -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
 {
     if let Some(ref l) = *left {
         balance_right_move(elem, (**l).clone(), right)
@@ -301,22 +1261,22 @@ fn balance_right<K, V>(elem: (K, V),
     }
 }
 
-fn balance_right_move<K, V>(elem: (K, V),
-                            left: TreeNode<K, V>,
-                            right: &Option<Rc<TreeNode<K, V>>>) -> TreeNode<K, V>
-    where K: Clone, V: Clone
+fn balance_right_move<K, V, P: PointerKind, A>(elem: (K, V),
+                               left: TreeNode<K, V, P, A>,
+                               right: &Option<NodeRef<K, V, P, A>>) -> TreeNode<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
 {
     let rsize = size(right);
     if is_balanced(rsize, left.size) {
-        TreeNode::new(elem, Some(Rc::new(left)), right.clone())
+        TreeNode::new(elem, Some(NodeRef::new(left)), right.clone())
     } else {
-        let TreeNode { elem: l_elem, size: _, left: ll, right: lr } = left;
+        let TreeNode { elem: l_elem, left: ll, right: lr, .. } = left;
         if is_single(size(&lr), size(&ll)) {
             let new_r = TreeNode::new(elem, lr, right.clone());
             TreeNode::new(
                 l_elem,
                 ll,
-                Some(Rc::new(new_r)),
+                Some(NodeRef::new(new_r)),
             )
         } else {
             if let Some(ref lr_node) = lr {
@@ -324,8 +1284,8 @@ fn balance_right_move<K, V>(elem: (K, V),
                 let new_r = TreeNode::new(elem, lr_node.right.clone(), right.clone());
                 TreeNode::new(
                     lr_node.elem.clone(),
-                    Some(Rc::new(new_l)),
-                    Some(Rc::new(new_r))
+                    Some(NodeRef::new(new_l)),
+                    Some(NodeRef::new(new_r))
                 )
             } else {
                 panic!("size invariant does not match!")
@@ -334,24 +1294,150 @@ fn balance_right_move<K, V>(elem: (K, V),
     }
 }
 
+// restore the balance of the node after one of its subtrees has grown or shrunk by one element.
+// the rotations are done in place, copying only the nodes shared with other trees.
+fn rebalance_mut<K, V, P: PointerKind, A>(node: &mut TreeNode<K, V, P, A>)
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    let lsize = size(&node.left);
+    let rsize = size(&node.right);
+    if !is_balanced(lsize, rsize) {
+        if let Some(ref mut r) = node.right {
+            let r = r.make_mut();
+            if !is_single(size(&r.left), size(&r.right)) {
+                rotate_right_mut(r);
+            }
+        }
+        rotate_left_mut(node);
+    } else if !is_balanced(rsize, lsize) {
+        if let Some(ref mut l) = node.left {
+            let l = l.make_mut();
+            if !is_single(size(&l.right), size(&l.left)) {
+                rotate_left_mut(l);
+            }
+        }
+        rotate_right_mut(node);
+    }
+    node.refresh();
+}
+
+// (a, x, (b, y, c)) => ((a, x, b), y, c)
+fn rotate_left_mut<K, V, P: PointerKind, A>(node: &mut TreeNode<K, V, P, A>)
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    let mut right = node.right.take().expect("size invariant does not match!");
+    {
+        let r = right.make_mut();
+        mem::swap(&mut node.elem, &mut r.elem);
+        node.right = r.right.take();
+        r.right = r.left.take();
+        r.left = node.left.take();
+        r.refresh();
+    }
+    node.left = Some(right);
+}
+
+// ((a, x, b), y, c) => (a, x, (b, y, c))
+fn rotate_right_mut<K, V, P: PointerKind, A>(node: &mut TreeNode<K, V, P, A>)
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    let mut left = node.left.take().expect("size invariant does not match!");
+    {
+        let l = left.make_mut();
+        mem::swap(&mut node.elem, &mut l.elem);
+        node.left = l.left.take();
+        l.left = l.right.take();
+        l.right = node.right.take();
+        l.refresh();
+    }
+    node.right = Some(left);
+}
+
+// an iterator over the elements of a tree in both directions.
+// `stack` and `rev_stack` hold the left and the right edges of the remaining elements, and the
+// iteration ends when `size` elements are taken from either end, so the edges never have to be
+// compared with each other.
 #[derive(Clone)]
-pub struct Iter<'r, K: 'r, V: 'r> {
-    stack: Vec<&'r TreeNode<K, V>>,
+pub struct Iter<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V> = ()> {
+    stack: Vec<&'r TreeNode<K, V, P, A>>,
+    rev_stack: Vec<&'r TreeNode<K, V, P, A>>,
     size: usize
 }
 
-impl<'r, K: 'r, V: 'r> Iter<'r, K, V> {
-    pub fn new(node: &'r Option<Rc<TreeNode<K, V>>>) -> Iter<'r, K, V> {
-        let mut iter = Iter { stack: Vec::new(), size: size(node) };
+pub type RevIter<'r, K, V, P, A = ()> = Rev<Iter<'r, K, V, P, A>>;
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V>> Iter<'r, K, V, P, A> {
+    pub fn new(node: &'r Option<NodeRef<K, V, P, A>>) -> Iter<'r, K, V, P, A> {
+        let mut iter = Iter { stack: Vec::new(), rev_stack: Vec::new(), size: size(node) };
 
         if let Some(ref n) = *node {
-            iter.push_left(n);
+            iter.left_edge(n);
+            iter.right_edge(n);
+        }
+
+        iter
+    }
+
+    // an iterator over the elements at the positions from `start` to `end`, exclusive.
+    // assumes that start <= end <= size(node)
+    pub fn with_index_range(node: &'r Option<NodeRef<K, V, P, A>>, start: usize, end: usize)
+            -> Iter<'r, K, V, P, A>
+    {
+        let mut iter = Iter { stack: Vec::new(), rev_stack: Vec::new(), size: end - start };
+
+        if start < end {
+            iter.left_edge_at(node, start);
+            iter.right_edge_at(node, end - 1);
         }
 
         iter
     }
 
-    fn push_left(&mut self, node: &'r TreeNode<K, V>) {
+    fn left_edge_at(&mut self, node: &'r Option<NodeRef<K, V, P, A>>, index: usize) {
+        let mut cursor = node;
+        let mut idx = index;
+
+        while let Some(ref n) = *cursor {
+            let lsize = size(&n.left);
+            match idx.cmp(&lsize) {
+                Ordering::Less => {
+                    self.stack.push(n);
+                    cursor = &n.left;
+                },
+                Ordering::Equal => {
+                    self.stack.push(n);
+                    break;
+                },
+                Ordering::Greater => {
+                    idx -= lsize + 1;
+                    cursor = &n.right;
+                }
+            }
+        }
+    }
+
+    fn right_edge_at(&mut self, node: &'r Option<NodeRef<K, V, P, A>>, index: usize) {
+        let mut cursor = node;
+        let mut idx = index;
+
+        while let Some(ref n) = *cursor {
+            let lsize = size(&n.left);
+            match idx.cmp(&lsize) {
+                Ordering::Less => cursor = &n.left,
+                Ordering::Equal => {
+                    self.rev_stack.push(n);
+                    break;
+                },
+                Ordering::Greater => {
+                    self.rev_stack.push(n);
+                    idx -= lsize + 1;
+                    cursor = &n.right;
+                }
+            }
+        }
+    }
+
+    fn left_edge(&mut self, node: &'r TreeNode<K, V, P, A>) {
         let mut cursor = node;
 
         loop {
@@ -362,24 +1448,36 @@ impl<'r, K: 'r, V: 'r> Iter<'r, K, V> {
             }
         }
     }
+
+    fn right_edge(&mut self, node: &'r TreeNode<K, V, P, A>) {
+        let mut cursor = node;
+
+        loop {
+            self.rev_stack.push(cursor);
+            match cursor.right {
+                None => break,
+                Some(ref r) => cursor = r
+            }
+        }
+    }
 }
 
-impl<'r, K: 'r, V: 'r> Iterator for Iter<'r, K, V> {
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V>> Iterator for Iter<'r, K, V, P, A> {
     type Item = (&'r K, &'r V);
 
     fn next(&mut self) -> Option<(&'r K, &'r V)> {
-        let top = match self.stack.pop() {
-            None => return None,
-            Some(t) => t
-        };
+        if self.size == 0 {
+            return None;
+        }
 
-        let ret = (&top.elem.0, &top.elem.1);
+        let top = self.stack.pop().expect("the left edge is exhausted");
+        self.size -= 1;
 
         if let Some(ref r) = top.right {
-            self.push_left(r);
+            self.left_edge(r);
         }
 
-        Some(ret)
+        Some((&top.elem.0, &top.elem.1))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -387,81 +1485,101 @@ impl<'r, K: 'r, V: 'r> Iterator for Iter<'r, K, V> {
     }
 }
 
-impl<'r, K: 'r, V: 'r> ExactSizeIterator for Iter<'r, K, V> {
+impl<'r, K: 'r, V: 'r, P: PointerKind, A> DoubleEndedIterator for Iter<'r, K, V, P, A>
+    where A: Summarize<K, V>
+{
+    fn next_back(&mut self) -> Option<(&'r K, &'r V)> {
+        if self.size == 0 {
+            return None;
+        }
+
+        let top = self.rev_stack.pop().expect("the right edge is exhausted");
+        self.size -= 1;
+
+        if let Some(ref l) = top.left {
+            self.right_edge(l);
+        }
+
+        Some((&top.elem.0, &top.elem.1))
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A> ExactSizeIterator for Iter<'r, K, V, P, A>
+    where A: Summarize<K, V>
+{
     fn len(&self) -> usize {
         self.size
     }
 }
 
+// an iterator over the elements in a range of keys. the comparator is used only to find the
+// edges of the range.
 #[derive(Clone)]
-pub struct RevIter<'r, K: 'r, V: 'r> {
-    stack: Vec<&'r TreeNode<K, V>>,
-    size: usize
+pub struct Range<'r, K: 'r, V: 'r, P: PointerKind, C: 'r, A: Summarize<K, V> = ()> {
+    iter: Iter<'r, K, V, P, A>,
+    marker: PhantomData<&'r C>
 }
 
-impl<'r, K: 'r, V: 'r> RevIter<'r, K, V> {
-    pub fn new(node: &'r Option<Rc<TreeNode<K, V>>>) -> RevIter<'r, K, V> {
-        let mut iter = RevIter { stack: Vec::new(), size: size(node) };
+// a query of `Search`, which prunes the subtrees by their summaries.
+pub trait Query<K, V, A: Summarize<K, V>> {
+    // returns false if no element summarized by `summary` matches the query. an element matches
+    // if `visit` returns true for the summary of the element alone, and `stop` returns false.
+    fn visit(&self, summary: &A::Summary) -> bool;
 
-        if let Some(ref n) = *node {
-            iter.push_right(n);
-        }
+    // returns true if neither the key nor the greater keys match the query
+    fn stop(&self, key: &K) -> bool;
+}
 
-        iter
+// an iterator over the elements matching the query in order, which skips the subtrees rejected
+// by the query. only the subtrees accepted by `visit` are entered, so the time to yield k elements
+// is O((k + 1) log n) if every accepted subtree contains a matching element or a stopping key.
+#[derive(Clone)]
+pub struct Search<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V>, Q> {
+    stack: Vec<&'r TreeNode<K, V, P, A>>,
+    query: Q
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Measure<K, V>, Q> Search<'r, K, V, P, A, Q>
+    where Q: Query<K, V, A>
+{
+    pub fn new(node: &'r Option<NodeRef<K, V, P, A>>, query: Q) -> Search<'r, K, V, P, A, Q> {
+        let mut search = Search { stack: Vec::new(), query };
+        search.left_edge(node);
+        search
     }
 
-    fn push_right(&mut self, node: &'r TreeNode<K, V>) {
+    fn left_edge(&mut self, node: &'r Option<NodeRef<K, V, P, A>>) {
         let mut cursor = node;
-
-        loop {
-            self.stack.push(cursor);
-            match cursor.right {
-                None => break,
-                Some(ref r) => cursor = r
+        while let Some(ref n) = *cursor {
+            if !self.query.visit(&n.summary) {
+                break;
             }
+            self.stack.push(n);
+            cursor = &n.left;
         }
     }
 }
 
-impl<'r, K: 'r, V: 'r> Iterator for RevIter<'r, K, V> {
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Measure<K, V>, Q> Iterator for Search<'r, K, V, P, A, Q>
+    where Q: Query<K, V, A>
+{
     type Item = (&'r K, &'r V);
 
     fn next(&mut self) -> Option<(&'r K, &'r V)> {
-        let top = match self.stack.pop() {
-            None => return None,
-            Some(t) => t
-        };
-
-        let ret = (&top.elem.0, &top.elem.1);
-
-        if let Some(ref r) = top.left {
-            self.push_right(r);
-        }
-
-        Some(ret)
-    }
-
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        let mut n = 0;
-        for node in &self.stack {
-            n += size(&node.left) + 1
-        }
-        (n, Some(n))
-    }
-}
-
-impl<'r, K: 'r, V: 'r> ExactSizeIterator for RevIter<'r, K, V> {
-    fn len(&self) -> usize {
-        self.size
+        while let Some(n) = self.stack.pop() {
+            if self.query.stop(&n.elem.0) {
+                self.stack.clear();
+                return None;
+            }
+            self.left_edge(&n.right);
+            if self.query.visit(&A::measure(&n.elem.0, &n.elem.1)) {
+                return Some((&n.elem.0, &n.elem.1));
+            }
+        }
+        None
     }
 }
 
-#[derive(Clone)]
-pub struct Range<'r, K: 'r, V: 'r> {
-    stack: Vec<&'r TreeNode<K, V>>,
-    rev_stack: Vec<&'r TreeNode<K, V>>
-}
-
 #[derive(Clone)]
 pub struct Keys<I> {
     src: I
@@ -469,7 +1587,7 @@ pub struct Keys<I> {
 
 impl<I> Keys<I> {
     pub fn new(src: I) -> Keys<I> {
-        Keys { src: src }
+        Keys { src }
     }
 }
 
@@ -508,7 +1626,7 @@ pub struct Values<I> {
 
 impl<I> Values<I> {
     pub fn new(src: I) -> Values<I> {
-        Values { src: src }
+        Values { src }
     }
 }
 
@@ -540,50 +1658,170 @@ impl<'r, K: 'r, V: 'r, I: 'r> ExactSizeIterator for Values<I>
     }
 }
 
-impl<'r, K: Ord + 'r, V: 'r> Range<'r, K, V> {
-    pub fn new<Q>(node: &'r Option<Rc<TreeNode<K, V>>>,
-                  min: Bound<&Q>, max: Bound<&Q>)
-            -> Range<'r, K, V>
-        where Q: Ord, K: Borrow<Q>
+// a subtree or an element which is not yielded yet by `IntoIter`
+enum Pending<K, V, P: PointerKind, A: Summarize<K, V>> {
+    Node(NodeRef<K, V, P, A>),
+    Elem((K, V))
+}
+
+// an iterator which takes the elements out of the tree.
+// the nodes which are not shared with other trees are unwrapped, and the shared nodes are cloned.
+pub struct IntoIter<K, V, P: PointerKind, A: Summarize<K, V> = ()> {
+    // the pending subtrees and elements in the key order
+    queue: VecDeque<Pending<K, V, P, A>>,
+    size: usize
+}
+
+impl<K, V, P: PointerKind, A: Measure<K, V>> IntoIter<K, V, P, A> {
+    pub fn new(node: Option<NodeRef<K, V, P, A>>) -> IntoIter<K, V, P, A> {
+        IntoIter {
+            size: size(&node),
+            queue: node.map(Pending::Node).into_iter().collect()
+        }
+    }
+}
+
+impl<K, V, P: PointerKind, A> Iterator for IntoIter<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            match self.queue.pop_front() {
+                None => return None,
+                Some(Pending::Elem(elem)) => {
+                    self.size -= 1;
+                    return Some(elem);
+                },
+                Some(Pending::Node(n)) => {
+                    let TreeNode { elem, left, right, .. } = n.unwrap_or_clone();
+                    if let Some(r) = right {
+                        self.queue.push_front(Pending::Node(r));
+                    }
+                    self.queue.push_front(Pending::Elem(elem));
+                    if let Some(l) = left {
+                        self.queue.push_front(Pending::Node(l));
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.size, Some(self.size))
+    }
+}
+
+impl<K, V, P: PointerKind, A> DoubleEndedIterator for IntoIter<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    fn next_back(&mut self) -> Option<(K, V)> {
+        loop {
+            match self.queue.pop_back() {
+                None => return None,
+                Some(Pending::Elem(elem)) => {
+                    self.size -= 1;
+                    return Some(elem);
+                },
+                Some(Pending::Node(n)) => {
+                    let TreeNode { elem, left, right, .. } = n.unwrap_or_clone();
+                    if let Some(l) = left {
+                        self.queue.push_back(Pending::Node(l));
+                    }
+                    self.queue.push_back(Pending::Elem(elem));
+                    if let Some(r) = right {
+                        self.queue.push_back(Pending::Node(r));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, P: PointerKind, A> ExactSizeIterator for IntoIter<K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    fn len(&self) -> usize {
+        self.size
+    }
+}
+
+pub struct IntoKeys<I> {
+    src: I
+}
+
+impl<I> IntoKeys<I> {
+    pub fn new(src: I) -> IntoKeys<I> {
+        IntoKeys { src }
+    }
+}
+
+impl<I, K, V> Iterator for IntoKeys<I> where I: Iterator<Item=(K, V)> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.src.next().map(|p| p.0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.src.size_hint()
+    }
+}
+
+impl<I, K, V> DoubleEndedIterator for IntoKeys<I> where I: DoubleEndedIterator<Item=(K, V)> {
+    fn next_back(&mut self) -> Option<K> {
+        self.src.next_back().map(|p| p.0)
+    }
+}
+
+impl<I, K, V> ExactSizeIterator for IntoKeys<I> where I: ExactSizeIterator<Item=(K, V)> {
+    fn len(&self) -> usize {
+        self.src.len()
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Measure<K, V>, C: 'r> Range<'r, K, V, P, C, A> {
+    pub fn new<Q>(node: &'r Option<NodeRef<K, V, P, A>>,
+                  min: Bound<&Q>, max: Bound<&Q>,
+                  cmp: &'r C)
+            -> Range<'r, K, V, P, C, A>
+        where K: Borrow<Q>, C: Compare<Q>
     {
-        let mut iter = Range { stack: Vec::new(), rev_stack: Vec::new() };
+        let mut iter = Iter { stack: Vec::new(), rev_stack: Vec::new(), size: 0 };
 
         if let Some(ref n) = *node {
             match min {
                 Bound::Unbounded => iter.left_edge(n),
-                Bound::Excluded(lower) => iter.left_edge_gt(n, lower),
-                Bound::Included(lower) => iter.left_edge_ge(n, lower)
+                Bound::Excluded(lower) => iter.left_edge_gt(n, lower, cmp),
+                Bound::Included(lower) => iter.left_edge_ge(n, lower, cmp)
             }
 
             match max {
                 Bound::Unbounded => iter.right_edge(n),
-                Bound::Excluded(upper) => iter.right_edge_lt(n, upper),
-                Bound::Included(upper) => iter.right_edge_le(n, upper)
+                Bound::Excluded(upper) => iter.right_edge_lt(n, upper, cmp),
+                Bound::Included(upper) => iter.right_edge_le(n, upper, cmp)
             }
-        }
-
-        iter
-    }
 
-    fn left_edge(&mut self, node: &'r TreeNode<K, V>) {
-        let mut cursor = node;
-
-        loop {
-            self.stack.push(cursor);
-            match cursor.left {
-                None => break,
-                Some(ref l) => cursor = l
-            }
+            // the elements above the lower bound and the elements below the upper bound overlap
+            // exactly in the range, unless the range is empty
+            let above: usize = iter.stack.iter().map(|n| size(&n.right) + 1).sum();
+            let below: usize = iter.rev_stack.iter().map(|n| size(&n.left) + 1).sum();
+            iter.size = (above + below).saturating_sub(n.size);
         }
+
+        Range { iter, marker: PhantomData }
     }
+}
 
-    fn left_edge_gt<Q: Ord>(&mut self, node: &'r TreeNode<K, V>, key: &Q)
-        where K: Borrow<Q>
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Measure<K, V>> Iter<'r, K, V, P, A> {
+    fn left_edge_gt<Q, C>(&mut self, node: &'r TreeNode<K, V, P, A>, key: &Q, cmp: &C)
+        where K: Borrow<Q>, C: Compare<Q>
     {
         let mut cursor = node;
 
         loop {
-            if cursor.elem.0.borrow() > key {
+            if cmp.compare(cursor.elem.0.borrow(), key) == Ordering::Greater {
                 self.stack.push(cursor);
                 match cursor.left {
                     None => break,
@@ -597,13 +1835,13 @@ impl<'r, K: Ord + 'r, V: 'r> Range<'r, K, V> {
         }
     }
 
-    fn left_edge_ge<Q: Ord>(&mut self, node: &'r TreeNode<K, V>, key: &Q)
-        where K: Borrow<Q>
+    fn left_edge_ge<Q, C>(&mut self, node: &'r TreeNode<K, V, P, A>, key: &Q, cmp: &C)
+        where K: Borrow<Q>, C: Compare<Q>
     {
         let mut cursor = node;
 
         loop {
-            match cursor.elem.0.borrow().cmp(key) {
+            match cmp.compare(cursor.elem.0.borrow(), key) {
                 Ordering::Less => match cursor.right {
                     None => break,
                     Some(ref r) => cursor = r
@@ -623,25 +1861,13 @@ impl<'r, K: Ord + 'r, V: 'r> Range<'r, K, V> {
         }
     }
 
-    fn right_edge(&mut self, node: &'r TreeNode<K, V>) {
-        let mut cursor = node;
-
-        loop {
-            self.rev_stack.push(cursor);
-            match cursor.right {
-                None => break,
-                Some(ref r) => cursor = r
-            }
-        }
-    }
-
-    fn right_edge_lt<Q: Ord>(&mut self, node: &'r TreeNode<K, V>, key: &Q)
-        where K: Borrow<Q>
+    fn right_edge_lt<Q, C>(&mut self, node: &'r TreeNode<K, V, P, A>, key: &Q, cmp: &C)
+        where K: Borrow<Q>, C: Compare<Q>
     {
         let mut cursor = node;
 
         loop {
-            if cursor.elem.0.borrow() < key {
+            if cmp.compare(cursor.elem.0.borrow(), key) == Ordering::Less {
                 self.rev_stack.push(cursor);
                 match cursor.right {
                     None => break,
@@ -655,13 +1881,13 @@ impl<'r, K: Ord + 'r, V: 'r> Range<'r, K, V> {
         }
     }
 
-    fn right_edge_le<Q: Ord>(&mut self, node: &'r TreeNode<K, V>, key: &Q)
-        where K: Borrow<Q>
+    fn right_edge_le<Q, C>(&mut self, node: &'r TreeNode<K, V, P, A>, key: &Q, cmp: &C)
+        where K: Borrow<Q>, C: Compare<Q>
     {
         let mut cursor = node;
 
         loop {
-            match cursor.elem.0.borrow().cmp(key) {
+            match cmp.compare(cursor.elem.0.borrow(), key) {
                 Ordering::Less => {
                     self.rev_stack.push(cursor);
                     match cursor.right {
@@ -682,60 +1908,429 @@ impl<'r, K: Ord + 'r, V: 'r> Range<'r, K, V> {
     }
 }
 
-impl<'r, K: Ord + 'r, V: 'r> Iterator for Range<'r, K, V> {
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r, A> Iterator for Range<'r, K, V, P, C, A>
+    where A: Measure<K, V>
+{
     type Item = (&'r K, &'r V);
 
     fn next(&mut self) -> Option<(&'r K, &'r V)> {
-        let top = match self.stack.pop() {
-            None => return None,
-            Some(t) => t
-        };
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
 
-        let ret = (&top.elem.0, &top.elem.1);
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r, A> DoubleEndedIterator for Range<'r, K, V, P, C, A>
+    where A: Measure<K, V>
+{
+    fn next_back(&mut self) -> Option<(&'r K, &'r V)> {
+        self.iter.next_back()
+    }
+}
 
-        if let Some(rev_top) = self.rev_stack.last() {
-            if rev_top.elem.0 < top.elem.0 {
-                return None;
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r, A> ExactSizeIterator for Range<'r, K, V, P, C, A>
+    where A: Measure<K, V>
+{
+    fn len(&self) -> usize {
+        self.iter.len()
+    }
+}
+
+// an element or a subtree which is not compared yet by `Diff`
+enum PendingRef<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V>> {
+    Node(&'r TreeNode<K, V, P, A>),
+    Elem(&'r (K, V))
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V>> Clone for PendingRef<'r, K, V, P, A> {
+    fn clone(&self) -> PendingRef<'r, K, V, P, A> {
+        *self
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V>> Copy for PendingRef<'r, K, V, P, A> {}
+
+// an iterator over the elements which differ between two trees, in the key order.
+// yields the pair of the element of the old tree and the element of the new tree, either of
+// which is `None` if only one of the trees has the key.
+// the subtrees shared by both trees are skipped without visiting their elements.
+pub struct Diff<'r, K: 'r, V: 'r, P: PointerKind, C: 'r, A: Summarize<K, V> = ()> {
+    // the pending elements and subtrees of each tree, the next one on the top
+    old: Vec<PendingRef<'r, K, V, P, A>>,
+    new: Vec<PendingRef<'r, K, V, P, A>>,
+    cmp: &'r C
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C: 'r, A: Measure<K, V>> Diff<'r, K, V, P, C, A> {
+    pub fn new(old: &'r Option<NodeRef<K, V, P, A>>,
+               new: &'r Option<NodeRef<K, V, P, A>>,
+               cmp: &'r C)
+            -> Diff<'r, K, V, P, C, A>
+    {
+        Diff {
+            old: old.iter().map(|n| PendingRef::Node(&**n)).collect(),
+            new: new.iter().map(|n| PendingRef::Node(&**n)).collect(),
+            cmp
+        }
+    }
+}
+
+// replace the subtree on the top with its left subtree, element and right subtree.
+fn expand<'r, K: 'r, V: 'r, P: PointerKind, A>(stack: &mut Vec<PendingRef<'r, K, V, P, A>>)
+    where A: Measure<K, V>
+{
+    if let Some(PendingRef::Node(n)) = stack.pop() {
+        if let Some(ref r) = n.right {
+            stack.push(PendingRef::Node(r));
+        }
+        stack.push(PendingRef::Elem(&n.elem));
+        if let Some(ref l) = n.left {
+            stack.push(PendingRef::Node(l));
+        }
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, C, A> Iterator for Diff<'r, K, V, P, C, A>
+    where V: PartialEq, C: Compare<K> + 'r, A: Measure<K, V>
+{
+    type Item = (Option<&'r (K, V)>, Option<&'r (K, V)>);
+
+    fn next(&mut self) -> Option<(Option<&'r (K, V)>, Option<&'r (K, V)>)> {
+        loop {
+            match (self.old.last().cloned(), self.new.last().cloned()) {
+                (None, None) => return None,
+                (Some(PendingRef::Elem(a)), None) => {
+                    self.old.pop();
+                    return Some((Some(a), None));
+                },
+                (None, Some(PendingRef::Elem(b))) => {
+                    self.new.pop();
+                    return Some((None, Some(b)));
+                },
+                (Some(PendingRef::Node(_)), None) => expand(&mut self.old),
+                (None, Some(PendingRef::Node(_))) => expand(&mut self.new),
+                (Some(PendingRef::Node(a)), Some(PendingRef::Node(b))) => {
+                    if ptr::eq(a, b) {
+                        self.old.pop();
+                        self.new.pop();
+                    } else {
+                        // a subtree can be shared only with a smaller subtree of the other tree
+                        if a.size >= b.size {
+                            expand(&mut self.old);
+                        }
+                        if b.size >= a.size {
+                            expand(&mut self.new);
+                        }
+                    }
+                },
+                (Some(PendingRef::Elem(a)), Some(PendingRef::Node(b))) => {
+                    // keep the subtree unexpanded while the elements before it are yielded
+                    if self.cmp.compare(&a.0, &find_min(b).0) == Ordering::Less {
+                        self.old.pop();
+                        return Some((Some(a), None));
+                    }
+                    expand(&mut self.new);
+                },
+                (Some(PendingRef::Node(a)), Some(PendingRef::Elem(b))) => {
+                    if self.cmp.compare(&b.0, &find_min(a).0) == Ordering::Less {
+                        self.new.pop();
+                        return Some((None, Some(b)));
+                    }
+                    expand(&mut self.old);
+                },
+                (Some(PendingRef::Elem(a)), Some(PendingRef::Elem(b))) => {
+                    match self.cmp.compare(&a.0, &b.0) {
+                        Ordering::Less => {
+                            self.old.pop();
+                            return Some((Some(a), None));
+                        },
+                        Ordering::Greater => {
+                            self.new.pop();
+                            return Some((None, Some(b)));
+                        },
+                        Ordering::Equal => {
+                            self.old.pop();
+                            self.new.pop();
+                            if a.1 != b.1 {
+                                return Some((Some(a), Some(b)));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the path from the root to the node with the key, or to the empty subtree where the key belongs.
+// the new tree is built by copying only the nodes on the path.
+#[allow(clippy::type_complexity)]
+pub struct Path<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V> = ()> {
+    // the ancestors of the target and the direction taken at each of them
+    steps: Vec<(&'r TreeNode<K, V, P, A>, Ordering)>,
+    target: Option<&'r TreeNode<K, V, P, A>>
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Measure<K, V>> Path<'r, K, V, P, A> {
+    pub fn new<Q: ?Sized, C>(node: &'r Option<NodeRef<K, V, P, A>>, key: &Q, cmp: &C)
+            -> Path<'r, K, V, P, A>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        let mut path = Path { steps: Vec::new(), target: None };
+        let mut cursor = node;
+        while let Some(ref n) = *cursor {
+            let dir = cmp.compare(key, n.elem.0.borrow());
+            match dir {
+                Ordering::Less => cursor = &n.left,
+                Ordering::Equal => {
+                    path.target = Some(n);
+                    break;
+                },
+                Ordering::Greater => cursor = &n.right
+            }
+            path.steps.push((n, dir));
+        }
+        path
+    }
+
+    pub fn found(&self) -> Option<&'r (K, V)> {
+        self.target.map(|n| &n.elem)
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A> Path<'r, K, V, P, A>
+    where K: Clone, V: Clone, A: Measure<K, V>
+{
+    // insert the element at the end of the path.
+    // assumes that the key is not found
+    pub fn insert(self, elem: (K, V)) -> TreeNode<K, V, P, A> {
+        let mut node = TreeNode::new(elem, None, None);
+        for &(n, dir) in self.steps.iter().rev() {
+            node = if dir == Ordering::Less {
+                balance_right_move(n.elem.clone(), node, &n.right)
+            } else {
+                balance_left_move(n.elem.clone(), &n.left, node)
+            };
+        }
+        node
+    }
+
+    // replace the found element with the element with the same key.
+    pub fn replace(self, elem: (K, V)) -> TreeNode<K, V, P, A> {
+        let target = self.target.expect("the key is not found");
+        let mut node = TreeNode::new(elem, target.left.clone(), target.right.clone());
+        for &(n, dir) in self.steps.iter().rev() {
+            node = if dir == Ordering::Less {
+                TreeNode::new(n.elem.clone(), Some(NodeRef::new(node)), n.right.clone())
+            } else {
+                TreeNode::new(n.elem.clone(), n.left.clone(), Some(NodeRef::new(node)))
+            };
+        }
+        node
+    }
+
+    // remove the found element.
+    pub fn remove(self) -> Option<NodeRef<K, V, P, A>> {
+        let target = self.target.expect("the key is not found");
+        let mut node = glue(&target.left, &target.right);
+        for &(n, dir) in self.steps.iter().rev() {
+            node = Some(NodeRef::new(if dir == Ordering::Less {
+                balance_left(n.elem.clone(), &node, &n.right)
+            } else {
+                balance_right(n.elem.clone(), &n.left, &node)
+            }));
+        }
+        node
+    }
+}
+
+// a cursor which points to an element of the tree, or to the "ghost" position which is before
+// the first element and after the last element at the same time.
+pub struct Cursor<'r, K: 'r, V: 'r, P: PointerKind, A: Summarize<K, V> = ()> {
+    root: &'r Option<NodeRef<K, V, P, A>>,
+    // the path from the root to the current node. empty at the ghost position.
+    stack: Vec<&'r TreeNode<K, V, P, A>>,
+    index: usize
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Measure<K, V>> Cursor<'r, K, V, P, A> {
+    fn ghost(root: &'r Option<NodeRef<K, V, P, A>>) -> Cursor<'r, K, V, P, A> {
+        Cursor { root, stack: Vec::new(), index: size(root) }
+    }
+
+    // points to the first element whose key satisfies `f`.
+    // assumes that the keys which satisfy `f` come after the keys which do not.
+    fn seek<F>(root: &'r Option<NodeRef<K, V, P, A>>, mut f: F) -> Cursor<'r, K, V, P, A>
+        where F: FnMut(&K) -> bool
+    {
+        let mut cursor = Cursor::ghost(root);
+        let mut found_depth = 0;
+        let mut offset = 0;
+        let mut node = root;
+
+        while let Some(ref n) = *node {
+            cursor.stack.push(n);
+            if f(&n.elem.0) {
+                found_depth = cursor.stack.len();
+                cursor.index = offset + size(&n.left);
+                node = &n.left;
+            } else {
+                offset += size(&n.left) + 1;
+                node = &n.right;
             }
+        }
+
+        cursor.stack.truncate(found_depth);
+        cursor
+    }
+
+    pub fn lower_bound<Q: ?Sized, C>(root: &'r Option<NodeRef<K, V, P, A>>, key: &Q, cmp: &C)
+            -> Cursor<'r, K, V, P, A>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        Cursor::seek(root, |k| cmp.compare(k.borrow(), key) != Ordering::Less)
+    }
+
+    pub fn upper_bound<Q: ?Sized, C>(root: &'r Option<NodeRef<K, V, P, A>>, key: &Q, cmp: &C)
+            -> Cursor<'r, K, V, P, A>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        Cursor::seek(root, |k| cmp.compare(k.borrow(), key) == Ordering::Greater)
+    }
+
+    pub fn find<Q: ?Sized, C>(root: &'r Option<NodeRef<K, V, P, A>>, key: &Q, cmp: &C)
+            -> Option<Cursor<'r, K, V, P, A>>
+        where K: Borrow<Q>, C: Compare<Q>
+    {
+        let cursor = Cursor::lower_bound(root, key, cmp);
+        match cursor.key() {
+            Some(k) if cmp.compare(k.borrow(), key) == Ordering::Equal => Some(cursor),
+            _ => None
+        }
+    }
+
+    pub fn key(&self) -> Option<&'r K> {
+        self.stack.last().map(|n| &n.elem.0)
+    }
+
+    pub fn value(&self) -> Option<&'r V> {
+        self.stack.last().map(|n| &n.elem.1)
+    }
+
+    pub fn rank(&self) -> Option<usize> {
+        if self.stack.is_empty() {
+            None
         } else {
-            return None;
+            Some(self.index)
         }
+    }
+
+    pub fn move_next(&mut self) {
+        let top = match self.stack.last() {
+            None => {
+                self.index = 0;
+                if let Some(ref n) = *self.root {
+                    self.push_left(n);
+                }
+                return;
+            },
+            Some(&t) => t
+        };
 
+        self.index += 1;
         if let Some(ref r) = top.right {
-            self.left_edge(r);
+            self.push_left(r);
+            return;
         }
 
-        Some(ret)
+        // climb up until the current node is the left child of its parent
+        while let Some(child) = self.stack.pop() {
+            if let Some(parent) = self.stack.last() {
+                if is_child(&parent.left, child) {
+                    return;
+                }
+            }
+        }
+        self.index = size(self.root);
     }
-}
 
-impl<'r, K: Ord + 'r, V: 'r> DoubleEndedIterator for Range<'r, K, V> {
-    fn next_back(&mut self) -> Option<(&'r K, &'r V)> {
-        let top = match self.rev_stack.pop() {
-            None => return None,
-            Some(t) => t
+    pub fn move_prev(&mut self) {
+        let top = match self.stack.last() {
+            None => {
+                if let Some(ref n) = *self.root {
+                    self.index = n.size - 1;
+                    self.push_right(n);
+                }
+                return;
+            },
+            Some(&t) => t
         };
 
-        let ret = (&top.elem.0, &top.elem.1);
+        if let Some(ref l) = top.left {
+            self.index -= 1;
+            self.push_right(l);
+            return;
+        }
 
-        if let Some(rev_top) = self.stack.last() {
-            if top.elem.0 < rev_top.elem.0 {
-                return None;
+        // climb up until the current node is the right child of its parent
+        while let Some(child) = self.stack.pop() {
+            if let Some(parent) = self.stack.last() {
+                if is_child(&parent.right, child) {
+                    self.index -= 1;
+                    return;
+                }
+            }
+        }
+        self.index = size(self.root);
+    }
+
+    fn push_left(&mut self, node: &'r TreeNode<K, V, P, A>) {
+        let mut cursor = node;
+
+        loop {
+            self.stack.push(cursor);
+            match cursor.left {
+                None => break,
+                Some(ref l) => cursor = l
             }
-        } else {
-            return None;
         }
+    }
+
+    fn push_right(&mut self, node: &'r TreeNode<K, V, P, A>) {
+        let mut cursor = node;
 
-        if let Some(ref r) = top.left {
-            self.right_edge(r);
+        loop {
+            self.stack.push(cursor);
+            match cursor.right {
+                None => break,
+                Some(ref r) => cursor = r
+            }
         }
+    }
+}
+
+impl<'r, K: 'r, V: 'r, P: PointerKind, A: Measure<K, V>> Clone for Cursor<'r, K, V, P, A> {
+    fn clone(&self) -> Cursor<'r, K, V, P, A> {
+        Cursor { root: self.root, stack: self.stack.clone(), index: self.index }
+    }
+}
 
-        Some(ret)
+fn is_child<K, V, P: PointerKind, A>(link: &Option<NodeRef<K, V, P, A>>,
+                                     node: &TreeNode<K, V, P, A>)
+        -> bool
+    where A: Measure<K, V>
+{
+    match *link {
+        None => false,
+        Some(ref n) => ptr::eq(&**n, node)
     }
 }
 
 #[cfg(test)]
-pub fn balanced<K, V>(node: &Option<Rc<TreeNode<K, V>>>) -> bool
+pub fn balanced<K, V, P: PointerKind, A>(node: &Option<NodeRef<K, V, P, A>>) -> bool
+    where A: Measure<K, V>
 {
     if let Some(ref n) = *node {
         is_balanced(size(&n.left), size(&n.right))
@@ -746,3 +2341,17 @@ pub fn balanced<K, V>(node: &Option<Rc<TreeNode<K, V>>>) -> bool
         true
     }
 }
+
+#[cfg(test)]
+pub fn same_shape<K, V, W, P: PointerKind>(a: &Option<NodeRef<K, V, P>>,
+                                           b: &Option<NodeRef<K, W, P>>)
+        -> bool
+{
+    match (a.as_ref(), b.as_ref()) {
+        (None, None) => true,
+        (Some(an), Some(bn)) => an.size == bn.size
+            && same_shape(&an.left, &bn.left)
+            && same_shape(&an.right, &bn.right),
+        _ => false
+    }
+}
@@ -0,0 +1,563 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::Index;
+
+use tree;
+use tree::NodeRef;
+use pointer::{PointerKind, RcKind, ArcKind};
+
+/// An immutable sequence based on weight-balanced binary tree.
+///
+/// The elements are ordered by their positions instead of a comparator. Every node caches the
+/// size of its subtree, so indexing, inserting or removing at any position, splitting and
+/// concatenating take O(log n) time, and the modified copies share most of their nodes with the
+/// original sequence.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::TreeVec;
+///
+/// let vec_0 = TreeVec::new();
+///
+/// // `push_back` returns new copies with the appended element, and does not change the
+/// // original sequence
+/// let vec_1 = vec_0.push_back('b');
+/// let vec_2 = vec_1.push_front('a').push_back('c');
+///
+/// assert_eq!(1, vec_1.len());
+/// assert_eq!(vec![&'a', &'b', &'c'], vec_2.iter().collect::<Vec<_>>());
+/// ```
+pub struct TreeVec<T, P: PointerKind = RcKind> {
+    root: Option<NodeRef<T, (), P>>
+}
+
+/// A `TreeVec` linked with `Arc`, for the sequences shared between threads.
+pub type ArcTreeVec<T> = TreeVec<T, ArcKind>;
+
+pub type TreeVecIter<'r, T, P = RcKind> = tree::Keys<tree::Iter<'r, T, (), P>>;
+pub type TreeVecIntoIter<T, P = RcKind> = tree::IntoKeys<tree::IntoIter<T, (), P>>;
+
+impl<T> TreeVec<T> {
+    /// Makes a new empty TreeVec
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new();
+    /// let new_vec = vec.push_back(1);
+    /// ```
+    pub fn new() -> TreeVec<T> {
+        TreeVec { root: None }
+    }
+}
+
+impl<T, P: PointerKind> TreeVec<T, P> {
+    /// Returns the number of elements in the sequence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_back(1).push_back(2);
+    /// assert_eq!(2, vec.len());
+    /// ```
+    pub fn len(&self) -> usize {
+        tree::size(&self.root)
+    }
+
+    /// Returns true if the sequence contains no elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let empty_vec = TreeVec::new();
+    /// let new_vec = empty_vec.push_back(1);
+    ///
+    /// assert!(empty_vec.is_empty());
+    /// assert!(!new_vec.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Returns a reference to the element at the given position, or `None` if the index is out
+    /// of bounds. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_back('a').push_back('b');
+    ///
+    /// assert_eq!(Some(&'b'), vec.get(1));
+    /// assert_eq!(None, vec.get(2));
+    /// ```
+    pub fn get(&self, index: usize) -> Option<&T> {
+        tree::find_index(&self.root, index).map(|p| &p.0)
+    }
+
+    /// Gets an iterator over the elements of the sequence, in order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_back(1).push_back(2).push_front(0);
+    ///
+    /// for x in vec.iter() {
+    ///     println!("{}", x);
+    /// }
+    ///
+    /// assert_eq!(vec![&2, &1, &0], vec.iter().rev().collect::<Vec<_>>());
+    /// ```
+    pub fn iter<'r>(&'r self) -> TreeVecIter<'r, T, P> {
+        tree::Keys::new(tree::Iter::new(&self.root))
+    }
+
+    /// Gets an iterator over the elements at the positions from `start` to `end`, exclusive.
+    /// Creating the iterator takes O(log n) time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start > end` or `end > len()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec: TreeVec<_> = (0..10).collect();
+    ///
+    /// let slice = vec.range(3, 6);
+    /// assert_eq!(3, slice.len());
+    /// assert_eq!(vec![&3, &4, &5], slice.collect::<Vec<_>>());
+    /// ```
+    pub fn range<'r>(&'r self, start: usize, end: usize) -> TreeVecIter<'r, T, P> {
+        assert!(start <= end, "range start {} is greater than end {}", start, end);
+        assert!(end <= self.len(), "range end {} is out of bounds of length {}", end, self.len());
+        tree::Keys::new(tree::Iter::with_index_range(&self.root, start, end))
+    }
+}
+
+impl<T: Clone, P: PointerKind> TreeVec<T, P> {
+    /// Returns a new sequence with the element appended at the end. This operation takes
+    /// O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_back(1).push_back(2);
+    ///
+    /// assert_eq!(vec![&1, &2], vec.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn push_back(&self, value: T) -> TreeVec<T, P> {
+        TreeVec { root: Some(NodeRef::new(tree::join((value, ()), &self.root, &None))) }
+    }
+
+    /// Returns a new sequence with the element prepended at the front. This operation takes
+    /// O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_front(1).push_front(2);
+    ///
+    /// assert_eq!(vec![&2, &1], vec.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn push_front(&self, value: T) -> TreeVec<T, P> {
+        TreeVec { root: Some(NodeRef::new(tree::join((value, ()), &None, &self.root))) }
+    }
+
+    /// Returns a new sequence with the element inserted at the given position, shifting the
+    /// following elements to the back. This operation takes O(log n) time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_back('a').push_back('c');
+    /// let new_vec = vec.insert_at(1, 'b');
+    ///
+    /// assert_eq!(vec![&'a', &'b', &'c'], new_vec.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn insert_at(&self, index: usize, value: T) -> TreeVec<T, P> {
+        assert!(index <= self.len(),
+                "insertion index {} is out of bounds of length {}", index, self.len());
+        let (left, right) = tree::split_at(&self.root, index);
+        TreeVec { root: Some(NodeRef::new(tree::join((value, ()), &left, &right))) }
+    }
+
+    /// Removes the element at the given position, and returns a new sequence without the
+    /// element and a reference to the removed element. Returns `None` if the index is out of
+    /// bounds. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_back('a').push_back('b').push_back('c');
+    ///
+    /// let (new_vec, removed) = vec.remove_at(1).unwrap();
+    ///
+    /// assert_eq!(&'b', removed);
+    /// assert_eq!(vec![&'a', &'c'], new_vec.iter().collect::<Vec<_>>());
+    /// assert!(vec.remove_at(3).is_none());
+    /// ```
+    pub fn remove_at(&self, index: usize) -> Option<(TreeVec<T, P>, &T)> {
+        tree::remove_index(&self.root, index).map(|(new_root, v)|
+            (TreeVec { root: new_root }, &v.0)
+        )
+    }
+
+    /// Returns a new sequence with the element at the given position replaced by the given
+    /// value. Returns `None` if the index is out of bounds. The shape of the tree is unchanged,
+    /// and only the nodes on the path to the element are copied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec = TreeVec::new().push_back(1).push_back(2);
+    ///
+    /// let new_vec = vec.set(0, 10).unwrap();
+    ///
+    /// assert_eq!(vec![&10, &2], new_vec.iter().collect::<Vec<_>>());
+    /// assert_eq!(vec![&1, &2], vec.iter().collect::<Vec<_>>());
+    /// assert!(vec.set(2, 30).is_none());
+    /// ```
+    pub fn set(&self, index: usize, value: T) -> Option<TreeVec<T, P>> {
+        if index < self.len() {
+            Some(TreeVec { root: tree::replace_index(&self.root, index, (value, ())) })
+        } else {
+            None
+        }
+    }
+
+    /// Splits the sequence at the given position. Returns the sequence with the first `index`
+    /// elements, and the sequence with the rest. This operation takes O(log n) time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let vec: TreeVec<_> = (0..5).collect();
+    ///
+    /// let (left, right) = vec.split_at(2);
+    ///
+    /// assert_eq!(vec![&0, &1], left.iter().collect::<Vec<_>>());
+    /// assert_eq!(vec![&2, &3, &4], right.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn split_at(&self, index: usize) -> (TreeVec<T, P>, TreeVec<T, P>) {
+        let (left, right) = tree::split_at(&self.root, index);
+        (TreeVec { root: left }, TreeVec { root: right })
+    }
+
+    /// Returns a new sequence with the elements of `self` followed by the elements of `other`.
+    /// This operation takes O(log n) time, and shares the nodes of both sequences.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use immutable_map::TreeVec;
+    ///
+    /// let a: TreeVec<_> = (0..3).collect();
+    /// let b: TreeVec<_> = (3..5).collect();
+    ///
+    /// assert_eq!(vec![&0, &1, &2, &3, &4], a.concat(&b).iter().collect::<Vec<_>>());
+    /// ```
+    pub fn concat(&self, other: &TreeVec<T, P>) -> TreeVec<T, P> {
+        TreeVec { root: tree::merge(&self.root, &other.root) }
+    }
+}
+
+impl<T, P: PointerKind> Clone for TreeVec<T, P> {
+    fn clone(&self) -> TreeVec<T, P> {
+        TreeVec { root: self.root.clone() }
+    }
+}
+
+impl<T, P: PointerKind> Default for TreeVec<T, P> {
+    fn default() -> TreeVec<T, P> {
+        TreeVec { root: None }
+    }
+}
+
+impl<T: Debug, P: PointerKind> Debug for TreeVec<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T, P: PointerKind> Index<usize> for TreeVec<T, P> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(v) => v,
+            None => panic!("index {} is out of bounds of length {}", index, self.len())
+        }
+    }
+}
+
+impl<'r, T, P: PointerKind> IntoIterator for &'r TreeVec<T, P> {
+    type Item = &'r T;
+    type IntoIter = TreeVecIter<'r, T, P>;
+
+    fn into_iter(self) -> TreeVecIter<'r, T, P> {
+        self.iter()
+    }
+}
+
+/// Moves the elements out of the sequence. The nodes owned only by this sequence are moved, and
+/// only the shared nodes are cloned.
+///
+/// # Examples
+///
+/// ```
+/// use immutable_map::TreeVec;
+///
+/// let vec = TreeVec::new().push_back(1).push_back(2).push_back(3);
+///
+/// let mut iter = vec.into_iter();
+/// assert_eq!(Some(1), iter.next());
+/// assert_eq!(Some(3), iter.next_back());
+/// assert_eq!(1, iter.len());
+/// assert_eq!(Some(2), iter.next());
+/// assert_eq!(None, iter.next());
+/// ```
+impl<T: Clone, P: PointerKind> IntoIterator for TreeVec<T, P> {
+    type Item = T;
+    type IntoIter = TreeVecIntoIter<T, P>;
+
+    fn into_iter(self) -> TreeVecIntoIter<T, P> {
+        tree::IntoKeys::new(tree::IntoIter::new(self.root))
+    }
+}
+
+impl<T: PartialEq, P: PointerKind> PartialEq for TreeVec<T, P> {
+    fn eq(&self, other: &TreeVec<T, P>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, P: PointerKind> Eq for TreeVec<T, P> {}
+
+impl<T: PartialOrd, P: PointerKind> PartialOrd for TreeVec<T, P> {
+    fn partial_cmp(&self, other: &TreeVec<T, P>) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Ord, P: PointerKind> Ord for TreeVec<T, P> {
+    fn cmp(&self, other: &TreeVec<T, P>) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Hash, P: PointerKind> Hash for TreeVec<T, P> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for v in self.iter() {
+            v.hash(state);
+        }
+    }
+}
+
+impl<T, P: PointerKind> FromIterator<T> for TreeVec<T, P> {
+    fn from_iter<I>(iter: I) -> TreeVec<T, P> where I: IntoIterator<Item=T> {
+        let elems: Vec<(T, ())> = iter.into_iter().map(|v| (v, ())).collect();
+        TreeVec { root: tree::from_sorted_vec(elems) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tree::balanced;
+
+    use super::TreeVec;
+
+    #[test]
+    fn test_push_both_ends() {
+        let mut vec = TreeVec::new();
+        for i in 0..100 {
+            vec = if i % 2 == 0 { vec.push_back(i) } else { vec.push_front(i) };
+            assert!(balanced(&vec.root));
+        }
+
+        let expected: Vec<_> = (0..100).rev().filter(|i| i % 2 == 1)
+            .chain((0..100).filter(|i| i % 2 == 0))
+            .collect();
+        assert_eq!(expected, vec.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_index() {
+        let vec: TreeVec<_> = (0..10).map(|i| i * i).collect();
+
+        assert_eq!(49, vec[7]);
+        assert_eq!(Some(&81), vec.get(9));
+        assert_eq!(None, vec.get(10));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_bounds() {
+        let vec: TreeVec<i32> = (0..10).collect();
+        let _ = vec[10];
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_out_of_bounds() {
+        let vec: TreeVec<i32> = (0..10).collect();
+        vec.insert_at(11, 0);
+    }
+
+    #[test]
+    fn test_empty_range() {
+        let vec: TreeVec<i32> = (0..10).collect();
+
+        assert_eq!(0, vec.range(4, 4).len());
+        assert_eq!(None, vec.range(10, 10).next());
+        assert_eq!(None, TreeVec::<i32>::new().range(0, 0).next());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_range_out_of_bounds() {
+        let vec: TreeVec<i32> = (0..10).collect();
+        vec.range(5, 11);
+    }
+
+    #[test]
+    fn test_ord() {
+        let a: TreeVec<_> = vec![1, 2, 3].into_iter().collect();
+        let b: TreeVec<_> = vec![1, 3].into_iter().collect();
+
+        assert!(a < b);
+        assert!(a > b.split_at(1).0);
+        assert_eq!(a, b.insert_at(1, 2));
+    }
+
+    #[test]
+    fn test_concat_uneven() {
+        // the shorter sequence is joined along the spine of the longer one
+        let long: TreeVec<_> = (0..1000).collect();
+        let short: TreeVec<_> = (1000..1003).collect();
+        let appended = long.concat(&short);
+        let prepended = short.concat(&long);
+
+        assert!(balanced(&appended.root));
+        assert!(balanced(&prepended.root));
+        assert_eq!(Some(&1002), appended.get(1002));
+        assert_eq!(Some(&0), prepended.get(3));
+        assert_eq!(Some(&999), prepended.get(1002));
+    }
+}
+
+#[cfg(test)]
+mod quickcheck {
+    use vec::TreeVec;
+    use tree::balanced;
+
+    fn to_vec(vec: &TreeVec<u8>) -> Vec<u8> {
+        vec.iter().cloned().collect()
+    }
+
+    quickcheck! {
+        fn check_insert_remove(xs: Vec<(usize, u8)>, ys: Vec<usize>) -> bool {
+            let mut vec = TreeVec::new();
+            let mut model = Vec::new();
+            for (i, x) in xs {
+                let index = i % (model.len() + 1);
+                vec = vec.insert_at(index, x);
+                model.insert(index, x);
+            }
+            if !balanced(&vec.root) || to_vec(&vec) != model {
+                return false;
+            }
+
+            for i in ys {
+                let index = i % (model.len() + 1);
+                match vec.remove_at(index) {
+                    Some((new_vec, &x)) => {
+                        if x != model.remove(index) {
+                            return false;
+                        }
+                        vec = new_vec;
+                    },
+                    None => if index != model.len() {
+                        return false;
+                    }
+                }
+            }
+            balanced(&vec.root) && to_vec(&vec) == model
+        }
+
+        fn check_set(xs: Vec<u8>, i: usize, x: u8) -> bool {
+            let vec: TreeVec<_> = xs.iter().cloned().collect();
+            let mut model = xs.clone();
+            match vec.set(i, x) {
+                Some(new_vec) => {
+                    model[i] = x;
+                    balanced(&new_vec.root) && to_vec(&new_vec) == model && to_vec(&vec) == xs
+                },
+                None => i >= xs.len()
+            }
+        }
+
+        fn check_split_concat(xs: Vec<u8>, ys: Vec<u8>, i: usize) -> bool {
+            let a: TreeVec<_> = xs.iter().cloned().collect();
+            let b: TreeVec<_> = ys.iter().cloned().collect();
+            let joined = a.concat(&b);
+            let index = i % (joined.len() + 1);
+            let (left, right) = joined.split_at(index);
+
+            let mut model = xs.clone();
+            model.extend(ys);
+
+            balanced(&joined.root) && balanced(&left.root) && balanced(&right.root)
+                && to_vec(&joined) == model
+                && to_vec(&left) == model[..index]
+                && to_vec(&right) == model[index..]
+        }
+
+        fn check_range(xs: Vec<u8>, a: usize, b: usize) -> bool {
+            let vec: TreeVec<_> = xs.iter().cloned().collect();
+            let a = a % (xs.len() + 1);
+            let b = b % (xs.len() + 1);
+            let (start, end) = if a <= b { (a, b) } else { (b, a) };
+            let model = &xs[start..end];
+
+            let range = vec.range(start, end);
+            let forward: Vec<u8> = range.clone().cloned().collect();
+            let backward: Vec<u8> = range.clone().rev().cloned().collect();
+            let reversed: Vec<u8> = model.iter().rev().cloned().collect();
+
+            range.len() == model.len() && forward == model && backward == reversed
+        }
+    }
+}